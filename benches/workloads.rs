@@ -0,0 +1,124 @@
+// Measures each stage of running the programs in benches/programs: loading
+// their JSON or binary AST, the checks done before evaluation, and the
+// evaluation.
+//
+// The JSON is produced from the `.rinha` sources with our own parser, so the
+// workloads only need to be kept in one format.
+
+use std::fs;
+use std::hint::black_box;
+use std::io;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use serde_json::Value;
+
+use rinha_interpreter::ast::File;
+use rinha_interpreter::eval::Evaluator;
+use rinha_interpreter::{binary, check, parser, source, validate};
+
+const PROGRAMS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/programs");
+
+const WORKLOADS: [&str; 5] = ["fib", "combination", "sum", "strings", "tuples"];
+
+struct Workload {
+    name: &'static str,
+    source: String,
+    json: String,
+    binary: Vec<u8>,
+    file: File,
+}
+
+fn workloads() -> Vec<Workload> {
+    WORKLOADS
+        .into_iter()
+        .map(|name| {
+            let filename = format!("{name}.rinha");
+            let path = format!("{PROGRAMS}/{filename}");
+            let source = fs::read_to_string(path).unwrap();
+            let file = parser::parse(&source, &filename).unwrap();
+            let json = serde_json::to_string(&file).unwrap();
+            let binary = binary::encode(&file);
+            Workload {
+                name,
+                source,
+                json,
+                binary,
+                file,
+            }
+        })
+        .collect()
+}
+
+fn load(c: &mut Criterion) {
+    let workloads = workloads();
+
+    let mut group = c.benchmark_group("deserialize");
+    for workload in &workloads {
+        group.bench_function(workload.name, |b| {
+            b.iter(|| source::from_json(black_box(&workload.json)).unwrap())
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("decode");
+    for workload in &workloads {
+        group.bench_function(workload.name, |b| {
+            b.iter(|| binary::decode(black_box(&workload.binary)).unwrap())
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("parse");
+    for workload in &workloads {
+        let filename = format!("{}.rinha", workload.name);
+        group.bench_function(workload.name, |b| {
+            b.iter(|| parser::parse(black_box(&workload.source), &filename))
+        });
+    }
+    group.finish();
+}
+
+fn prepass(c: &mut Criterion) {
+    let workloads = workloads();
+
+    let mut group = c.benchmark_group("validate");
+    for workload in &workloads {
+        let value: Value = serde_json::from_str(&workload.json).unwrap();
+        group.bench_function(workload.name, |b| {
+            b.iter(|| validate::validate(black_box(&value)).unwrap())
+        });
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("parse_errors");
+    for workload in &workloads {
+        group.bench_function(workload.name, |b| {
+            b.iter(|| check::parse_errors(black_box(&workload.file)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn eval(c: &mut Criterion) {
+    let workloads = workloads();
+
+    let mut group = c.benchmark_group("eval");
+    for workload in &workloads {
+        group.bench_function(workload.name, |b| {
+            b.iter_batched(
+                || workload.file.expression.clone(),
+                |term| {
+                    let mut evaluator = Evaluator::new();
+                    evaluator.output(io::sink());
+                    let mut env = Default::default();
+                    evaluator.eval(&mut env, term)
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, load, prepass, eval);
+criterion_main!(benches);
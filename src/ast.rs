@@ -2,9 +2,13 @@
 // https://github.com/aripiprazole/rinha-de-compiler/blob/main/src/ast.rs
 
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
+use std::mem;
 use std::rc::Rc;
 
+use crate::error::RuntimeError;
+use crate::integer::Integer;
+
 #[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct File {
     pub name: String,
@@ -42,8 +46,12 @@ impl Location {
         }
     }
 
+    /// The span from the start of `self` to the end of `other`. Values from
+    /// different files have no span covering both, so `self` is kept.
     pub fn merge(self, other: Self) -> Self {
-        assert_eq!(self.filename, other.filename);
+        if self.filename != other.filename {
+            return self;
+        }
         Self {
             start: self.start,
             end: other.end,
@@ -56,11 +64,25 @@ pub trait Element {
     fn location(&self) -> &Location;
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Error {
     pub message: String,
     pub full_text: String,
     pub location: Location,
+    /// What went wrong, for errors raised by the evaluator.
+    #[serde(skip)]
+    pub kind: Option<Box<RuntimeError>>,
+}
+
+impl Error {
+    pub fn runtime(kind: RuntimeError, location: Location) -> Self {
+        Self {
+            message: kind.title().into(),
+            full_text: kind.to_string(),
+            location,
+            kind: Some(Box::new(kind)),
+        }
+    }
 }
 
 impl Element for Error {
@@ -69,6 +91,23 @@ impl Element for Error {
     }
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Location {
+            start,
+            end,
+            filename,
+        } = &self.location;
+        let message = &self.message;
+        let full_text = &self.full_text;
+        writeln!(f, "[Error ({}:{}:{})] {}", filename, start, end, message)?;
+        writeln!(f, "{}", full_text)?;
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct If {
     pub condition: Box<Term>,
@@ -77,6 +116,14 @@ pub struct If {
     pub location: Location,
 }
 
+/// A function provided by the interpreter, bound in the prelude. Builtins
+/// only exist as values, so they are never part of a program's AST.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Builtin {
+    pub name: String,
+    pub location: Location,
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Let {
     pub name: Var,
@@ -85,6 +132,15 @@ pub struct Let {
     pub location: Location,
 }
 
+/// Evaluates the file at `path`, relative to the importing file, and binds
+/// the names defined at its top level before evaluating `next`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Import {
+    pub path: Str,
+    pub next: Box<Term>,
+    pub location: Location,
+}
+
 #[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Str {
     pub value: String,
@@ -111,7 +167,7 @@ impl Element for Bool {
 
 #[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Int {
-    pub value: i32,
+    pub value: Integer,
     pub location: Location,
 }
 
@@ -138,6 +194,42 @@ pub enum BinaryOp {
     Or,
 }
 
+impl BinaryOp {
+    pub const ALL: [BinaryOp; 13] = [
+        BinaryOp::Add,
+        BinaryOp::Sub,
+        BinaryOp::Mul,
+        BinaryOp::Div,
+        BinaryOp::Rem,
+        BinaryOp::Eq,
+        BinaryOp::Neq,
+        BinaryOp::Lt,
+        BinaryOp::Gt,
+        BinaryOp::Lte,
+        BinaryOp::Gte,
+        BinaryOp::And,
+        BinaryOp::Or,
+    ];
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Eq => "==",
+            BinaryOp::Neq => "!=",
+            BinaryOp::Lt => "<",
+            BinaryOp::Gt => ">",
+            BinaryOp::Lte => "<=",
+            BinaryOp::Gte => ">=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        }
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Binary {
     pub lhs: Box<Term>,
@@ -227,7 +319,7 @@ impl Element for Tuple {
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Var {
     pub text: String,
     pub location: Location,
@@ -239,7 +331,7 @@ impl Element for Var {
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "kind")]
 pub enum Term {
     Error(Error),
@@ -248,7 +340,10 @@ pub enum Term {
     Call(Call),
     Binary(Binary),
     Function(Function),
+    #[serde(skip)]
+    Builtin(Builtin),
     Let(Let),
+    Import(Import),
     If(If),
     Print(Print),
     First(First),
@@ -265,6 +360,7 @@ impl Element for Term {
             Term::Int(arg0) => &arg0.location,
             Term::Str(arg0) => &arg0.location,
             Term::Function(arg0) => &arg0.location,
+            Term::Builtin(arg0) => &arg0.location,
             Term::Call(arg0) => arg0.location(),
             Term::Var(arg0) => arg0.location(),
             Term::Binary(arg0) => &arg0.location,
@@ -272,9 +368,417 @@ impl Element for Term {
             Term::First(arg0) => &arg0.location,
             Term::Second(arg0) => &arg0.location,
             Term::Let(arg0) => &arg0.location,
+            Term::Import(arg0) => &arg0.location,
             Term::If(arg0) => &arg0.location,
             Term::Bool(arg0) => &arg0.location,
             Term::Tuple(arg0) => arg0.location(),
         }
     }
 }
+
+impl Term {
+    pub fn location_mut(&mut self) -> &mut Location {
+        match self {
+            Term::Error(arg0) => &mut arg0.location,
+            Term::Int(arg0) => &mut arg0.location,
+            Term::Str(arg0) => &mut arg0.location,
+            Term::Function(arg0) => &mut arg0.location,
+            Term::Builtin(arg0) => &mut arg0.location,
+            Term::Call(arg0) => &mut arg0.location,
+            Term::Var(arg0) => &mut arg0.location,
+            Term::Binary(arg0) => &mut arg0.location,
+            Term::Print(arg0) => &mut arg0.location,
+            Term::First(arg0) => &mut arg0.location,
+            Term::Second(arg0) => &mut arg0.location,
+            Term::Let(arg0) => &mut arg0.location,
+            Term::Import(arg0) => &mut arg0.location,
+            Term::If(arg0) => &mut arg0.location,
+            Term::Bool(arg0) => &mut arg0.location,
+            Term::Tuple(arg0) => &mut arg0.location,
+        }
+    }
+
+    /// The direct subterms, in source order.
+    pub fn children(&self) -> Vec<&Term> {
+        match self {
+            Term::Error(_)
+            | Term::Int(_)
+            | Term::Str(_)
+            | Term::Bool(_)
+            | Term::Builtin(_)
+            | Term::Var(_) => vec![],
+            Term::Call(term) => {
+                let mut children = vec![term.callee.as_ref()];
+                children.extend(&term.arguments);
+                children
+            }
+            Term::Binary(term) => vec![&term.lhs, &term.rhs],
+            Term::Function(term) => vec![&term.value],
+            Term::Let(term) => vec![&term.value, &term.next],
+            Term::Import(term) => vec![&term.next],
+            Term::If(term) => {
+                vec![&term.condition, &term.then, &term.otherwise]
+            }
+            Term::Print(term) => vec![&term.value],
+            Term::First(term) => vec![&term.value],
+            Term::Second(term) => vec![&term.value],
+            Term::Tuple(term) => vec![&term.first, &term.second],
+        }
+    }
+
+    /// Moves the term out, leaving a childless placeholder behind, as terms
+    /// can't be taken apart by value.
+    pub fn take(&mut self) -> Term {
+        mem::replace(self, placeholder())
+    }
+
+    /// Whether the term has no subterms.
+    fn is_leaf(&self) -> bool {
+        matches!(
+            self,
+            Term::Error(_)
+                | Term::Int(_)
+                | Term::Str(_)
+                | Term::Bool(_)
+                | Term::Builtin(_)
+                | Term::Var(_)
+        )
+    }
+
+    /// Moves the direct subterms that have subterms of their own onto
+    /// `pending`, leaving placeholders. Leaves are dropped in place.
+    fn detach(&mut self, pending: &mut Vec<Term>) {
+        let mut push = |term: &mut Term| {
+            if !term.is_leaf() {
+                pending.push(term.take());
+            }
+        };
+        match self {
+            Term::Error(_)
+            | Term::Int(_)
+            | Term::Str(_)
+            | Term::Bool(_)
+            | Term::Builtin(_)
+            | Term::Var(_) => {}
+            Term::Call(term) => {
+                push(&mut term.callee);
+                term.arguments.iter_mut().for_each(push);
+            }
+            Term::Binary(term) => {
+                push(&mut term.lhs);
+                push(&mut term.rhs);
+            }
+            Term::Let(term) => {
+                push(&mut term.value);
+                push(&mut term.next);
+            }
+            Term::If(term) => {
+                push(&mut term.condition);
+                push(&mut term.then);
+                push(&mut term.otherwise);
+            }
+            Term::Tuple(term) => {
+                push(&mut term.first);
+                push(&mut term.second);
+            }
+            Term::Import(Import { next: value, .. })
+            | Term::Function(Function { value, .. })
+            | Term::Print(Print { value, .. })
+            | Term::First(First { value, .. })
+            | Term::Second(Second { value, .. }) => push(value),
+        }
+    }
+
+    /// A copy of this term alone, with each subterm replaced by `copy` of
+    /// it, called in the order of [`Term::children`].
+    fn copy_with(&self, copy: &mut impl FnMut(&Term) -> Term) -> Term {
+        let mut boxed = |term: &Term| Box::new(copy(term));
+        match self {
+            Term::Error(term) => Term::Error(term.clone()),
+            Term::Int(term) => Term::Int(term.clone()),
+            Term::Str(term) => Term::Str(term.clone()),
+            Term::Bool(term) => Term::Bool(term.clone()),
+            Term::Builtin(term) => Term::Builtin(term.clone()),
+            Term::Var(term) => Term::Var(term.clone()),
+            Term::Call(term) => Term::Call(Call {
+                callee: boxed(&term.callee),
+                arguments: term.arguments.iter().map(copy).collect(),
+                location: term.location.clone(),
+            }),
+            Term::Binary(term) => Term::Binary(Binary {
+                lhs: boxed(&term.lhs),
+                op: term.op,
+                rhs: boxed(&term.rhs),
+                location: term.location.clone(),
+            }),
+            Term::Function(term) => Term::Function(Function {
+                parameters: term.parameters.clone(),
+                value: boxed(&term.value),
+                location: term.location.clone(),
+            }),
+            Term::Let(term) => Term::Let(Let {
+                name: term.name.clone(),
+                value: boxed(&term.value),
+                next: boxed(&term.next),
+                location: term.location.clone(),
+            }),
+            Term::Import(term) => Term::Import(Import {
+                path: term.path.clone(),
+                next: boxed(&term.next),
+                location: term.location.clone(),
+            }),
+            Term::If(term) => Term::If(If {
+                condition: boxed(&term.condition),
+                then: boxed(&term.then),
+                otherwise: boxed(&term.otherwise),
+                location: term.location.clone(),
+            }),
+            Term::Print(term) => Term::Print(Print {
+                value: boxed(&term.value),
+                location: term.location.clone(),
+            }),
+            Term::First(term) => Term::First(First {
+                value: boxed(&term.value),
+                location: term.location.clone(),
+            }),
+            Term::Second(term) => Term::Second(Second {
+                value: boxed(&term.value),
+                location: term.location.clone(),
+            }),
+            Term::Tuple(term) => Term::Tuple(Tuple {
+                first: boxed(&term.first),
+                second: boxed(&term.second),
+                location: term.location.clone(),
+            }),
+        }
+    }
+
+    /// Copies the first `depth` levels recursively and anything deeper from
+    /// a work list.
+    fn copy_within(&self, depth: usize) -> Term {
+        match depth {
+            0 => self.copy_deep(),
+            _ => self.copy_with(&mut |term| term.copy_within(depth - 1)),
+        }
+    }
+
+    /// Copies the tree without recursing, building each term once copies
+    /// of its subterms are on top of `copies`.
+    fn copy_deep(&self) -> Term {
+        let mut pending = vec![(self, false)];
+        let mut copies = Vec::new();
+        while let Some((term, visited)) = pending.pop() {
+            let children = term.children();
+            if visited || children.is_empty() {
+                let rest = copies.len() - children.len();
+                let mut children = copies.split_off(rest).into_iter();
+                let copy = term.copy_with(&mut |_| {
+                    children.next().unwrap_or_else(placeholder)
+                });
+                copies.push(copy);
+            } else {
+                pending.push((term, true));
+                let children = children.into_iter().rev();
+                pending.extend(children.map(|child| (child, false)));
+            }
+        }
+        copies.pop().unwrap_or_else(placeholder)
+    }
+}
+
+/// A term without children, standing in for one that was moved out.
+fn placeholder() -> Term {
+    Term::Bool(Bool::default())
+}
+
+/// Levels of nesting cloned recursively, as most terms are shallow.
+const CLONE_DEPTH: usize = 64;
+
+impl Clone for Term {
+    // Deriving it would recurse once per level of nesting
+    fn clone(&self) -> Self {
+        self.copy_within(CLONE_DEPTH)
+    }
+}
+
+impl Drop for Term {
+    // Subterms are moved onto a work list before being dropped, so deeply
+    // nested trees can't overflow the stack
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        self.detach(&mut pending);
+        while let Some(mut term) = pending.pop() {
+            term.detach(&mut pending);
+        }
+    }
+}
+
+/// Functions building terms at the default location, for writing ASTs by
+/// hand:
+///
+/// ```
+/// use rinha_interpreter::ast::build::*;
+/// use rinha_interpreter::eval::Evaluator;
+///
+/// let term = let_("id", fun(["x"], var("x")), call(var("id"), [int(42)]));
+/// let result = Evaluator::new().eval(&mut Default::default(), term);
+/// assert_eq!(result, int(42));
+/// ```
+pub mod build {
+    use super::*;
+
+    fn name(text: &str) -> Var {
+        Var {
+            text: text.into(),
+            location: Default::default(),
+        }
+    }
+
+    pub fn int(value: impl Into<Integer>) -> Term {
+        Term::Int(Int {
+            value: value.into(),
+            location: Default::default(),
+        })
+    }
+
+    pub fn string(value: impl Into<String>) -> Term {
+        Term::Str(Str {
+            value: value.into(),
+            location: Default::default(),
+        })
+    }
+
+    pub fn boolean(value: bool) -> Term {
+        Term::Bool(Bool {
+            value,
+            location: Default::default(),
+        })
+    }
+
+    pub fn parse_error(message: &str, full_text: &str) -> Term {
+        Term::Error(Error {
+            message: message.into(),
+            full_text: full_text.into(),
+            location: Default::default(),
+            kind: None,
+        })
+    }
+
+    pub fn var(text: &str) -> Term {
+        Term::Var(name(text))
+    }
+
+    pub fn let_(text: &str, value: Term, next: Term) -> Term {
+        Term::Let(Let {
+            name: name(text),
+            value: Box::new(value),
+            next: Box::new(next),
+            location: Default::default(),
+        })
+    }
+
+    pub fn import(path: &str, next: Term) -> Term {
+        Term::Import(Import {
+            path: Str {
+                value: path.into(),
+                location: Default::default(),
+            },
+            next: Box::new(next),
+            location: Default::default(),
+        })
+    }
+
+    pub fn fun<'a>(
+        parameters: impl IntoIterator<Item = &'a str>,
+        value: Term,
+    ) -> Term {
+        Term::Function(Function {
+            parameters: parameters.into_iter().map(name).collect(),
+            value: Box::new(value),
+            location: Default::default(),
+        })
+    }
+
+    pub fn call(
+        callee: Term,
+        arguments: impl IntoIterator<Item = Term>,
+    ) -> Term {
+        Term::Call(Call {
+            callee: Box::new(callee),
+            arguments: arguments.into_iter().collect(),
+            location: Default::default(),
+        })
+    }
+
+    pub fn if_(condition: Term, then: Term, otherwise: Term) -> Term {
+        Term::If(If {
+            condition: Box::new(condition),
+            then: Box::new(then),
+            otherwise: Box::new(otherwise),
+            location: Default::default(),
+        })
+    }
+
+    pub fn binary(lhs: Term, op: BinaryOp, rhs: Term) -> Term {
+        Term::Binary(Binary {
+            lhs: Box::new(lhs),
+            op,
+            rhs: Box::new(rhs),
+            location: Default::default(),
+        })
+    }
+
+    pub fn tuple(first: Term, second: Term) -> Term {
+        Term::Tuple(Tuple {
+            first: Box::new(first),
+            second: Box::new(second),
+            location: Default::default(),
+        })
+    }
+
+    pub fn first(value: Term) -> Term {
+        Term::First(First {
+            value: Box::new(value),
+            location: Default::default(),
+        })
+    }
+
+    pub fn second(value: Term) -> Term {
+        Term::Second(Second {
+            value: Box::new(value),
+            location: Default::default(),
+        })
+    }
+
+    pub fn print(value: Term) -> Term {
+        Term::Print(Print {
+            value: Box::new(value),
+            location: Default::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::build::*;
+    use super::*;
+    use crate::binary;
+
+    #[test]
+    fn clone_deep() {
+        let mut expression = int(0);
+        for i in 0..200_000 {
+            expression = match i % 2 {
+                0 => let_("_", int(i), expression),
+                _ => if_(boolean(true), expression, call(var("f"), [])),
+            };
+        }
+        let file = File {
+            name: "deep.rinha".into(),
+            expression,
+            location: Default::default(),
+        };
+        let copy = file.clone();
+        assert_eq!(binary::encode(&copy), binary::encode(&file));
+    }
+}
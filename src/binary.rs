@@ -0,0 +1,549 @@
+// A compact binary encoding of `ast::File`, used as a cache of programs so
+// that large JSON ASTs don't have to be parsed on every run.
+//
+// The file starts with `MAGIC` and the format `VERSION`, followed by the
+// table of every string in the program, the file name and location, and the
+// terms in postorder, children first. Numbers are LEB128 varints, signed ones
+// zigzag encoded. Strings are written as indices into the table, and each
+// location as its file, the offset of its start from the start of the
+// previous location, and its length.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::*;
+use crate::integer::Integer;
+
+/// Starts every binary AST, telling it apart from JSON and rinha sources.
+pub const MAGIC: &[u8; 8] = b"RINHAAST";
+
+/// Changes whenever the encoding does, so that stale caches are rejected.
+pub const VERSION: u64 = 2;
+
+const ERROR: u8 = 0;
+const INT: u8 = 1;
+const BIG_INT: u8 = 2;
+const STR: u8 = 3;
+const BOOL: u8 = 4;
+const CALL: u8 = 5;
+const BINARY: u8 = 6;
+const FUNCTION: u8 = 7;
+const LET: u8 = 8;
+const IF: u8 = 9;
+const PRINT: u8 = 10;
+const FIRST: u8 = 11;
+const SECOND: u8 = 12;
+const TUPLE: u8 = 13;
+const VAR: u8 = 14;
+/// Follows the last term.
+const END: u8 = 15;
+const IMPORT: u8 = 16;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Invalid {
+    /// Written by a version of the interpreter with another encoding.
+    Version(u64),
+    /// Ends before the program does.
+    Truncated,
+    Malformed(&'static str),
+}
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Invalid::Version(version) => write!(
+                f,
+                "binary AST has version {version}, but this interpreter \
+                 reads version {VERSION}, compile it again with compile-ast"
+            ),
+            Invalid::Truncated => write!(f, "binary AST is truncated"),
+            Invalid::Malformed(reason) => {
+                write!(f, "invalid binary AST: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Invalid {}
+
+/// Whether `bytes` look like a binary AST rather than text.
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn encode(file: &File) -> Vec<u8> {
+    let mut encoder = Encoder::default();
+    encoder.string(&file.name);
+    encoder.location(&file.location);
+
+    // Children are written before their parents, in source order
+    let mut pending = vec![(&file.expression, false)];
+    while let Some((term, visited)) = pending.pop() {
+        if visited {
+            encoder.term(term);
+        } else {
+            pending.push((term, true));
+            let children = term.children().into_iter().rev();
+            pending.extend(children.map(|child| (child, false)));
+        }
+    }
+
+    encoder.tag(END);
+
+    let mut bytes = MAGIC.to_vec();
+    unsigned(&mut bytes, VERSION);
+    unsigned(&mut bytes, encoder.strings.len() as u64);
+    for string in &encoder.strings {
+        unsigned(&mut bytes, string.len() as u64);
+        bytes.extend(string.as_bytes());
+    }
+    bytes.extend(encoder.bytes);
+    bytes
+}
+
+pub fn decode(bytes: &[u8]) -> Result<File, Invalid> {
+    let bytes = bytes
+        .strip_prefix(MAGIC)
+        .ok_or(Invalid::Malformed("missing header"))?;
+    let mut decoder = Decoder {
+        bytes,
+        strings: Vec::new(),
+        start: 0,
+    };
+    let version = decoder.unsigned()?;
+    if version != VERSION {
+        return Err(Invalid::Version(version));
+    }
+    let count = decoder.unsigned()?;
+    for _ in 0..count {
+        let len = decoder.length()?;
+        let string = std::str::from_utf8(decoder.take(len)?)
+            .map_err(|_| Invalid::Malformed("string is not UTF-8"))?;
+        decoder.strings.push(string.to_string());
+    }
+    let name = decoder.string()?;
+    let location = decoder.location()?;
+
+    let mut terms = Vec::new();
+    loop {
+        match decoder.byte()? {
+            END => break,
+            tag => decoder.term(tag, &mut terms)?,
+        }
+    }
+    if !decoder.bytes.is_empty() {
+        return Err(Invalid::Malformed("data after the end of the program"));
+    }
+    if terms.len() != 1 {
+        return Err(Invalid::Malformed("expected a single expression"));
+    }
+    let expression = terms.pop().unwrap();
+    Ok(File {
+        name,
+        expression,
+        location,
+    })
+}
+
+fn unsigned(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn signed(bytes: &mut Vec<u8>, value: i64) {
+    unsigned(bytes, ((value << 1) ^ (value >> 63)) as u64);
+}
+
+#[derive(Default)]
+struct Encoder<'a> {
+    bytes: Vec<u8>,
+    strings: Vec<&'a str>,
+    indices: HashMap<&'a str, usize>,
+    /// Where the previous location starts.
+    start: usize,
+}
+
+impl<'a> Encoder<'a> {
+    fn string(&mut self, string: &'a str) {
+        let next = self.strings.len();
+        let index = *self.indices.entry(string).or_insert(next);
+        if index == next {
+            self.strings.push(string);
+        }
+        unsigned(&mut self.bytes, index as u64);
+    }
+
+    fn location(&mut self, location: &'a Location) {
+        self.string(&location.filename);
+        let start = location.start as i64 - self.start as i64;
+        signed(&mut self.bytes, start);
+        signed(&mut self.bytes, location.end as i64 - location.start as i64);
+        self.start = location.start;
+    }
+
+    fn var(&mut self, var: &'a Var) {
+        self.string(&var.text);
+        self.location(&var.location);
+    }
+
+    fn tag(&mut self, tag: u8) {
+        self.bytes.push(tag);
+    }
+
+    /// Writes `term` without its children, which are already written.
+    fn term(&mut self, term: &'a Term) {
+        match term {
+            Term::Error(term) => {
+                self.tag(ERROR);
+                self.string(&term.message);
+                self.string(&term.full_text);
+            }
+            Term::Int(term) => match term.value.to_i64() {
+                Some(value) => {
+                    self.tag(INT);
+                    signed(&mut self.bytes, value);
+                }
+                None => {
+                    self.tag(BIG_INT);
+                    let digits = term.value.to_string();
+                    unsigned(&mut self.bytes, digits.len() as u64);
+                    self.bytes.extend(digits.as_bytes());
+                }
+            },
+            Term::Str(term) => {
+                self.tag(STR);
+                self.string(&term.value);
+            }
+            Term::Bool(term) => {
+                self.tag(BOOL);
+                self.bytes.push(term.value as u8);
+            }
+            Term::Call(term) => {
+                self.tag(CALL);
+                unsigned(&mut self.bytes, term.arguments.len() as u64);
+            }
+            Term::Binary(term) => {
+                self.tag(BINARY);
+                let op = BinaryOp::ALL.iter().position(|op| *op == term.op);
+                self.bytes.push(op.unwrap() as u8);
+            }
+            Term::Function(term) => {
+                self.tag(FUNCTION);
+                unsigned(&mut self.bytes, term.parameters.len() as u64);
+                term.parameters.iter().for_each(|var| self.var(var));
+            }
+            Term::Let(term) => {
+                self.tag(LET);
+                self.var(&term.name);
+            }
+            Term::Import(term) => {
+                self.tag(IMPORT);
+                self.string(&term.path.value);
+                self.location(&term.path.location);
+            }
+            Term::If(_) => self.tag(IF),
+            Term::Print(_) => self.tag(PRINT),
+            Term::First(_) => self.tag(FIRST),
+            Term::Second(_) => self.tag(SECOND),
+            Term::Tuple(_) => self.tag(TUPLE),
+            Term::Var(term) => {
+                self.tag(VAR);
+                self.string(&term.text);
+            }
+            // Builtins are only written as the names they are bound to
+            Term::Builtin(term) => {
+                self.tag(VAR);
+                self.string(&term.name);
+            }
+        }
+        self.location(term.location());
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    strings: Vec<String>,
+    start: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Invalid> {
+        if self.bytes.len() < len {
+            return Err(Invalid::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, Invalid> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn unsigned(&mut self) -> Result<u64, Invalid> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Invalid::Malformed("number is too large"))
+    }
+
+    fn signed(&mut self) -> Result<i64, Invalid> {
+        let value = self.unsigned()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    /// A length or count, which can't be larger than the remaining input.
+    fn length(&mut self) -> Result<usize, Invalid> {
+        match self.unsigned()? {
+            len if len > self.bytes.len() as u64 => Err(Invalid::Truncated),
+            len => Ok(len as usize),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, Invalid> {
+        let index = self.unsigned()?;
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| self.strings.get(index))
+            .cloned()
+            .ok_or(Invalid::Malformed("string index out of bounds"))
+    }
+
+    fn location(&mut self) -> Result<Location, Invalid> {
+        let filename = self.string()?;
+        let offset = |base: usize, delta: i64| {
+            base.checked_add_signed(delta as isize)
+                .ok_or(Invalid::Malformed("location out of bounds"))
+        };
+        let start = offset(self.start, self.signed()?)?;
+        let end = offset(start, self.signed()?)?;
+        self.start = start;
+        Ok(Location {
+            start,
+            end,
+            filename,
+        })
+    }
+
+    fn var(&mut self) -> Result<Var, Invalid> {
+        Ok(Var {
+            text: self.string()?,
+            location: self.location()?,
+        })
+    }
+
+    /// Reads a term, taking its children from the end of `terms` and
+    /// pushing it in their place.
+    fn term(&mut self, tag: u8, terms: &mut Vec<Term>) -> Result<(), Invalid> {
+        let arity = match tag {
+            CALL => self.length()?.checked_add(1),
+            BINARY | LET | TUPLE => Some(2),
+            IF => Some(3),
+            FUNCTION | IMPORT | PRINT | FIRST | SECOND => Some(1),
+            _ => Some(0),
+        };
+        let mut children = match arity.and_then(|n| terms.len().checked_sub(n))
+        {
+            Some(first) => terms.split_off(first),
+            None => return Err(Invalid::Malformed("missing subterm")),
+        };
+        let arguments = match tag {
+            CALL => children.split_off(1),
+            _ => Vec::new(),
+        };
+        children.reverse();
+        let mut child = || Box::new(children.pop().unwrap());
+
+        let term = match tag {
+            ERROR => Term::Error(Error {
+                message: self.string()?,
+                full_text: self.string()?,
+                location: self.location()?,
+                kind: None,
+            }),
+            INT => Term::Int(Int {
+                value: self.signed()?.into(),
+                location: self.location()?,
+            }),
+            BIG_INT => {
+                let len = self.length()?;
+                let value = std::str::from_utf8(self.take(len)?)
+                    .ok()
+                    .and_then(|digits| digits.parse::<Integer>().ok())
+                    .ok_or(Invalid::Malformed("invalid integer"))?;
+                Term::Int(Int {
+                    value,
+                    location: self.location()?,
+                })
+            }
+            STR => Term::Str(Str {
+                value: self.string()?,
+                location: self.location()?,
+            }),
+            BOOL => Term::Bool(Bool {
+                value: match self.byte()? {
+                    0 => false,
+                    1 => true,
+                    _ => return Err(Invalid::Malformed("invalid boolean")),
+                },
+                location: self.location()?,
+            }),
+            CALL => Term::Call(Call {
+                location: self.location()?,
+                callee: child(),
+                arguments,
+            }),
+            BINARY => Term::Binary(Binary {
+                op: BinaryOp::ALL
+                    .get(self.byte()? as usize)
+                    .copied()
+                    .ok_or(Invalid::Malformed("invalid operator"))?,
+                location: self.location()?,
+                lhs: child(),
+                rhs: child(),
+            }),
+            FUNCTION => {
+                let count = self.length()?;
+                Term::Function(Function {
+                    parameters: (0..count)
+                        .map(|_| self.var())
+                        .collect::<Result<_, _>>()?,
+                    location: self.location()?,
+                    value: child(),
+                })
+            }
+            LET => Term::Let(Let {
+                name: self.var()?,
+                location: self.location()?,
+                value: child(),
+                next: child(),
+            }),
+            IMPORT => Term::Import(Import {
+                path: Str {
+                    value: self.string()?,
+                    location: self.location()?,
+                },
+                location: self.location()?,
+                next: child(),
+            }),
+            IF => Term::If(If {
+                location: self.location()?,
+                condition: child(),
+                then: child(),
+                otherwise: child(),
+            }),
+            PRINT => Term::Print(Print {
+                location: self.location()?,
+                value: child(),
+            }),
+            FIRST => Term::First(First {
+                location: self.location()?,
+                value: child(),
+            }),
+            SECOND => Term::Second(Second {
+                location: self.location()?,
+                value: child(),
+            }),
+            TUPLE => Term::Tuple(Tuple {
+                location: self.location()?,
+                first: child(),
+                second: child(),
+            }),
+            VAR => Term::Var(Var {
+                text: self.string()?,
+                location: self.location()?,
+            }),
+            _ => return Err(Invalid::Malformed("unknown term")),
+        };
+        terms.push(term);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::build::*;
+    use crate::parser;
+
+    fn roundtrip(file: &File) {
+        let decoded = decode(&encode(file)).unwrap();
+        assert_eq!(&decoded, file);
+    }
+
+    #[test]
+    fn binary_roundtrip() {
+        let source = "let fib = fn (n) => {
+          if (n < 2) { n } else { fib(n - 1) + fib(n - 2) }
+        };
+        import \"lib.rinha\";
+        let pair = (fib(10), (\"fib\", true));
+        print(first(pair) + second(second(pair)) + 99999999999999999999)";
+        let mut file = parser::parse(source, "fib.rinha").unwrap();
+        roundtrip(&file);
+
+        // Terms from other files and with locations in any order
+        let Term::Let(term) = &mut file.expression else {
+            panic!("expected a let");
+        };
+        *term.value = parse_error("Unexpected token", "expected an expression");
+        *term.value.location_mut() = Location::new(7, 3, "other.rinha");
+        roundtrip(&file);
+    }
+
+    #[test]
+    fn binary_deep_roundtrip() {
+        let mut expression = int(0);
+        for _ in 0..100_000 {
+            expression = tuple(int(1), expression);
+        }
+        let file = File {
+            name: "deep.rinha".into(),
+            expression,
+            location: Default::default(),
+        };
+        let bytes = encode(&file);
+        let decoded = decode(&bytes).unwrap();
+        assert_eq!(encode(&decoded), bytes);
+
+        // Invalid input is rejected after the deep term is decoded
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn binary_is_compact() {
+        let source = "let f = fn (x) => { x + x }; print(f(f(1)))";
+        let file = parser::parse(source, "f.rinha").unwrap();
+        let json = serde_json::to_vec(&file).unwrap();
+        assert!(encode(&file).len() * 5 < json.len());
+    }
+
+    #[test]
+    fn binary_rejects_invalid() {
+        let file = parser::parse("print(1 + 2)", "a.rinha").unwrap();
+        let bytes = encode(&file);
+        assert!(is_binary(&bytes));
+        assert!(!is_binary(b"{\"name\": \"a.rinha\"}"));
+
+        let mut stale = MAGIC.to_vec();
+        unsigned(&mut stale, VERSION + 1);
+        stale.extend(&bytes[MAGIC.len() + 1..]);
+        assert_eq!(decode(&stale), Err(Invalid::Version(VERSION + 1)));
+
+        for len in MAGIC.len()..bytes.len() {
+            assert!(decode(&bytes[..len]).is_err(), "decoded {len} bytes");
+        }
+        let mut extra = bytes.clone();
+        extra.extend(&bytes[bytes.len() - 8..]);
+        assert!(decode(&extra).is_err());
+        assert!(decode(b"{}").is_err());
+    }
+}
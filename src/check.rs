@@ -0,0 +1,767 @@
+// Static checks, resolving variables to the bindings that lexically enclose
+// them and inferring simple types, without running the program.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::ast::*;
+use crate::error;
+use crate::prelude::{self, Signature};
+use crate::source;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub location: Location,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Type {
+    Unknown,
+    Int,
+    Str,
+    Bool,
+    Tuple(Box<Type>, Box<Type>),
+    Function(Vec<(String, Type)>, Box<Type>),
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Unknown => write!(f, "?"),
+            Type::Int => write!(f, "Int"),
+            Type::Str => write!(f, "Str"),
+            Type::Bool => write!(f, "Bool"),
+            Type::Tuple(first, second) => write!(f, "({first}, {second})"),
+            Type::Function(parameters, result) => {
+                write!(f, "fn (")?;
+                for (i, (name, kind)) in parameters.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{name}: {kind}")?;
+                }
+                write!(f, ") => {result}")
+            }
+        }
+    }
+}
+
+impl Type {
+    /// The type of a builtin, as given by its `signature`.
+    fn builtin(signature: &Signature) -> Type {
+        let parameters = signature
+            .parameters
+            .iter()
+            .map(|(name, ty)| (name.to_string(), Type::from(*ty)))
+            .collect();
+        Type::Function(parameters, Box::new(signature.result.into()))
+    }
+
+    /// The type of a term that evaluates to either `self` or `other`.
+    fn join(self, other: Type) -> Type {
+        match (self, other) {
+            (Type::Unknown, other) => other,
+            (this, Type::Unknown) => this,
+            (this, other) if this == other => this,
+            _ => Type::Unknown,
+        }
+    }
+}
+
+impl From<error::Type> for Type {
+    fn from(ty: error::Type) -> Type {
+        match ty {
+            error::Type::Int => Type::Int,
+            error::Type::Str => Type::Str,
+            error::Type::Bool => Type::Bool,
+            error::Type::Tuple => {
+                Type::Tuple(Box::new(Type::Unknown), Box::new(Type::Unknown))
+            }
+            // Without their parameters, functions are as good as unknown
+            error::Type::Function | error::Type::Unknown => Type::Unknown,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Kind {
+    Let,
+    Parameter,
+    Import,
+    Builtin,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Binding {
+    pub name: String,
+    pub kind: Kind,
+    /// Location of the bound name, or of the path it is imported from.
+    pub location: Location,
+    /// Location of the `let`, function or `import` that binds the name.
+    pub definition: Location,
+    pub ty: Type,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Analysis {
+    pub bindings: Vec<Binding>,
+    /// Every resolved variable, with the index of its binding.
+    pub references: Vec<(Location, usize)>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Analysis {
+    /// The binding named or referenced at `offset`.
+    pub fn binding_at(&self, offset: usize) -> Option<&Binding> {
+        let contains = |location: &Location| {
+            location.start <= offset && offset < location.end
+        };
+        let reference = self
+            .references
+            .iter()
+            .filter(|(location, _)| contains(location))
+            .min_by_key(|(location, _)| location.end - location.start)
+            .map(|(_, index)| &self.bindings[*index]);
+        reference.or_else(|| {
+            self.bindings
+                .iter()
+                .find(|binding| contains(&binding.location))
+        })
+    }
+
+    /// The binding of the name at `location`.
+    pub fn binding(&self, location: &Location) -> Option<&Binding> {
+        self.bindings
+            .iter()
+            .find(|binding| binding.location == *location)
+    }
+}
+
+struct Checker<'a> {
+    analysis: Analysis,
+    /// Visible bindings, innermost last.
+    scope: Vec<usize>,
+    /// Where the bindings of each function body start in `scope`.
+    frames: Vec<usize>,
+    /// Every name bound anywhere in the program.
+    names: HashSet<&'a str>,
+    /// The files being checked, outermost first, to detect import cycles.
+    importing: Vec<(PathBuf, String)>,
+}
+
+/// Errors left in the tree by the parser that produced it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseErrors(pub Vec<Error>);
+
+impl fmt::Display for ParseErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for error in &self.0 {
+            error.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseErrors {}
+
+/// Collects every `Error` node of `file`, in source order, even on branches
+/// that would never be evaluated. Programs with any of them must not run.
+pub fn parse_errors(file: &File) -> Result<(), ParseErrors> {
+    let mut errors = Vec::new();
+    let mut pending = vec![&file.expression];
+    while let Some(term) = pending.pop() {
+        match term {
+            Term::Error(error) => errors.push(error.clone()),
+            term => pending.extend(term.children().into_iter().rev()),
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(ParseErrors(errors)),
+    }
+}
+
+pub fn check(file: &File) -> Analysis {
+    let filename = &file.expression.location().filename;
+    let root = PathBuf::from(filename);
+    let root = fs::canonicalize(&root).unwrap_or(root);
+    check_imported(file, vec![(root, filename.clone())])
+}
+
+fn check_imported(file: &File, importing: Vec<(PathBuf, String)>) -> Analysis {
+    let mut names = HashSet::new();
+    collect_names(&file.expression, &mut names);
+
+    let mut checker = Checker {
+        analysis: Analysis::default(),
+        scope: Vec::new(),
+        frames: Vec::new(),
+        names,
+        importing,
+    };
+    // Builtins are in an outer scope, so programs may bind the same names
+    for signature in prelude::BUILTINS {
+        let var = Var {
+            text: signature.name.into(),
+            location: prelude::location(),
+        };
+        let index = checker.bind(&var, Kind::Builtin, &var.location);
+        checker.analysis.bindings[index].ty = Type::builtin(signature);
+        checker.scope.push(index);
+    }
+    checker.frames.push(checker.scope.len());
+    checker.term(&file.expression);
+    checker.analysis
+}
+
+fn collect_names<'a>(term: &'a Term, names: &mut HashSet<&'a str>) {
+    match term {
+        Term::Let(term) => {
+            names.insert(&term.name.text);
+        }
+        Term::Function(term) => {
+            for parameter in &term.parameters {
+                names.insert(&parameter.text);
+            }
+        }
+        _ => {}
+    }
+    for child in term.children() {
+        collect_names(child, names);
+    }
+}
+
+/// The names exported by an imported `module` with their types, or the
+/// first error found in it.
+fn module_exports(
+    module: &File,
+    importing: Vec<(PathBuf, String)>,
+) -> Result<Vec<(String, Type)>, String> {
+    let analysis = check_imported(module, importing);
+    if let Some(error) = analysis
+        .diagnostics
+        .iter()
+        .find(|diagnostic| diagnostic.severity == Severity::Error)
+    {
+        return Err(error.message.clone());
+    }
+
+    // The bindings of the `let`s and `import`s that the file runs in order
+    let mut exports = Vec::new();
+    let mut term = &module.expression;
+    loop {
+        let (location, next) = match term {
+            Term::Let(term) => (&term.name.location, &term.next),
+            Term::Import(term) => (&term.path.location, &term.next),
+            _ => break,
+        };
+        let bindings = analysis.bindings.iter();
+        exports.extend(
+            bindings
+                .filter(|binding| binding.location == *location)
+                .map(|binding| (binding.name.clone(), binding.ty.clone())),
+        );
+        term = next;
+    }
+    Ok(exports)
+}
+
+impl<'a> Checker<'a> {
+    fn lookup(&self, name: &str) -> Option<usize> {
+        let bindings = &self.analysis.bindings;
+        self.scope
+            .iter()
+            .rev()
+            .copied()
+            .find(|&index| bindings[index].name == name)
+    }
+
+    fn bind(&mut self, var: &Var, kind: Kind, definition: &Location) -> usize {
+        self.analysis.bindings.push(Binding {
+            name: var.text.clone(),
+            kind,
+            location: var.location.clone(),
+            definition: definition.clone(),
+            ty: Type::Unknown,
+        });
+        self.analysis.bindings.len() - 1
+    }
+
+    fn report(&mut self, severity: Severity, message: String, at: &Location) {
+        self.analysis.diagnostics.push(Diagnostic {
+            severity,
+            message,
+            location: at.clone(),
+        });
+    }
+
+    /// Records that `term` is used as a `ty`, warning if it cannot be one.
+    fn expect(&mut self, term: &Term, found: &Type, ty: Type, op: &str) {
+        match found {
+            Type::Unknown => {
+                if let Term::Var(var) = term {
+                    if let Some(index) = self.lookup(&var.text) {
+                        self.analysis.bindings[index].ty = ty;
+                    }
+                }
+            }
+            found if *found != ty => {
+                let message = format!("{op} expects {ty}, found {found}");
+                self.report(Severity::Warning, message, term.location());
+            }
+            _ => {}
+        }
+    }
+
+    fn term(&mut self, term: &'a Term) -> Type {
+        match term {
+            Term::Error(error) => {
+                let message = error.full_text.clone();
+                self.report(Severity::Error, message, &error.location);
+                Type::Unknown
+            }
+            Term::Builtin(term) => match prelude::signature(&term.name) {
+                Some(signature) => Type::builtin(signature),
+                None => Type::Unknown,
+            },
+            Term::Int(_) => Type::Int,
+            Term::Str(_) => Type::Str,
+            Term::Bool(_) => Type::Bool,
+            Term::Var(var) => self.check_var(var),
+            Term::Let(term) => self.check_let(term),
+            Term::Import(term) => self.check_import(term),
+            Term::Function(term) => self.check_function(term),
+            Term::Call(term) => self.check_call(term),
+            Term::Binary(term) => self.check_binary(term),
+            Term::If(term) => {
+                let condition = self.term(&term.condition);
+                self.expect(&term.condition, &condition, Type::Bool, "if");
+                let then = self.term(&term.then);
+                let otherwise = self.term(&term.otherwise);
+                then.join(otherwise)
+            }
+            Term::Tuple(term) => {
+                let first = self.term(&term.first);
+                let second = self.term(&term.second);
+                Type::Tuple(Box::new(first), Box::new(second))
+            }
+            Term::First(First { value, .. })
+            | Term::Second(Second { value, .. }) => {
+                let first = matches!(term, Term::First(_));
+                match self.term(value) {
+                    Type::Tuple(lhs, _) if first => *lhs,
+                    Type::Tuple(_, rhs) => *rhs,
+                    Type::Unknown => Type::Unknown,
+                    found => {
+                        let op = if first { "first" } else { "second" };
+                        let message =
+                            format!("{op} expects a tuple, found {found}");
+                        self.report(
+                            Severity::Warning,
+                            message,
+                            value.location(),
+                        );
+                        Type::Unknown
+                    }
+                }
+            }
+            Term::Print(term) => self.term(&term.value),
+        }
+    }
+
+    fn check_var(&mut self, var: &Var) -> Type {
+        match self.lookup(&var.text) {
+            Some(index) => {
+                let location = var.location.clone();
+                self.analysis.references.push((location, index));
+                self.analysis.bindings[index].ty.clone()
+            }
+            None if self.names.contains(var.text.as_str()) => {
+                let message = format!(
+                    "Variable \"{}\" is not bound here and depends on the \
+                     scope of the caller",
+                    var.text,
+                );
+                self.report(Severity::Warning, message, &var.location);
+                Type::Unknown
+            }
+            None => {
+                let message = format!("Undefined variable \"{}\"", var.text);
+                self.report(Severity::Error, message, &var.location);
+                Type::Unknown
+            }
+        }
+    }
+
+    fn check_let(&mut self, term: &'a Let) -> Type {
+        let start = self.scope.len();
+        let name = &term.name;
+
+        if name.text == "_" {
+            self.term(&term.value);
+            return self.term(&term.next);
+        }
+
+        let frame = *self.frames.last().unwrap();
+        let bindings = &self.analysis.bindings;
+        if self.scope[frame..]
+            .iter()
+            .any(|&index| bindings[index].name == name.text)
+        {
+            let message =
+                format!("Variable \"{}\" is already defined", name.text);
+            self.report(Severity::Error, message, &name.location);
+        }
+
+        let index = self.bind(name, Kind::Let, &term.location);
+
+        // Functions may call themselves, so they are bound in their body
+        let function = matches!(*term.value, Term::Function(_));
+        if let Term::Function(function) = &*term.value {
+            let parameters = function
+                .parameters
+                .iter()
+                .map(|parameter| (parameter.text.clone(), Type::Unknown))
+                .collect();
+            let ty = Type::Function(parameters, Box::new(Type::Unknown));
+            self.analysis.bindings[index].ty = ty;
+            self.scope.push(index);
+        }
+
+        let ty = self.term(&term.value);
+        self.analysis.bindings[index].ty = ty;
+        if !function {
+            self.scope.push(index);
+        }
+
+        let ty = self.term(&term.next);
+        self.scope.truncate(start);
+        ty
+    }
+
+    fn check_import(&mut self, term: &'a Import) -> Type {
+        let start = self.scope.len();
+        match self.exports(&term.path) {
+            Ok(exports) => {
+                for (name, ty) in exports {
+                    let var = Var {
+                        text: name,
+                        location: term.path.location.clone(),
+                    };
+                    let index = self.bind(&var, Kind::Import, &term.location);
+                    self.analysis.bindings[index].ty = ty;
+                    self.scope.push(index);
+                }
+            }
+            Err(message) => {
+                let message =
+                    format!("Cannot import {}: {message}", term.path.value);
+                self.report(Severity::Error, message, &term.path.location);
+            }
+        }
+        let ty = self.term(&term.next);
+        self.scope.truncate(start);
+        ty
+    }
+
+    /// The names exported by the file imported as `path`, with their types,
+    /// checking that file in turn.
+    fn exports(&self, path: &Str) -> Result<Vec<(String, Type)>, String> {
+        let file = source::resolve(&path.location.filename, &path.value);
+        let key = fs::canonicalize(&file).unwrap_or_else(|_| file.clone());
+        let name = file.to_string_lossy().into_owned();
+        if let Some(start) =
+            self.importing.iter().position(|(file, _)| *file == key)
+        {
+            let mut files = self.importing[start..]
+                .iter()
+                .map(|(_, name)| name.as_str())
+                .collect::<Vec<_>>();
+            files.push(&name);
+            return Err(format!("import cycle {}", files.join(" imports ")));
+        }
+
+        let module = source::load(&name).map_err(|error| error.to_string())?;
+        let mut importing = self.importing.clone();
+        importing.push((key, name));
+        module_exports(&module, importing)
+    }
+
+    fn check_function(&mut self, term: &'a Function) -> Type {
+        let start = self.scope.len();
+        self.frames.push(start);
+
+        let parameters = term
+            .parameters
+            .iter()
+            .map(|parameter| {
+                let index =
+                    self.bind(parameter, Kind::Parameter, &term.location);
+                self.scope.push(index);
+                index
+            })
+            .collect::<Vec<_>>();
+
+        let result = self.term(&term.value);
+
+        self.frames.pop();
+        self.scope.truncate(start);
+
+        let parameters = parameters
+            .into_iter()
+            .map(|index| {
+                let binding = &self.analysis.bindings[index];
+                (binding.name.clone(), binding.ty.clone())
+            })
+            .collect();
+        Type::Function(parameters, Box::new(result))
+    }
+
+    fn check_call(&mut self, term: &'a Call) -> Type {
+        let callee = self.term(&term.callee);
+        for argument in &term.arguments {
+            self.term(argument);
+        }
+
+        match callee {
+            Type::Function(parameters, result) => {
+                let expected = parameters.len();
+                let found = term.arguments.len();
+                if expected != found {
+                    let message =
+                        format!("Expected {expected} arguments, found {found}");
+                    self.report(Severity::Error, message, &term.location);
+                }
+                *result
+            }
+            Type::Unknown => Type::Unknown,
+            found => {
+                let message = format!("Expected a function, found {found}");
+                self.report(Severity::Warning, message, term.callee.location());
+                Type::Unknown
+            }
+        }
+    }
+
+    fn check_binary(&mut self, term: &'a Binary) -> Type {
+        let lhs = self.term(&term.lhs);
+        let rhs = self.term(&term.rhs);
+        let op = format!("\"{}\"", term.op.symbol());
+
+        let operands = |this: &mut Self, ty: Type| {
+            this.expect(&term.lhs, &lhs, ty.clone(), &op);
+            this.expect(&term.rhs, &rhs, ty, &op);
+        };
+
+        match term.op {
+            BinaryOp::Add => match lhs.clone().join(rhs.clone()) {
+                ty @ (Type::Int | Type::Str) => {
+                    operands(self, ty.clone());
+                    ty
+                }
+                Type::Unknown if lhs == Type::Unknown => Type::Unknown,
+                _ => {
+                    let message = format!(
+                        "{op} expects Int or Str operands, found {lhs} and {rhs}"
+                    );
+                    self.report(Severity::Warning, message, &term.location);
+                    Type::Unknown
+                }
+            },
+            BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => {
+                operands(self, Type::Int);
+                Type::Int
+            }
+            BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Lte | BinaryOp::Gte => {
+                operands(self, Type::Int);
+                Type::Bool
+            }
+            BinaryOp::And | BinaryOp::Or => {
+                operands(self, Type::Bool);
+                Type::Bool
+            }
+            BinaryOp::Eq | BinaryOp::Neq => Type::Bool,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    fn analyze(source: &str) -> Analysis {
+        check(&parser::parse(source, "test.rinha").unwrap())
+    }
+
+    fn messages(analysis: &Analysis) -> Vec<(Severity, &str)> {
+        analysis
+            .diagnostics
+            .iter()
+            .map(|diagnostic| {
+                (diagnostic.severity, diagnostic.message.as_str())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn check_types() {
+        let source = "\
+let fib = fn (n) => { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };
+let pair = (fib(10), \"x\");
+print(first(pair))";
+        let analysis = analyze(source);
+        assert!(analysis.diagnostics.is_empty());
+
+        let types = analysis
+            .bindings
+            .iter()
+            .filter(|binding| binding.kind != Kind::Builtin)
+            .map(|binding| (binding.name.as_str(), binding.ty.to_string()))
+            .collect::<Vec<_>>();
+        let expected = [
+            ("fib", "fn (n: Int) => Int".to_string()),
+            ("n", "Int".to_string()),
+            ("pair", "(Int, Str)".to_string()),
+        ];
+        assert_eq!(types, expected);
+    }
+
+    #[test]
+    fn check_builtins() {
+        let source = "\
+let min = fn (a, b) => { if (a < b) { a } else { b } };
+let n = len(\"abc\") + pow(2, 3);
+print(substring(\"abc\", n))";
+        let analysis = analyze(source);
+        let n = analysis.binding_at(60).unwrap();
+        assert_eq!((n.name.as_str(), n.ty.to_string()), ("n", "Int".into()));
+        let len = analysis.binding_at(64).unwrap();
+        assert_eq!(len.kind, Kind::Builtin);
+        assert_eq!(len.ty.to_string(), "fn (s: Str) => Int");
+
+        // Builtins may be shadowed, but are still called with their arity
+        let expected = [(Severity::Error, "Expected 3 arguments, found 2")];
+        assert_eq!(messages(&analysis), expected);
+    }
+
+    #[test]
+    fn check_references() {
+        let source = "let f = fn (x) => { x + 1 };\nf(2)";
+        let analysis = analyze(source);
+        let x = analysis.binding_at(20).unwrap();
+        assert_eq!((x.name.as_str(), x.kind), ("x", Kind::Parameter));
+        assert_eq!((x.location.start, x.location.end), (12, 13));
+        let f = analysis.binding_at(29).unwrap();
+        assert_eq!((f.name.as_str(), f.kind), ("f", Kind::Let));
+        assert_eq!((f.definition.start, f.definition.end), (0, 33));
+    }
+
+    #[test]
+    fn check_diagnostics() {
+        let source = "\
+let f = fn (x) => { x + y };
+let g = fn () => { let y = 1; f(1, 2) };
+let g = 1;
+print(z - true)";
+        let analysis = analyze(source);
+        let expected = [
+            (
+                Severity::Warning,
+                "Variable \"y\" is not bound here and depends on the scope of \
+                 the caller",
+            ),
+            (Severity::Error, "Expected 1 arguments, found 2"),
+            (Severity::Error, "Variable \"g\" is already defined"),
+            (Severity::Error, "Undefined variable \"z\""),
+            (Severity::Warning, "\"-\" expects Int, found Bool"),
+        ];
+        assert_eq!(messages(&analysis), expected);
+    }
+
+    #[test]
+    fn check_imports() {
+        let dir = std::env::temp_dir()
+            .join(format!("rinha-check-imports-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let lib = "let double = fn (n) => { n * 2 };\nprint(double(1))";
+        fs::write(dir.join("lib.rinha"), lib).unwrap();
+        fs::write(dir.join("self.rinha"), "import \"self.rinha\";\n0").unwrap();
+        let main = dir.join("main.rinha");
+        let analyze = |source: &str| {
+            check(&parser::parse(source, main.to_str().unwrap()).unwrap())
+        };
+
+        let analysis = analyze("import \"lib.rinha\";\ndouble(1, 2)");
+        let double = analysis.binding_at(21).unwrap();
+        assert_eq!(double.kind, Kind::Import);
+        assert_eq!(double.ty.to_string(), "fn (n: Int) => Int");
+        assert_eq!((double.location.start, double.location.end), (7, 18));
+        let expected = [(Severity::Error, "Expected 1 arguments, found 2")];
+        assert_eq!(messages(&analysis), expected);
+
+        let analysis = analyze("import \"lib.rinha\";\nlet double = 1;\n0");
+        let expected =
+            [(Severity::Error, "Variable \"double\" is already defined")];
+        assert_eq!(messages(&analysis), expected);
+
+        for (source, reason) in [
+            (
+                "import \"missing.rinha\";\n0",
+                "Cannot import missing.rinha",
+            ),
+            ("import \"self.rinha\";\n0", "import cycle"),
+        ] {
+            let analysis = analyze(source);
+            let [diagnostic] = analysis.diagnostics.as_slice() else {
+                panic!("expected one diagnostic for {source}");
+            };
+            assert_eq!(diagnostic.severity, Severity::Error);
+            assert!(diagnostic.message.contains(reason), "{source}");
+            assert_eq!(diagnostic.location.start, 7);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn check_parse_errors() {
+        let location = r#"{"start":0,"end":1,"filename":"e.rinha"}"#;
+        let error = |text| {
+            format!(
+                r#"{{"kind":"Error","message":"Unexpected token","full_text":"{text}","location":{location}}}"#
+            )
+        };
+        let json = format!(
+            r#"{{"name":"e.rinha","location":{location},"expression":{{"kind":"If","condition":{{"kind":"Bool","value":true,"location":{location}}},"then":{{"kind":"Tuple","first":{},"second":{{"kind":"Int","value":1,"location":{location}}},"location":{location}}},"otherwise":{},"location":{location}}}}}"#,
+            error("first"),
+            error("second"),
+        );
+        let file = crate::source::from_json(&json).unwrap();
+
+        let ParseErrors(errors) = parse_errors(&file).unwrap_err();
+        let texts = errors
+            .iter()
+            .map(|error| error.full_text.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(texts, ["first", "second"]);
+
+        let analysis = check(&file);
+        let expected =
+            [(Severity::Error, "first"), (Severity::Error, "second")];
+        assert_eq!(messages(&analysis), expected);
+
+        let file = parser::parse("print(1)", "test.rinha").unwrap();
+        assert!(parse_errors(&file).is_ok());
+    }
+}
@@ -0,0 +1,592 @@
+use crate::integer::{Overflow, Precision};
+use crate::{coverage, debug, dot, format, profile, report, source, trace};
+
+pub const USAGE: &str = "\
+Usage: rinha-interpreter [options] <file>
+       rinha-interpreter [options] -e <expression>
+       rinha-interpreter fmt [options] <rinha-file>...
+       rinha-interpreter dot [options] <file>
+       rinha-interpreter debug [options] <file>
+       rinha-interpreter compile-ast [options] <file>
+       rinha-interpreter dap
+       rinha-interpreter lsp
+       rinha-interpreter --explain <code>
+
+A <file> of - reads the program from stdin, as a JSON or binary AST or as
+rinha source.
+
+Options:
+    -e <expression>         Run the rinha expression instead of a file
+    --trace                 Log every function call to stderr
+    --trace-filter <names>  Only trace these comma-separated functions
+    --trace-depth <n>       Only trace calls less than <n> calls deep
+    --trace-output <file>   Write the trace to <file> instead of stderr
+    --profile               Print per-function statistics to stderr and
+                            write folded stacks to profile.folded
+    --profile-output <file> Write the folded stacks to <file>
+    --coverage              Print uncovered functions and branches to
+                            stderr and write an LCOV report to lcov.info
+    --coverage-output <file>
+                            Write the LCOV report to <file>
+    --error-format <format> Report failures as human readable text (the
+                            default) or as JSON objects with \"json\"
+    --integers <precision>  Evaluate integers as \"i32\" (the default),
+                            \"i64\" or arbitrary precision \"big\" values
+    --overflow <mode>       Wrap around on integer overflow (\"wrap\", the
+                            default) or fail with an \"error\"
+
+Options for fmt:
+    --check        Report unformatted files instead of rewriting them
+    --stdout       Print the formatted files instead of rewriting them
+    --width <n>    Maximum line width (default: 80)
+    --indent <n>   Spaces per indentation level (default: 2)
+
+Options for dot:
+    --depth <n>            Render at most <n> levels below the root
+    --at <start>[:<end>]   Render the smallest subtree spanning these
+                           byte offsets
+
+Options for debug:
+    --break <breakpoint>   Pause at <line>, <file>:<line> or <function>
+                           before running, may be repeated
+    --integers <precision> Evaluate integers as \"i32\", \"i64\" or \"big\"
+    --overflow <mode>      Wrap around on integer overflow or fail with an
+                           \"error\"
+
+Options for compile-ast:
+    --output <file>        Write the binary AST to <file> instead of the
+                           input path with a .ast extension, or to stdout
+                           for - or when reading from stdin
+
+Options taking a value also accept it as --<option>=<value>.";
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum Command {
+    Usage,
+    Run(RunArgs),
+    Fmt(FmtArgs),
+    Dot(DotArgs),
+    Debug(DebugArgs),
+    CompileAst(CompileArgs),
+    Dap,
+    Lsp,
+    Explain(String),
+}
+
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct RunArgs {
+    pub path: String,
+    /// Rinha source to run instead of the file at `path`.
+    pub expression: Option<String>,
+    pub trace: Option<trace::Options>,
+    pub profile: Option<profile::Options>,
+    pub coverage: Option<coverage::Options>,
+    pub error_format: report::Format,
+    pub precision: Precision,
+    pub overflow: Overflow,
+}
+
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct FmtArgs {
+    pub paths: Vec<String>,
+    pub check: bool,
+    pub stdout: bool,
+    pub config: format::Config,
+}
+
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct DotArgs {
+    pub path: String,
+    pub options: dot::Options,
+}
+
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct DebugArgs {
+    pub path: String,
+    pub breakpoints: Vec<debug::Breakpoint>,
+    pub precision: Precision,
+    pub overflow: Overflow,
+}
+
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct CompileArgs {
+    pub path: String,
+    pub output: Option<String>,
+}
+
+impl CompileArgs {
+    /// Where the binary AST is written: `fib.rinha.json` and `fib.rinha`
+    /// are both compiled to `fib.rinha.ast` by default, and programs read
+    /// from stdin are written to stdout.
+    pub fn output(&self) -> String {
+        match &self.output {
+            Some(output) => output.clone(),
+            None if self.path == source::STDIO => source::STDIO.into(),
+            None => {
+                let path =
+                    self.path.strip_suffix(".json").unwrap_or(&self.path);
+                format!("{path}.ast")
+            }
+        }
+    }
+}
+
+pub fn parse(args: &[String]) -> Result<Command, String> {
+    // `--option=value` is the same as `--option value`
+    let args = args
+        .iter()
+        .flat_map(|arg| match arg.split_once('=') {
+            Some((option, value)) if option.starts_with("--") => {
+                vec![option.to_string(), value.to_string()]
+            }
+            _ => vec![arg.clone()],
+        })
+        .collect::<Vec<_>>();
+    match args.as_slice() {
+        [] => Ok(Command::Usage),
+        [command, rest @ ..] if command == "fmt" => parse_fmt(rest),
+        [command, rest @ ..] if command == "dot" => parse_dot(rest),
+        [command, rest @ ..] if command == "debug" => parse_debug(rest),
+        [command, rest @ ..] if command == "compile-ast" => {
+            parse_compile_ast(rest)
+        }
+        [command] if command == "dap" => Ok(Command::Dap),
+        [command] if command == "lsp" => Ok(Command::Lsp),
+        [arg] if arg == "-h" || arg == "--help" => Ok(Command::Usage),
+        [arg, code] if arg == "--explain" => Ok(Command::Explain(code.clone())),
+        [arg] if arg == "--explain" => Err("--explain expects a code".into()),
+        _ => parse_run(&args),
+    }
+}
+
+fn parse_run(args: &[String]) -> Result<Command, String> {
+    let mut run = RunArgs::default();
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let trace = &mut run.trace;
+        match arg.as_str() {
+            "--trace" => {
+                trace.get_or_insert_with(Default::default);
+            }
+            "--trace-filter" => {
+                let names =
+                    args.next().ok_or("--trace-filter expects names")?;
+                let names = names.split(',').map(|name| name.to_string());
+                trace
+                    .get_or_insert_with(Default::default)
+                    .filter
+                    .extend(names);
+            }
+            "--trace-depth" => {
+                let depth = number(arg, args.next())?;
+                trace.get_or_insert_with(Default::default).depth = Some(depth);
+            }
+            "--trace-output" => {
+                let file =
+                    args.next().ok_or("--trace-output expects a file")?;
+                trace.get_or_insert_with(Default::default).output =
+                    Some(file.clone());
+            }
+            "--profile" => {
+                run.profile.get_or_insert_with(Default::default);
+            }
+            "--profile-output" => {
+                let file =
+                    args.next().ok_or("--profile-output expects a file")?;
+                run.profile.get_or_insert_with(Default::default).output =
+                    file.clone();
+            }
+            "--coverage" => {
+                run.coverage.get_or_insert_with(Default::default);
+            }
+            "--coverage-output" => {
+                let file =
+                    args.next().ok_or("--coverage-output expects a file")?;
+                run.coverage.get_or_insert_with(Default::default).output =
+                    file.clone();
+            }
+            "--error-format" => {
+                let format =
+                    args.next().ok_or("--error-format expects a format")?;
+                run.error_format = format.parse()?;
+            }
+            "--integers" => {
+                let precision =
+                    args.next().ok_or("--integers expects a precision")?;
+                run.precision = precision.parse()?;
+            }
+            "--overflow" => {
+                let mode = args.next().ok_or("--overflow expects a mode")?;
+                run.overflow = mode.parse()?;
+            }
+            "-e" => {
+                let expression =
+                    args.next().ok_or("-e expects an expression")?;
+                run.expression = Some(expression.clone());
+            }
+            source::STDIO => paths.push(arg.clone()),
+            _ if arg.starts_with('-') => {
+                return Err(format!("unknown option {arg}"))
+            }
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    match (<[String; 1]>::try_from(paths), &run.expression) {
+        (Ok([path]), None) => run.path = path,
+        (Err(paths), Some(_)) if paths.is_empty() => {}
+        (_, Some(_)) => return Err("-e can't be used with a file".into()),
+        (_, None) => return Err("expected exactly one file".into()),
+    }
+
+    Ok(Command::Run(run))
+}
+
+fn parse_fmt(args: &[String]) -> Result<Command, String> {
+    let mut fmt = FmtArgs::default();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => fmt.check = true,
+            "--stdout" => fmt.stdout = true,
+            "--width" => fmt.config.width = number(arg, args.next())?,
+            "--indent" => fmt.config.indent = number(arg, args.next())?,
+            _ if arg.starts_with('-') => {
+                return Err(format!("unknown option {arg}"))
+            }
+            _ => fmt.paths.push(arg.clone()),
+        }
+    }
+
+    if fmt.paths.is_empty() {
+        return Err("fmt expects at least one file".into());
+    }
+
+    Ok(Command::Fmt(fmt))
+}
+
+fn parse_dot(args: &[String]) -> Result<Command, String> {
+    let mut dot = DotArgs::default();
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--depth" => dot.options.depth = Some(number(arg, args.next())?),
+            "--at" => dot.options.at = Some(span(arg, args.next())?),
+            source::STDIO => paths.push(arg.clone()),
+            _ if arg.starts_with('-') => {
+                return Err(format!("unknown option {arg}"))
+            }
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    match <[String; 1]>::try_from(paths) {
+        Ok([path]) => dot.path = path,
+        Err(_) => return Err("dot expects exactly one file".into()),
+    }
+
+    Ok(Command::Dot(dot))
+}
+
+fn parse_debug(args: &[String]) -> Result<Command, String> {
+    let mut debug = DebugArgs::default();
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--break" => {
+                let breakpoint =
+                    args.next().ok_or("--break expects a breakpoint")?;
+                debug.breakpoints.push(breakpoint.parse()?);
+            }
+            "--integers" => {
+                let precision =
+                    args.next().ok_or("--integers expects a precision")?;
+                debug.precision = precision.parse()?;
+            }
+            "--overflow" => {
+                let mode = args.next().ok_or("--overflow expects a mode")?;
+                debug.overflow = mode.parse()?;
+            }
+            _ if arg.starts_with('-') => {
+                return Err(format!("unknown option {arg}"))
+            }
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    match <[String; 1]>::try_from(paths) {
+        Ok([path]) => debug.path = path,
+        Err(_) => return Err("debug expects exactly one file".into()),
+    }
+
+    Ok(Command::Debug(debug))
+}
+
+fn parse_compile_ast(args: &[String]) -> Result<Command, String> {
+    let mut compile = CompileArgs::default();
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => {
+                let file = args.next().ok_or("--output expects a file")?;
+                compile.output = Some(file.clone());
+            }
+            source::STDIO => paths.push(arg.clone()),
+            _ if arg.starts_with('-') => {
+                return Err(format!("unknown option {arg}"))
+            }
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    match <[String; 1]>::try_from(paths) {
+        Ok([path]) => compile.path = path,
+        Err(_) => return Err("compile-ast expects exactly one file".into()),
+    }
+
+    Ok(Command::CompileAst(compile))
+}
+
+fn span(
+    option: &str,
+    value: Option<&String>,
+) -> Result<(usize, usize), String> {
+    let error = || format!("{option} expects <start>[:<end>]");
+    let value = value.ok_or_else(error)?;
+    let (start, end) = value.split_once(':').unwrap_or((value, value));
+    match (start.parse(), end.parse()) {
+        (Ok(start), Ok(end)) if start <= end => Ok((start, end)),
+        _ => Err(error()),
+    }
+}
+
+fn number(option: &str, value: Option<&String>) -> Result<usize, String> {
+    value
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| format!("{option} expects a non-negative number"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_run() {
+        let command = parse(&args(&["fib.json"]));
+        let expected = RunArgs {
+            path: "fib.json".into(),
+            ..Default::default()
+        };
+        assert_eq!(command, Ok(Command::Run(expected)));
+        assert_eq!(parse(&args(&[])), Ok(Command::Usage));
+        assert!(parse(&args(&["a.json", "b.json"])).is_err());
+    }
+
+    #[test]
+    fn parse_stdin_and_expression() {
+        let command = parse(&args(&["--trace", "-"]));
+        let Ok(Command::Run(run)) = command else {
+            panic!("expected a run, got {command:?}");
+        };
+        assert_eq!(run.path, "-");
+
+        let command = parse(&args(&["-e", "print(1 + 2)"]));
+        let expected = RunArgs {
+            expression: Some("print(1 + 2)".into()),
+            ..Default::default()
+        };
+        assert_eq!(command, Ok(Command::Run(expected)));
+        assert!(parse(&args(&["-e", "1", "a.json"])).is_err());
+        assert!(parse(&args(&["-e"])).is_err());
+
+        let command = parse(&args(&["compile-ast", "-"]));
+        let Ok(Command::CompileAst(compile)) = command else {
+            panic!("expected compile-ast, got {command:?}");
+        };
+        assert_eq!(compile.output(), "-");
+    }
+
+    #[test]
+    fn parse_error_format() {
+        let command = parse(&args(&["--error-format", "json", "a.json"]));
+        let expected = RunArgs {
+            path: "a.json".into(),
+            error_format: report::Format::Json,
+            ..Default::default()
+        };
+        assert_eq!(command, Ok(Command::Run(expected)));
+        assert!(parse(&args(&["--error-format", "xml", "a.json"])).is_err());
+    }
+
+    #[test]
+    fn parse_integers() {
+        let command = parse(&args(&["--integers", "big", "a.json"]));
+        let expected = RunArgs {
+            path: "a.json".into(),
+            precision: Precision::Big,
+            ..Default::default()
+        };
+        assert_eq!(command, Ok(Command::Run(expected)));
+        let command = parse(&args(&["debug", "--integers", "i64", "a.json"]));
+        let expected = DebugArgs {
+            path: "a.json".into(),
+            precision: Precision::I64,
+            ..Default::default()
+        };
+        assert_eq!(command, Ok(Command::Debug(expected)));
+        assert!(parse(&args(&["--integers", "u8", "a.json"])).is_err());
+    }
+
+    #[test]
+    fn parse_overflow() {
+        let command = parse(&args(&["--overflow=error", "a.json"]));
+        let expected = RunArgs {
+            path: "a.json".into(),
+            overflow: Overflow::Error,
+            ..Default::default()
+        };
+        assert_eq!(command, Ok(Command::Run(expected)));
+        let command = parse(&args(&["--overflow", "error", "a.json"]));
+        let expected = RunArgs {
+            path: "a.json".into(),
+            overflow: Overflow::Error,
+            ..Default::default()
+        };
+        assert_eq!(command, Ok(Command::Run(expected)));
+        assert!(parse(&args(&["--overflow=saturate", "a.json"])).is_err());
+    }
+
+    #[test]
+    fn parse_trace() {
+        let command = parse(&args(&["--trace-filter", "f,g", "a.json"]));
+        let expected = RunArgs {
+            path: "a.json".into(),
+            trace: Some(trace::Options {
+                filter: vec!["f".into(), "g".into()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(command, Ok(Command::Run(expected)));
+        assert!(parse(&args(&["--trace-depth", "x", "a.json"])).is_err());
+    }
+
+    #[test]
+    fn parse_profile() {
+        let command = parse(&args(&["--profile-output", "f.txt", "a.json"]));
+        let expected = RunArgs {
+            path: "a.json".into(),
+            profile: Some(profile::Options {
+                output: "f.txt".into(),
+            }),
+            ..Default::default()
+        };
+        assert_eq!(command, Ok(Command::Run(expected)));
+    }
+
+    #[test]
+    fn parse_coverage() {
+        let command = parse(&args(&["--coverage", "a.json"]));
+        let expected = RunArgs {
+            path: "a.json".into(),
+            coverage: Some(coverage::Options {
+                output: "lcov.info".into(),
+            }),
+            ..Default::default()
+        };
+        assert_eq!(command, Ok(Command::Run(expected)));
+        assert!(parse(&args(&["a.json", "--coverage-output"])).is_err());
+    }
+
+    #[test]
+    fn parse_fmt() {
+        let command = parse(&args(&["fmt", "--width", "100", "a.rinha"]));
+        let expected = FmtArgs {
+            paths: vec!["a.rinha".into()],
+            config: format::Config {
+                width: 100,
+                indent: 2,
+            },
+            ..Default::default()
+        };
+        assert_eq!(command, Ok(Command::Fmt(expected)));
+        assert!(parse(&args(&["fmt", "--indent"])).is_err());
+        assert!(parse(&args(&["fmt"])).is_err());
+    }
+
+    #[test]
+    fn parse_dot() {
+        let command = parse(&args(&["dot", "--at", "10:20", "a.json"]));
+        let expected = DotArgs {
+            path: "a.json".into(),
+            options: dot::Options {
+                depth: None,
+                at: Some((10, 20)),
+            },
+        };
+        assert_eq!(command, Ok(Command::Dot(expected)));
+        assert!(parse(&args(&["dot", "--at", "20:10", "a.json"])).is_err());
+        assert!(parse(&args(&["dot", "a.json", "b.json"])).is_err());
+    }
+
+    #[test]
+    fn parse_compile_ast() {
+        let command = parse(&args(&["compile-ast", "fib.rinha.json"]));
+        let Ok(Command::CompileAst(compile)) = command else {
+            panic!("expected compile-ast, got {command:?}");
+        };
+        assert_eq!(compile.path, "fib.rinha.json");
+        assert_eq!(compile.output(), "fib.rinha.ast");
+
+        let command =
+            parse(&args(&["compile-ast", "--output=fib.bin", "fib.rinha"]));
+        let expected = CompileArgs {
+            path: "fib.rinha".into(),
+            output: Some("fib.bin".into()),
+        };
+        assert_eq!(command, Ok(Command::CompileAst(expected)));
+        assert!(parse(&args(&["compile-ast"])).is_err());
+        assert!(parse(&args(&["compile-ast", "a", "b"])).is_err());
+    }
+
+    #[test]
+    fn parse_debug() {
+        let command =
+            parse(&args(&["debug", "--break", "fib", "--break", "3", "a"]));
+        let expected = DebugArgs {
+            path: "a".into(),
+            breakpoints: vec![
+                debug::Breakpoint::Function("fib".into()),
+                debug::Breakpoint::Line {
+                    file: None,
+                    line: 3,
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(command, Ok(Command::Debug(expected)));
+        assert!(parse(&args(&["debug", "--break", "a:b", "a"])).is_err());
+        assert_eq!(parse(&args(&["dap"])), Ok(Command::Dap));
+        assert_eq!(parse(&args(&["lsp"])), Ok(Command::Lsp));
+    }
+
+    #[test]
+    fn parse_explain() {
+        let command = parse(&args(&["--explain", "R0005"]));
+        assert_eq!(command, Ok(Command::Explain("R0005".into())));
+        assert!(parse(&args(&["--explain"])).is_err());
+    }
+}
@@ -0,0 +1,384 @@
+// Code coverage, recording which terms were evaluated and reporting them
+// per line, function and `if` branch.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::env::Env;
+use crate::eval::Observer;
+use crate::source::LineIndex;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Options {
+    /// Where to write the LCOV report.
+    pub output: String,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            output: "lcov.info".into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct FunctionInfo {
+    name: String,
+    location: Location,
+    body: Location,
+}
+
+#[derive(Clone, Debug)]
+struct BranchInfo {
+    location: Location,
+    then: Location,
+    otherwise: Location,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Coverage {
+    hits: HashMap<Location, u64>,
+    terms: Vec<Location>,
+    functions: Vec<FunctionInfo>,
+    branches: Vec<BranchInfo>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Totals {
+    pub lines: (usize, usize),
+    pub functions: (usize, usize),
+    pub branches: (usize, usize),
+}
+
+impl Coverage {
+    /// Prepares the coverage of every term in `file`.
+    pub fn new(file: &File) -> Self {
+        let mut coverage = Coverage::default();
+        coverage.collect(&file.expression);
+        coverage
+    }
+
+    /// Registers `root` and its subterms, looping over an explicit stack
+    /// as programs can be nested deeper than the native stack allows.
+    fn collect(&mut self, root: &Term) {
+        let mut pending = vec![(root, None)];
+        while let Some((term, name)) = pending.pop() {
+            let location = term.location();
+            self.hits.insert(location.clone(), 0);
+            self.terms.push(location.clone());
+
+            match term {
+                Term::Let(term) => {
+                    pending.push((&term.next, None));
+                    pending.push((&term.value, Some(term.name.text.as_str())));
+                    continue;
+                }
+                Term::Function(term) => self.functions.push(FunctionInfo {
+                    name: name.unwrap_or("<anonymous>").into(),
+                    location: location.clone(),
+                    body: term.value.location().clone(),
+                }),
+                Term::If(term) => self.branches.push(BranchInfo {
+                    location: location.clone(),
+                    then: term.then.location().clone(),
+                    otherwise: term.otherwise.location().clone(),
+                }),
+                _ => {}
+            }
+
+            for child in term.children().into_iter().rev() {
+                pending.push((child, None));
+            }
+        }
+    }
+
+    fn hits(&self, location: &Location) -> u64 {
+        self.hits.get(location).copied().unwrap_or_default()
+    }
+
+    /// The files with covered terms, in order of appearance.
+    pub fn files(&self) -> Vec<&str> {
+        let mut seen = HashSet::new();
+        self.terms
+            .iter()
+            .map(|location| location.filename.as_str())
+            .filter(|filename| seen.insert(*filename))
+            .collect()
+    }
+
+    fn lines(&self, filename: &str, index: &LineIndex) -> BTreeMap<usize, u64> {
+        let mut lines = BTreeMap::new();
+        for location in &self.terms {
+            if location.filename == filename {
+                let line = index.line(location.start);
+                let hits = lines.entry(line).or_default();
+                *hits = self.hits(location).max(*hits);
+            }
+        }
+        lines
+    }
+
+    pub fn totals(&self, sources: &HashMap<String, LineIndex>) -> Totals {
+        let mut totals = Totals::default();
+        for (filename, index) in sources {
+            let lines = self.lines(filename, index);
+            totals.lines.0 += lines.values().filter(|&&hits| hits > 0).count();
+            totals.lines.1 += lines.len();
+        }
+        for function in &self.functions {
+            if sources.contains_key(&function.location.filename) {
+                totals.functions.0 += (self.hits(&function.body) > 0) as usize;
+                totals.functions.1 += 1;
+            }
+        }
+        for branch in &self.branches {
+            if sources.contains_key(&branch.location.filename) {
+                totals.branches.0 += (self.hits(&branch.then) > 0) as usize;
+                totals.branches.0 +=
+                    (self.hits(&branch.otherwise) > 0) as usize;
+                totals.branches.1 += 2;
+            }
+        }
+        totals
+    }
+
+    /// An LCOV tracefile for every file in `sources`.
+    pub fn lcov(&self, sources: &HashMap<String, LineIndex>) -> String {
+        let mut lcov = String::new();
+
+        for filename in self.files() {
+            let Some(index) = sources.get(filename) else {
+                continue;
+            };
+
+            writeln!(lcov, "TN:\nSF:{filename}").unwrap();
+
+            let functions = self
+                .functions
+                .iter()
+                .filter(|function| function.location.filename == filename)
+                .collect::<Vec<_>>();
+            let mut names = HashMap::<&str, usize>::new();
+            for function in &functions {
+                *names.entry(&function.name).or_default() += 1;
+            }
+            let mut hit = 0;
+            for function in &functions {
+                let line = index.line(function.location.start);
+                let name = match names[function.name.as_str()] {
+                    1 => function.name.clone(),
+                    _ => format!("{}@{}", function.name, line),
+                };
+                let hits = self.hits(&function.body);
+                hit += (hits > 0) as usize;
+                writeln!(lcov, "FN:{line},{name}\nFNDA:{hits},{name}").unwrap();
+            }
+            writeln!(lcov, "FNF:{}\nFNH:{}", functions.len(), hit).unwrap();
+
+            let branches = self
+                .branches
+                .iter()
+                .filter(|branch| branch.location.filename == filename)
+                .collect::<Vec<_>>();
+            let mut hit = 0;
+            for (block, branch) in branches.iter().enumerate() {
+                let line = index.line(branch.location.start);
+                let evaluated = self.hits(&branch.location) > 0;
+                for (i, location) in
+                    [&branch.then, &branch.otherwise].into_iter().enumerate()
+                {
+                    let hits = self.hits(location);
+                    hit += (hits > 0) as usize;
+                    let taken = match evaluated {
+                        true => hits.to_string(),
+                        false => "-".into(),
+                    };
+                    writeln!(lcov, "BRDA:{line},{block},{i},{taken}").unwrap();
+                }
+            }
+            writeln!(lcov, "BRF:{}\nBRH:{}", branches.len() * 2, hit).unwrap();
+
+            let lines = self.lines(filename, index);
+            for (line, hits) in &lines {
+                writeln!(lcov, "DA:{line},{hits}").unwrap();
+            }
+            let hit = lines.values().filter(|&&hits| hits > 0).count();
+            writeln!(lcov, "LF:{}\nLH:{}", lines.len(), hit).unwrap();
+            writeln!(lcov, "end_of_record").unwrap();
+        }
+
+        lcov
+    }
+
+    /// Totals followed by every function and branch that was never run.
+    pub fn summary(&self, sources: &HashMap<String, LineIndex>) -> String {
+        let Totals {
+            lines,
+            functions,
+            branches,
+        } = self.totals(sources);
+        let ratio = |(hit, total): (usize, usize)| match total {
+            0 => format!("{hit}/{total}"),
+            _ => {
+                let percent = hit as f64 * 100.0 / total as f64;
+                format!("{hit}/{total} ({percent:.1}%)")
+            }
+        };
+
+        let mut summary = String::new();
+        writeln!(
+            summary,
+            "coverage: lines {}, functions {}, branches {}",
+            ratio(lines),
+            ratio(functions),
+            ratio(branches),
+        )
+        .unwrap();
+
+        for function in &self.functions {
+            let filename = &function.location.filename;
+            let Some(index) = sources.get(filename) else {
+                continue;
+            };
+            if self.hits(&function.body) == 0 {
+                let line = index.line(function.location.start);
+                let name = &function.name;
+                writeln!(
+                    summary,
+                    "uncovered: {filename}:{line}: function \"{name}\" was \
+                     never called",
+                )
+                .unwrap();
+            }
+        }
+
+        for branch in &self.branches {
+            let filename = &branch.location.filename;
+            let Some(index) = sources.get(filename) else {
+                continue;
+            };
+            let line = index.line(branch.location.start);
+            if self.hits(&branch.location) == 0 {
+                writeln!(
+                    summary,
+                    "uncovered: {filename}:{line}: \"if\" was never evaluated",
+                )
+                .unwrap();
+                continue;
+            }
+            for (name, location) in
+                [("then", &branch.then), ("else", &branch.otherwise)]
+            {
+                if self.hits(location) == 0 {
+                    writeln!(
+                        summary,
+                        "uncovered: {filename}:{line}: \"{name}\" branch was \
+                         never taken",
+                    )
+                    .unwrap();
+                }
+            }
+        }
+
+        summary
+    }
+}
+
+impl Observer for Coverage {
+    fn visit(
+        &mut self,
+        _env: &Rc<RefCell<Env>>,
+        term: &Term,
+    ) -> Result<(), Error> {
+        if let Some(hits) = self.hits.get_mut(term.location()) {
+            *hits += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::Evaluator;
+    use crate::parser;
+
+    const SOURCE: &str = "\
+let abs = fn (n) => {
+  if (n < 0) {
+    0 - n
+  } else {
+    n
+  }
+};
+let unused = fn () => {
+  if (true) {
+    1
+  } else {
+    2
+  }
+};
+print(abs(5))
+";
+
+    fn coverage() -> (Coverage, HashMap<String, LineIndex>) {
+        let file = parser::parse(SOURCE, "abs.rinha").unwrap();
+        let coverage = Rc::new(RefCell::new(Coverage::new(&file)));
+        let mut evaluator = Evaluator::new();
+        evaluator.observe(coverage.clone());
+        evaluator.eval(&mut Default::default(), file.expression);
+        let coverage = coverage.borrow().clone();
+        let sources = [("abs.rinha".into(), LineIndex::new(SOURCE))];
+        (coverage, sources.into_iter().collect())
+    }
+
+    #[test]
+    fn coverage_lcov() {
+        let (coverage, sources) = coverage();
+        let expected = "\
+TN:
+SF:abs.rinha
+FN:1,abs
+FNDA:1,abs
+FN:8,unused
+FNDA:0,unused
+FNF:2
+FNH:1
+BRDA:2,0,0,0
+BRDA:2,0,1,1
+BRDA:9,1,0,-
+BRDA:9,1,1,-
+BRF:4
+BRH:1
+DA:1,1
+DA:2,1
+DA:3,0
+DA:5,1
+DA:8,1
+DA:9,0
+DA:10,0
+DA:12,0
+DA:15,1
+LF:9
+LH:5
+end_of_record
+";
+        assert_eq!(coverage.lcov(&sources), expected);
+    }
+
+    #[test]
+    fn coverage_summary() {
+        let (coverage, sources) = coverage();
+        let expected = "\
+coverage: lines 5/9 (55.6%), functions 1/2 (50.0%), branches 1/4 (25.0%)
+uncovered: abs.rinha:8: function \"unused\" was never called
+uncovered: abs.rinha:2: \"then\" branch was never taken
+uncovered: abs.rinha:9: \"if\" was never evaluated
+";
+        assert_eq!(coverage.summary(&sources), expected);
+    }
+}
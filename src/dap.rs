@@ -0,0 +1,887 @@
+// Debug Adapter Protocol server, driving the step debugger from an editor
+// through `Content-Length` framed JSON messages.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use serde_json::{json, Value};
+
+use crate::ast::*;
+use crate::debug::{
+    self, Breakpoint, Debugger, Frontend, Reason, Session, Step,
+};
+use crate::env::Env;
+use crate::eval::{inspect, Evaluator};
+use crate::integer::{Overflow, Precision};
+use crate::{check, prelude, rpc, source};
+
+/// The only thread, as rinha programs are single threaded.
+const THREAD: u64 = 1;
+
+pub struct Connection<R, W> {
+    input: R,
+    output: W,
+    seq: u64,
+}
+
+impl<R: BufRead, W: Write> Connection<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self {
+            input,
+            output,
+            seq: 0,
+        }
+    }
+
+    /// Reads the next message, or `None` at the end of the input. Messages
+    /// that aren't JSON can't be answered, so they are skipped.
+    pub fn read(&mut self) -> io::Result<Option<Value>> {
+        loop {
+            match rpc::read(&mut self.input)? {
+                Some(Ok(message)) => return Ok(Some(message)),
+                Some(Err(_)) => continue,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    pub fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        rpc::write(&mut self.output, &message)
+    }
+
+    pub fn respond(&mut self, request: &Value, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "success": true,
+            "command": request["command"],
+            "body": body,
+        }))
+    }
+
+    pub fn fail(&mut self, request: &Value, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "success": false,
+            "command": request["command"],
+            "message": message,
+        }))
+    }
+
+    pub fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "event",
+            "event": event,
+            "body": body,
+        }))
+    }
+}
+
+type Shared<R, W> = Rc<RefCell<Connection<R, W>>>;
+
+/// Forwards the output of `print` as `output` events, a line at a time.
+struct Output<R: BufRead, W: Write> {
+    connection: Shared<R, W>,
+    buffer: Vec<u8>,
+}
+
+impl<R: BufRead, W: Write> Output<R, W> {
+    fn new(connection: Shared<R, W>) -> Self {
+        Self {
+            connection,
+            buffer: Vec::new(),
+        }
+    }
+
+    fn send(&mut self, end: usize) -> io::Result<()> {
+        let text = self.buffer.drain(..end).collect::<Vec<_>>();
+        let body = json!({
+            "category": "stdout",
+            "output": String::from_utf8_lossy(&text),
+        });
+        self.connection.borrow_mut().event("output", body)
+    }
+}
+
+impl<R: BufRead, W: Write> Write for Output<R, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        while let Some(i) = self.buffer.iter().position(|&b| b == b'\n') {
+            self.send(i + 1)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.buffer.is_empty() {
+            true => Ok(()),
+            false => self.send(self.buffer.len()),
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> Drop for Output<R, W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// A value that can be expanded by a `variables` request.
+enum Reference {
+    Scope(Rc<RefCell<Env>>),
+    Tuple(Term),
+}
+
+struct Server<R, W> {
+    connection: Shared<R, W>,
+    program: Option<(String, File)>,
+    /// Where the source of each file named by a `Location` was found.
+    paths: HashMap<String, PathBuf>,
+    sources: HashMap<String, String>,
+    stop_on_entry: bool,
+    precision: Precision,
+    overflow: Overflow,
+    lines: BTreeMap<String, Vec<usize>>,
+    functions: Vec<String>,
+    /// Values expandable while paused, referenced by their index plus one.
+    references: Vec<Reference>,
+    disconnected: bool,
+}
+
+/// Serves a single debug session, returning once the client disconnects.
+pub fn serve<R, W>(input: R, output: W) -> io::Result<()>
+where
+    R: BufRead + 'static,
+    W: Write + 'static,
+{
+    let mut server = Server {
+        connection: Rc::new(RefCell::new(Connection::new(input, output))),
+        program: None,
+        paths: HashMap::new(),
+        sources: HashMap::new(),
+        stop_on_entry: false,
+        precision: Precision::default(),
+        overflow: Overflow::default(),
+        lines: BTreeMap::new(),
+        functions: Vec::new(),
+        references: Vec::new(),
+        disconnected: false,
+    };
+
+    let mut configured = false;
+    while !configured || server.program.is_none() {
+        let Some(request) = server.read()? else {
+            return Ok(());
+        };
+        match request["command"].as_str().unwrap_or_default() {
+            "configurationDone" => {
+                server.respond(&request, json!({}))?;
+                configured = true;
+            }
+            "disconnect" => return server.respond(&request, json!({})),
+            _ => server.handle(&request)?,
+        }
+    }
+
+    server.run()
+}
+
+impl<R, W> Server<R, W>
+where
+    R: BufRead + 'static,
+    W: Write + 'static,
+{
+    fn read(&self) -> io::Result<Option<Value>> {
+        self.connection.borrow_mut().read()
+    }
+
+    fn respond(&self, request: &Value, body: Value) -> io::Result<()> {
+        self.connection.borrow_mut().respond(request, body)
+    }
+
+    fn fail(&self, request: &Value, message: &str) -> io::Result<()> {
+        self.connection.borrow_mut().fail(request, message)
+    }
+
+    fn event(&self, event: &str, body: Value) -> io::Result<()> {
+        self.connection.borrow_mut().event(event, body)
+    }
+
+    fn breakpoints(&self) -> Vec<Breakpoint> {
+        let lines = self.lines.iter().flat_map(|(file, lines)| {
+            lines.iter().map(|&line| Breakpoint::Line {
+                file: Some(file.clone()),
+                line,
+            })
+        });
+        let functions =
+            self.functions.iter().cloned().map(Breakpoint::Function);
+        lines.chain(functions).collect()
+    }
+
+    /// The name used by `Location` for the file at `path`.
+    fn filename(&self, path: &str) -> String {
+        let canonical = Path::new(path).canonicalize().ok();
+        self.paths
+            .iter()
+            .find(|(filename, found)| {
+                *filename == path
+                    || canonical.is_some()
+                        && found.canonicalize().ok() == canonical
+            })
+            .map(|(filename, _)| filename.clone())
+            .unwrap_or_else(|| path.into())
+    }
+
+    /// Handles the requests that are valid whether or not the program is
+    /// running.
+    fn handle(&mut self, request: &Value) -> io::Result<()> {
+        let arguments = &request["arguments"];
+        match request["command"].as_str().unwrap_or_default() {
+            "initialize" => {
+                let capabilities = json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsFunctionBreakpoints": true,
+                    "supportsEvaluateForHovers": true,
+                    "supportsTerminateRequest": true,
+                });
+                self.respond(request, capabilities)?;
+                self.event("initialized", json!({}))
+            }
+            "launch" => {
+                let Some(program) = arguments["program"].as_str() else {
+                    return self.fail(request, "launch expects a program");
+                };
+                let precision = match arguments.get("integers") {
+                    Some(value) => value.as_str().unwrap_or_default().parse(),
+                    None => Ok(Precision::default()),
+                };
+                let precision = match precision {
+                    Ok(precision) => precision,
+                    Err(message) => return self.fail(request, &message),
+                };
+                let overflow = match arguments.get("overflow") {
+                    Some(value) => value.as_str().unwrap_or_default().parse(),
+                    None => Ok(Overflow::default()),
+                };
+                let overflow = match overflow {
+                    Ok(overflow) => overflow,
+                    Err(message) => return self.fail(request, &message),
+                };
+                let loaded = source::load(program).and_then(|file| {
+                    check::parse_errors(&file)?;
+                    Ok(file)
+                });
+                let file = match loaded {
+                    Ok(file) => file,
+                    Err(error) => {
+                        return self.fail(request, &error.to_string())
+                    }
+                };
+                let filename = file.expression.location().filename.clone();
+                if let Ok((path, text)) = source::read(program, &filename) {
+                    self.paths.insert(filename.clone(), path);
+                    self.sources.insert(filename, text);
+                }
+                self.stop_on_entry =
+                    arguments["stopOnEntry"].as_bool().unwrap_or_default();
+                self.precision = precision;
+                self.overflow = overflow;
+                self.program = Some((program.into(), file));
+                self.respond(request, json!({}))
+            }
+            "setBreakpoints" => {
+                let path = arguments["source"]["path"].as_str();
+                let filename = self.filename(path.unwrap_or_default());
+                let lines = match arguments["breakpoints"].as_array() {
+                    Some(breakpoints) => breakpoints
+                        .iter()
+                        .filter_map(|breakpoint| breakpoint["line"].as_u64())
+                        .map(|line| line as usize)
+                        .collect(),
+                    None => Vec::new(),
+                };
+                let count = self
+                    .sources
+                    .get(&filename)
+                    .map(|text| text.lines().count());
+                let breakpoints = lines
+                    .iter()
+                    .map(|&line| {
+                        let verified = count.is_some_and(|count| line <= count);
+                        json!({ "verified": verified, "line": line })
+                    })
+                    .collect::<Vec<_>>();
+                self.lines.insert(filename, lines);
+                self.respond(request, json!({ "breakpoints": breakpoints }))
+            }
+            "setFunctionBreakpoints" => {
+                let names = match arguments["breakpoints"].as_array() {
+                    Some(breakpoints) => breakpoints
+                        .iter()
+                        .filter_map(|breakpoint| breakpoint["name"].as_str())
+                        .map(|name| name.to_string())
+                        .collect(),
+                    None => Vec::new(),
+                };
+                let breakpoints = names
+                    .iter()
+                    .map(|_| json!({ "verified": true }))
+                    .collect::<Vec<_>>();
+                self.functions = names;
+                self.respond(request, json!({ "breakpoints": breakpoints }))
+            }
+            "setExceptionBreakpoints" => {
+                self.respond(request, json!({ "breakpoints": [] }))
+            }
+            "threads" => {
+                let threads = [json!({ "id": THREAD, "name": "main" })];
+                self.respond(request, json!({ "threads": threads }))
+            }
+            command => {
+                let message = format!("unsupported request {command}");
+                self.fail(request, &message)
+            }
+        }
+    }
+
+    fn run(self) -> io::Result<()> {
+        let mut server = self;
+        let (_, file) = server.program.take().unwrap();
+        let connection = server.connection.clone();
+        let sources = std::mem::take(&mut server.sources);
+        let breakpoints = server.breakpoints();
+        let (precision, overflow) = (server.precision, server.overflow);
+
+        let debugger = Rc::new(RefCell::new(Debugger::new(server)));
+        {
+            let mut debugger = debugger.borrow_mut();
+            for (filename, text) in &sources {
+                debugger.source(filename, text);
+            }
+            debugger.state_mut().breakpoints = breakpoints;
+        }
+
+        let mut evaluator = Evaluator::new();
+        evaluator.precision(precision);
+        evaluator.overflow(overflow);
+        evaluator.observe(debugger.clone());
+        evaluator.output(Output::new(connection.clone()));
+        let result = evaluator.eval(&mut prelude::env(), file.expression);
+        drop(evaluator);
+
+        let mut debugger = debugger.borrow_mut();
+        let quit = debugger.state().quit();
+        let server = debugger.frontend_mut();
+
+        if !quit {
+            let code = match &result {
+                Term::Error(error) => {
+                    let output = json!({
+                        "category": "stderr",
+                        "output": error.to_string(),
+                    });
+                    server.event("output", output)?;
+                    1
+                }
+                _ => 0,
+            };
+            server.event("exited", json!({ "exitCode": code }))?;
+        }
+
+        if server.disconnected {
+            return Ok(());
+        }
+        server.event("terminated", json!({}))?;
+
+        while let Some(request) = server.read()? {
+            match request["command"].as_str().unwrap_or_default() {
+                "disconnect" => return server.respond(&request, json!({})),
+                _ => server.handle(&request)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn reference(&mut self, reference: Reference) -> usize {
+        self.references.push(reference);
+        self.references.len()
+    }
+
+    fn variable(&mut self, name: &str, term: &Term) -> Value {
+        let kind = match term {
+            Term::Int(_) => "Int",
+            Term::Str(_) => "Str",
+            Term::Bool(_) => "Bool",
+            Term::Function(_) => "Function",
+            Term::Tuple(_) => "Tuple",
+            _ => "Term",
+        };
+        let reference = match term {
+            Term::Tuple(_) => self.reference(Reference::Tuple(term.clone())),
+            _ => 0,
+        };
+        json!({
+            "name": name,
+            "value": inspect(term),
+            "type": kind,
+            "variablesReference": reference,
+        })
+    }
+
+    fn stack_trace(
+        &self,
+        session: &Session,
+        request: &Value,
+    ) -> io::Result<()> {
+        let state = &session.state;
+        let frames = state
+            .stack
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(id, frame)| {
+                let position = frame.location.as_ref().and_then(|location| {
+                    Some((location, state.position(location)?))
+                });
+                let mut value = json!({
+                    "id": id,
+                    "name": frame.name,
+                    "line": 0,
+                    "column": 0,
+                });
+                if let Some((location, (line, column))) = position {
+                    let filename = &location.filename;
+                    let path = self
+                        .paths
+                        .get(filename)
+                        .and_then(|path| path.canonicalize().ok())
+                        .unwrap_or_else(|| filename.into());
+                    let name = Path::new(filename)
+                        .file_name()
+                        .map(|name| name.to_string_lossy().to_string())
+                        .unwrap_or_else(|| filename.clone());
+                    value["line"] = json!(line);
+                    value["column"] = json!(column);
+                    value["source"] = json!({
+                        "name": name,
+                        "path": path,
+                    });
+                }
+                value
+            })
+            .collect::<Vec<_>>();
+        let total = frames.len();
+        let body = json!({ "stackFrames": frames, "totalFrames": total });
+        self.respond(request, body)
+    }
+
+    fn scopes(&mut self, session: &Session, request: &Value) -> io::Result<()> {
+        let Some(env) = frame(session, &request["arguments"]["frameId"])
+            .and_then(|frame| frame.env.clone())
+        else {
+            return self.fail(request, "unknown frame");
+        };
+
+        let mut envs = Vec::new();
+        let mut env = Some(env);
+        while let Some(scope) = env {
+            env = scope.borrow().parent();
+            if !prelude::is_prelude(&scope.borrow()) {
+                envs.push(scope);
+            }
+        }
+
+        let globals = envs.len().saturating_sub(1);
+        let mut scopes = Vec::new();
+        for (i, env) in envs.into_iter().enumerate() {
+            let name = match i {
+                0 => "Locals".to_string(),
+                _ if i == globals => "Globals".to_string(),
+                _ => format!("Scope {i}"),
+            };
+            let reference = self.reference(Reference::Scope(env));
+            scopes.push(json!({
+                "name": name,
+                "variablesReference": reference,
+                "expensive": false,
+            }));
+        }
+        self.respond(request, json!({ "scopes": scopes }))
+    }
+
+    fn variables(&mut self, request: &Value) -> io::Result<()> {
+        let reference = request["arguments"]["variablesReference"].as_u64();
+        let bindings = match reference
+            .and_then(|reference| (reference as usize).checked_sub(1))
+            .and_then(|i| self.references.get(i))
+        {
+            Some(Reference::Scope(env)) => env
+                .borrow()
+                .bindings()
+                .into_iter()
+                .map(|(name, term)| (name.to_string(), term.clone()))
+                .collect(),
+            Some(Reference::Tuple(Term::Tuple(tuple))) => vec![
+                ("first".into(), (*tuple.first).clone()),
+                ("second".into(), (*tuple.second).clone()),
+            ],
+            _ => return self.fail(request, "unknown variables reference"),
+        };
+        let variables = bindings
+            .iter()
+            .map(|(name, term)| self.variable(name, term))
+            .collect::<Vec<_>>();
+        self.respond(request, json!({ "variables": variables }))
+    }
+
+    fn evaluate(
+        &mut self,
+        session: &Session,
+        request: &Value,
+    ) -> io::Result<()> {
+        let arguments = &request["arguments"];
+        let frame = match arguments.get("frameId") {
+            Some(id) => frame(session, id),
+            None => session.state.stack.last(),
+        };
+        let (Some(env), Some(location)) = (
+            frame.and_then(|frame| frame.env.as_ref()),
+            frame.and_then(|frame| frame.location.as_ref()),
+        ) else {
+            return self.fail(request, "unknown frame");
+        };
+        let expression = arguments["expression"].as_str().unwrap_or_default();
+
+        let mut evaluator = Evaluator::new();
+        evaluator.precision(self.precision);
+        evaluator.overflow(self.overflow);
+        evaluator.output(Output::new(self.connection.clone()));
+        let result = debug::evaluate(
+            &mut evaluator,
+            env,
+            &location.filename,
+            expression,
+        );
+        drop(evaluator);
+
+        match &result {
+            Term::Error(error) => {
+                self.fail(request, error.to_string().trim_end())
+            }
+            term => {
+                let variable = self.variable("", term);
+                let body = json!({
+                    "result": variable["value"],
+                    "type": variable["type"],
+                    "variablesReference": variable["variablesReference"],
+                });
+                self.respond(request, body)
+            }
+        }
+    }
+
+    /// Handles a request while paused, returning how to resume if it
+    /// resumes the program.
+    fn paused(
+        &mut self,
+        session: &mut Session,
+        request: &Value,
+    ) -> io::Result<Option<Step>> {
+        let step = match request["command"].as_str().unwrap_or_default() {
+            "continue" => Step::Continue,
+            "next" => Step::Over,
+            "stepIn" => Step::Into,
+            "stepOut" => Step::Out,
+            "disconnect" => {
+                self.disconnected = true;
+                Step::Quit
+            }
+            "terminate" => Step::Quit,
+            "stackTrace" => {
+                self.stack_trace(session, request)?;
+                return Ok(None);
+            }
+            "scopes" => {
+                self.scopes(session, request)?;
+                return Ok(None);
+            }
+            "variables" => {
+                self.variables(request)?;
+                return Ok(None);
+            }
+            "evaluate" => {
+                self.evaluate(session, request)?;
+                return Ok(None);
+            }
+            _ => {
+                self.handle(request)?;
+                session.state.breakpoints = self.breakpoints();
+                return Ok(None);
+            }
+        };
+
+        let body = match step {
+            Step::Continue => json!({ "allThreadsContinued": true }),
+            _ => json!({}),
+        };
+        self.respond(request, body)?;
+        Ok(Some(step))
+    }
+}
+
+/// The stack frame with the given DAP id, where 0 is the innermost frame.
+fn frame<'a>(
+    session: &'a Session,
+    id: &Value,
+) -> Option<&'a debug::StackFrame> {
+    let id = id.as_u64()? as usize;
+    session.state.stack.iter().rev().nth(id)
+}
+
+impl<R, W> Frontend for Server<R, W>
+where
+    R: BufRead + 'static,
+    W: Write + 'static,
+{
+    fn pause(&mut self, mut session: Session) -> Step {
+        if session.reason == Reason::Entry && !self.stop_on_entry {
+            return Step::Continue;
+        }
+
+        let reason = match session.reason {
+            Reason::Entry => "entry",
+            Reason::Step => "step",
+            Reason::Breakpoint(index) => match session.state.breakpoints[index]
+            {
+                Breakpoint::Function(_) => "function breakpoint",
+                Breakpoint::Line { .. } => "breakpoint",
+            },
+        };
+        self.references.clear();
+        let body = json!({
+            "reason": reason,
+            "threadId": THREAD,
+            "allThreadsStopped": true,
+        });
+        if self.event("stopped", body).is_err() {
+            return Step::Quit;
+        }
+
+        loop {
+            let request = match self.read() {
+                Ok(Some(request)) => request,
+                _ => return Step::Quit,
+            };
+            match self.paused(&mut session, &request) {
+                Ok(Some(step)) => return step,
+                Ok(None) => {}
+                Err(_) => return Step::Quit,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const SOURCE: &str = "\
+let sum = fn (n) => {
+  if (n < 1) {
+    0
+  } else {
+    n + sum(n - 1)
+  }
+};
+print(sum(2))
+";
+
+    /// Runs a session with the given requests, returning every message
+    /// sent by the server.
+    fn session(requests: &[(&str, Value)]) -> Vec<Value> {
+        let mut input = Vec::new();
+        for (seq, (command, arguments)) in requests.iter().enumerate() {
+            let request = json!({
+                "seq": seq + 1,
+                "type": "request",
+                "command": command,
+                "arguments": arguments,
+            });
+            let body = request.to_string();
+            write!(input, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+                .unwrap();
+        }
+
+        let output = Buffer::default();
+        serve(io::Cursor::new(input), output.clone()).unwrap();
+
+        let output = output.0.borrow().clone();
+        let mut connection = Connection::new(&output[..], io::sink());
+        std::iter::from_fn(|| connection.read().unwrap()).collect()
+    }
+
+    fn kinds(messages: &[Value]) -> Vec<String> {
+        messages
+            .iter()
+            .map(|message| match message["type"].as_str().unwrap() {
+                "event" => format!("event {}", message["event"]),
+                _ => format!("response {}", message["command"]),
+            })
+            .map(|kind| kind.replace('"', ""))
+            .collect()
+    }
+
+    #[test]
+    fn dap_breakpoint_session() {
+        let path = std::env::temp_dir()
+            .join(format!("dap-{}.rinha", std::process::id()));
+        std::fs::write(&path, SOURCE).unwrap();
+        let program = path.to_str().unwrap();
+
+        let messages = session(&[
+            ("initialize", json!({ "adapterID": "rinha" })),
+            ("launch", json!({ "program": program })),
+            (
+                "setBreakpoints",
+                json!({
+                    "source": { "path": program },
+                    "breakpoints": [{ "line": 3 }],
+                }),
+            ),
+            ("configurationDone", json!({})),
+            ("stackTrace", json!({ "threadId": 1 })),
+            ("scopes", json!({ "frameId": 0 })),
+            ("variables", json!({ "variablesReference": 1 })),
+            ("evaluate", json!({ "expression": "n + 10", "frameId": 1 })),
+            (
+                "setBreakpoints",
+                json!({ "source": { "path": program }, "breakpoints": [] }),
+            ),
+            ("continue", json!({ "threadId": 1 })),
+            ("disconnect", json!({})),
+        ]);
+        std::fs::remove_file(&path).unwrap();
+
+        let expected = [
+            "response initialize",
+            "event initialized",
+            "response launch",
+            "response setBreakpoints",
+            "response configurationDone",
+            "event stopped",
+            "response stackTrace",
+            "response scopes",
+            "response variables",
+            "response evaluate",
+            "response setBreakpoints",
+            "response continue",
+            "event output",
+            "event exited",
+            "event terminated",
+            "response disconnect",
+        ];
+        assert_eq!(kinds(&messages), expected);
+        assert!(messages.iter().all(|message| message["success"] != false));
+
+        assert_eq!(messages[3]["body"]["breakpoints"][0]["verified"], true);
+        assert_eq!(messages[5]["body"]["reason"], "breakpoint");
+
+        let frames = messages[6]["body"]["stackFrames"].as_array().unwrap();
+        let frames = frames
+            .iter()
+            .map(|frame| (frame["name"].clone(), frame["line"].clone()))
+            .collect::<Vec<_>>();
+        let expected = [
+            (json!("sum"), json!(3)),
+            (json!("sum"), json!(5)),
+            (json!("sum"), json!(5)),
+            (json!("<main>"), json!(8)),
+        ];
+        assert_eq!(frames, expected);
+
+        assert_eq!(messages[7]["body"]["scopes"][0]["name"], "Locals");
+        let variables = &messages[8]["body"]["variables"];
+        assert_eq!(
+            variables,
+            &json!([{
+                "name": "n",
+                "value": "0",
+                "type": "Int",
+                "variablesReference": 0,
+            }])
+        );
+        assert_eq!(messages[9]["body"]["result"], "11");
+        assert_eq!(messages[12]["body"]["output"], "3\n");
+        assert_eq!(messages[13]["body"]["exitCode"], 0);
+    }
+
+    #[test]
+    fn dap_unknown_reference() {
+        let path = std::env::temp_dir()
+            .join(format!("dap-reference-{}.rinha", std::process::id()));
+        std::fs::write(&path, SOURCE).unwrap();
+        let program = path.to_str().unwrap();
+
+        let messages = session(&[
+            ("initialize", json!({})),
+            ("launch", json!({ "program": program })),
+            (
+                "setBreakpoints",
+                json!({
+                    "source": { "path": program },
+                    "breakpoints": [{ "line": 3 }],
+                }),
+            ),
+            ("configurationDone", json!({})),
+            ("variables", json!({ "variablesReference": 0 })),
+            ("variables", json!({ "variablesReference": 99 })),
+            ("disconnect", json!({})),
+        ]);
+        std::fs::remove_file(&path).unwrap();
+
+        let variables = messages
+            .iter()
+            .filter(|message| message["command"] == "variables")
+            .collect::<Vec<_>>();
+        assert_eq!(variables.len(), 2);
+        assert!(variables.iter().all(|message| message["success"] == false));
+    }
+
+    #[test]
+    fn dap_launch_error() {
+        let messages = session(&[
+            ("initialize", json!({})),
+            ("launch", json!({ "program": "/nonexistent.rinha" })),
+            ("disconnect", json!({})),
+        ]);
+        let expected = [
+            "response initialize",
+            "event initialized",
+            "response launch",
+            "response disconnect",
+        ];
+        assert_eq!(kinds(&messages), expected);
+        assert_eq!(messages[2]["success"], false);
+    }
+}
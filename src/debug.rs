@@ -0,0 +1,722 @@
+// Step debugger, pausing the evaluation at breakpoints and between steps so
+// that a frontend can inspect the paused scope.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+use std::str::FromStr;
+
+use crate::ast::*;
+use crate::env::Env;
+use crate::eval::{inspect, Evaluator, Frame, Observer};
+use crate::integer::{Overflow, Precision};
+use crate::source::LineIndex;
+use crate::{parser, prelude};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Breakpoint {
+    /// Pauses at the first term of `line`, in `file` or in any file.
+    Line { file: Option<String>, line: usize },
+    /// Pauses at the start of every call to the function.
+    Function(String),
+}
+
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    /// Parses `<line>`, `<file>:<line>` or `<function>`.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (file, line) = match value.rsplit_once(':') {
+            Some((file, line)) => (Some(file.to_string()), line),
+            None => (None, value),
+        };
+        match line.parse() {
+            Ok(line) => Ok(Breakpoint::Line { file, line }),
+            Err(_) if file.is_none() && !value.is_empty() => {
+                Ok(Breakpoint::Function(value.into()))
+            }
+            Err(_) => Err(format!("invalid breakpoint {value}")),
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Breakpoint::Line {
+                file: Some(file),
+                line,
+            } => write!(f, "{file}:{line}"),
+            Breakpoint::Line { file: None, line } => write!(f, "line {line}"),
+            Breakpoint::Function(name) => write!(f, "function {name}"),
+        }
+    }
+}
+
+/// How to resume a paused evaluation.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Step {
+    /// Run until the next breakpoint.
+    Continue,
+    /// Pause at the next line, entering calls.
+    Into,
+    /// Pause at the next line of the current call or its callers.
+    Over,
+    /// Pause once the current call returns.
+    Out,
+    /// Stop the evaluation.
+    Quit,
+}
+
+/// Why the evaluation paused.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Reason {
+    Entry,
+    Step,
+    /// Index of the breakpoint that was hit.
+    Breakpoint(usize),
+}
+
+#[derive(Clone, Debug)]
+pub struct StackFrame {
+    pub name: String,
+    /// Location of the call site, or `None` for the program itself.
+    pub call: Option<Location>,
+    /// Location of the term being evaluated.
+    pub location: Option<Location>,
+    /// Scope of the term being evaluated.
+    pub env: Option<Rc<RefCell<Env>>>,
+    line: Option<usize>,
+}
+
+struct Source {
+    text: String,
+    index: LineIndex,
+}
+
+#[derive(Default)]
+pub struct State {
+    pub breakpoints: Vec<Breakpoint>,
+    /// The precision expressions typed while paused are evaluated at.
+    pub precision: Precision,
+    pub overflow: Overflow,
+    /// The active calls, innermost last.
+    pub stack: Vec<StackFrame>,
+    sources: HashMap<String, Source>,
+    started: bool,
+    /// The current step and the stack depth it started at.
+    step: Option<(Step, usize)>,
+    function: Option<usize>,
+    returned: bool,
+    quit: bool,
+}
+
+impl State {
+    /// The 1-based line of `location`, if its source is known.
+    pub fn line(&self, location: &Location) -> Option<usize> {
+        let source = self.sources.get(&location.filename)?;
+        Some(source.index.line(location.start))
+    }
+
+    /// The 1-based line and column of `location`, if its source is known.
+    pub fn position(&self, location: &Location) -> Option<(usize, usize)> {
+        let source = self.sources.get(&location.filename)?;
+        Some(source.index.position(location.start))
+    }
+
+    /// The names of the files whose sources are known.
+    pub fn files(&self) -> impl Iterator<Item = &str> {
+        self.sources.keys().map(|filename| filename.as_str())
+    }
+
+    /// The text of a 1-based line, without its line break.
+    pub fn source_line(&self, filename: &str, line: usize) -> Option<&str> {
+        let source = self.sources.get(filename)?;
+        let start = source.index.offset(line)?;
+        let end = source.index.offset(line + 1).unwrap_or(source.text.len());
+        Some(source.text[start..end].trim_end_matches(['\n', '\r']))
+    }
+
+    /// `file:line`, or `file:start:end` if the source is unknown.
+    pub fn describe(&self, location: &Location) -> String {
+        let Location {
+            start,
+            end,
+            filename,
+        } = location;
+        match self.line(location) {
+            Some(line) => format!("{filename}:{line}"),
+            None => format!("{filename}:{start}:{end}"),
+        }
+    }
+
+    /// Whether the evaluation was stopped by the frontend.
+    pub fn quit(&self) -> bool {
+        self.quit
+    }
+
+    fn breakpoint(&self, location: &Location, line: usize) -> Option<usize> {
+        self.breakpoints
+            .iter()
+            .position(|breakpoint| match breakpoint {
+                Breakpoint::Line {
+                    file: Some(file),
+                    line: other,
+                } => *other == line && *file == location.filename,
+                Breakpoint::Line {
+                    file: None,
+                    line: other,
+                } => *other == line,
+                Breakpoint::Function(_) => false,
+            })
+    }
+
+    fn reason(
+        &self,
+        location: &Location,
+        line: Option<usize>,
+        new_line: bool,
+    ) -> Option<Reason> {
+        if !self.started {
+            return Some(Reason::Entry);
+        }
+        if let Some(index) = self.function {
+            return Some(Reason::Breakpoint(index));
+        }
+        if self.returned {
+            return Some(Reason::Step);
+        }
+        if !new_line {
+            return None;
+        }
+        if let Some(index) =
+            line.and_then(|line| self.breakpoint(location, line))
+        {
+            return Some(Reason::Breakpoint(index));
+        }
+        let depth = self.stack.len();
+        match self.step {
+            Some((Step::Into, _)) => Some(Reason::Step),
+            Some((Step::Over, start)) if depth <= start => Some(Reason::Step),
+            _ => None,
+        }
+    }
+}
+
+/// A paused evaluation, as seen by a [`Frontend`].
+pub struct Session<'a> {
+    pub reason: Reason,
+    pub location: &'a Location,
+    pub env: &'a Rc<RefCell<Env>>,
+    pub state: &'a mut State,
+}
+
+impl Session<'_> {
+    /// Evaluates `source` in the paused scope.
+    pub fn evaluate(&self, source: &str) -> Term {
+        let filename = &self.location.filename;
+        let mut evaluator = Evaluator::new();
+        evaluator.precision(self.state.precision);
+        evaluator.overflow(self.state.overflow);
+        evaluate(&mut evaluator, self.env, filename, source)
+    }
+}
+
+/// Evaluates `source` in a new scope extending `env`, so that its bindings
+/// are discarded afterwards.
+pub fn evaluate(
+    evaluator: &mut Evaluator,
+    env: &Rc<RefCell<Env>>,
+    filename: &str,
+    source: &str,
+) -> Term {
+    match parser::parse(source, filename) {
+        Ok(file) => {
+            let mut env = Rc::new(RefCell::new(Env::extend(env.clone())));
+            evaluator.eval(&mut env, file.expression)
+        }
+        Err(error) => Term::Error(error),
+    }
+}
+
+/// The bindings of `env` and its parents, innermost scope first, leaving
+/// out the builtins.
+pub fn scopes(env: &Rc<RefCell<Env>>) -> Vec<Vec<(String, Term)>> {
+    let mut scopes = Vec::new();
+    let mut env = Some(env.clone());
+    while let Some(scope) = env {
+        let scope = scope.borrow();
+        if prelude::is_prelude(&scope) {
+            break;
+        }
+        let bindings = scope
+            .bindings()
+            .into_iter()
+            .map(|(name, term)| (name.to_string(), term.clone()))
+            .collect();
+        scopes.push(bindings);
+        env = scope.parent();
+    }
+    scopes
+}
+
+/// The user interface of a [`Debugger`].
+pub trait Frontend {
+    /// Called whenever the evaluation pauses, returning how to resume it.
+    fn pause(&mut self, session: Session) -> Step;
+}
+
+pub struct Debugger<F> {
+    state: State,
+    frontend: F,
+}
+
+impl<F: Frontend> Debugger<F> {
+    /// A debugger that pauses before the first term.
+    pub fn new(frontend: F) -> Self {
+        Self {
+            state: Default::default(),
+            frontend,
+        }
+    }
+
+    /// Registers the source of `filename`, enabling line breakpoints and
+    /// line stepping in it.
+    pub fn source(&mut self, filename: &str, text: &str) {
+        let source = Source {
+            text: text.into(),
+            index: LineIndex::new(text),
+        };
+        self.state.sources.insert(filename.into(), source);
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut State {
+        &mut self.state
+    }
+
+    pub fn frontend(&self) -> &F {
+        &self.frontend
+    }
+
+    pub fn frontend_mut(&mut self) -> &mut F {
+        &mut self.frontend
+    }
+}
+
+fn interrupted(location: &Location) -> Error {
+    Error {
+        message: "Interrupted".into(),
+        full_text: "Evaluation stopped by the debugger".into(),
+        location: location.clone(),
+        kind: None,
+    }
+}
+
+impl<F: Frontend> Observer for Debugger<F> {
+    fn visit(
+        &mut self,
+        env: &Rc<RefCell<Env>>,
+        term: &Term,
+    ) -> Result<(), Error> {
+        let location = term.location();
+        let state = &mut self.state;
+
+        if state.quit {
+            return Err(interrupted(location));
+        }
+
+        if state.stack.is_empty() {
+            state.stack.push(StackFrame {
+                name: "<main>".into(),
+                call: None,
+                location: None,
+                env: None,
+                line: None,
+            });
+        }
+
+        let line = state.line(location);
+        let frame = state.stack.last_mut().unwrap();
+        let new_line = line.is_none() || line != frame.line;
+        frame.line = line;
+        frame.location = Some(location.clone());
+        frame.env = Some(env.clone());
+
+        let Some(reason) = state.reason(location, line, new_line) else {
+            return Ok(());
+        };
+
+        state.started = true;
+        state.function = None;
+        state.returned = false;
+
+        let session = Session {
+            reason,
+            location,
+            env,
+            state,
+        };
+        let step = self.frontend.pause(session);
+
+        let state = &mut self.state;
+        state.step = match step {
+            Step::Continue => None,
+            Step::Quit => {
+                state.quit = true;
+                return Err(interrupted(location));
+            }
+            step => Some((step, state.stack.len())),
+        };
+
+        Ok(())
+    }
+
+    fn enter(&mut self, frame: &Frame, arguments: &[Term]) {
+        let state = &mut self.state;
+        // Arguments are evaluated in the scope of the callee, so the scope
+        // of the caller is the parent of the last one seen
+        if !arguments.is_empty() {
+            if let Some(caller) = state.stack.last_mut() {
+                let env =
+                    caller.env.as_ref().and_then(|env| env.borrow().parent());
+                caller.env = env;
+            }
+        }
+        state.stack.push(StackFrame {
+            name: frame.name.clone(),
+            call: Some(frame.location.clone()),
+            location: None,
+            env: None,
+            line: None,
+        });
+        let breakpoint = state.breakpoints.iter().position(|breakpoint| {
+            *breakpoint == Breakpoint::Function(frame.name.clone())
+        });
+        if breakpoint.is_some() {
+            state.function = breakpoint;
+        }
+    }
+
+    fn exit(&mut self, _frame: &Frame, _result: &Term) {
+        let state = &mut self.state;
+        state.stack.pop();
+        if let Some((_, start)) = state.step {
+            if state.stack.len() < start {
+                state.returned = true;
+            }
+        }
+    }
+}
+
+const HELP: &str = "\
+Commands:
+    break <line> | <file>:<line> | <function>   Add a breakpoint (b)
+    delete <n>    Remove a breakpoint (d)
+    breakpoints   List the breakpoints (info)
+    continue      Run until the next breakpoint (c)
+    step          Step to the next line, entering calls (s)
+    next          Step to the next line, over calls (n)
+    finish        Run until the current call returns (f)
+    backtrace     Show the call stack (bt)
+    env           Show the bindings of every scope (locals)
+    print <expr>  Evaluate an expression in the paused scope (p)
+    list          Show the source around the paused line (l)
+    quit          Stop the program (q)";
+
+/// A command line frontend reading commands from `input`.
+pub struct Console<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Console<R, W> {
+    pub fn new(input: R, output: W) -> Self {
+        Self { input, output }
+    }
+
+    pub fn output(&self) -> &W {
+        &self.output
+    }
+
+    fn stopped(&mut self, session: &Session) {
+        let state = &session.state;
+        let at = state.describe(session.location);
+        let _ = match session.reason {
+            Reason::Entry => writeln!(self.output, "stopped at {at}"),
+            Reason::Step => writeln!(self.output, "{at}"),
+            Reason::Breakpoint(index) => writeln!(
+                self.output,
+                "breakpoint {} ({}) at {at}",
+                index + 1,
+                state.breakpoints[index],
+            ),
+        };
+        self.list(session, 0);
+    }
+
+    fn list(&mut self, session: &Session, context: usize) {
+        let state = &session.state;
+        let Some(line) = state.line(session.location) else {
+            return;
+        };
+        let filename = &session.location.filename;
+        for number in line.saturating_sub(context).max(1)..=line + context {
+            if let Some(text) = state.source_line(filename, number) {
+                let marker = if number == line { '>' } else { ' ' };
+                let _ = writeln!(self.output, "{marker}{number:4} | {text}");
+            }
+        }
+    }
+
+    fn backtrace(&mut self, session: &Session) {
+        let state = &session.state;
+        for (i, frame) in state.stack.iter().rev().enumerate() {
+            let at = match &frame.location {
+                Some(location) => state.describe(location),
+                None => "?".into(),
+            };
+            let _ = match &frame.call {
+                Some(call) => writeln!(
+                    self.output,
+                    "#{i} {} at {at}, called from {}",
+                    frame.name,
+                    state.describe(call),
+                ),
+                None => writeln!(self.output, "#{i} {} at {at}", frame.name),
+            };
+        }
+    }
+
+    fn env(&mut self, session: &Session) {
+        let scopes = scopes(session.env);
+        let globals = scopes.len() - 1;
+        for (i, scope) in scopes.iter().enumerate() {
+            let _ = match i == globals {
+                true => writeln!(self.output, "global scope:"),
+                false => writeln!(self.output, "scope {i}:"),
+            };
+            for (name, term) in scope {
+                let _ = writeln!(self.output, "  {name} = {}", inspect(term));
+            }
+        }
+    }
+
+    /// Runs a command, returning how to resume if it resumes the program.
+    fn command(&mut self, session: &mut Session, line: &str) -> Option<Step> {
+        let (command, argument) = match line.trim().split_once(' ') {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (line.trim(), ""),
+        };
+        let state = &mut session.state;
+
+        match command {
+            "" => {}
+            "help" | "h" => {
+                let _ = writeln!(self.output, "{HELP}");
+            }
+            "break" | "b" => match argument.parse::<Breakpoint>() {
+                Ok(breakpoint) => {
+                    let _ = writeln!(
+                        self.output,
+                        "breakpoint {} at {breakpoint}",
+                        state.breakpoints.len() + 1,
+                    );
+                    state.breakpoints.push(breakpoint);
+                }
+                Err(error) => {
+                    let _ = writeln!(self.output, "error: {error}");
+                }
+            },
+            "delete" | "d" => match argument.parse::<usize>() {
+                Ok(n) if (1..=state.breakpoints.len()).contains(&n) => {
+                    state.breakpoints.remove(n - 1);
+                }
+                _ => {
+                    let _ = writeln!(
+                        self.output,
+                        "error: no breakpoint {argument}"
+                    );
+                }
+            },
+            "breakpoints" | "info" => {
+                for (i, breakpoint) in state.breakpoints.iter().enumerate() {
+                    let _ = writeln!(self.output, "{} {breakpoint}", i + 1);
+                }
+            }
+            "continue" | "c" => return Some(Step::Continue),
+            "step" | "s" => return Some(Step::Into),
+            "next" | "n" => return Some(Step::Over),
+            "finish" | "f" => return Some(Step::Out),
+            "quit" | "q" => return Some(Step::Quit),
+            "backtrace" | "bt" => self.backtrace(session),
+            "env" | "locals" => self.env(session),
+            "list" | "l" => self.list(session, 2),
+            "print" | "p" => {
+                let _ = match &session.evaluate(argument) {
+                    Term::Error(error) => {
+                        writeln!(
+                            self.output,
+                            "{}",
+                            error.to_string().trim_end()
+                        )
+                    }
+                    term => writeln!(self.output, "{}", inspect(term)),
+                };
+            }
+            _ => {
+                let _ = writeln!(
+                    self.output,
+                    "error: unknown command {command}, try help"
+                );
+            }
+        }
+
+        None
+    }
+}
+
+impl<R: BufRead, W: Write> Frontend for Console<R, W> {
+    fn pause(&mut self, mut session: Session) -> Step {
+        self.stopped(&session);
+        loop {
+            let _ = write!(self.output, "(rinha) ");
+            let _ = self.output.flush();
+            let mut line = String::new();
+            match self.input.read_line(&mut line) {
+                Ok(0) | Err(_) => return Step::Quit,
+                Ok(_) => {}
+            }
+            if let Some(step) = self.command(&mut session, &line) {
+                return step;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "\
+let sum = fn (n) => {
+  if (n < 1) {
+    0
+  } else {
+    n + sum(n - 1)
+  }
+};
+let total = sum(2);
+print(total)
+";
+
+    /// Resumes with the given steps, recording where it paused.
+    #[derive(Default)]
+    struct Script {
+        steps: Vec<Step>,
+        pauses: Vec<(Reason, String, usize)>,
+    }
+
+    impl Frontend for Script {
+        fn pause(&mut self, session: Session) -> Step {
+            let line = session.state.line(session.location).unwrap();
+            let name = session.state.stack.last().unwrap().name.clone();
+            self.pauses.push((session.reason, name, line));
+            match self.steps.is_empty() {
+                true => Step::Continue,
+                false => self.steps.remove(0),
+            }
+        }
+    }
+
+    fn debug<F: Frontend + 'static>(
+        frontend: F,
+        breakpoints: &[&str],
+    ) -> (Rc<RefCell<Debugger<F>>>, Term) {
+        let file = parser::parse(SOURCE, "sum.rinha").unwrap();
+        let debugger = Rc::new(RefCell::new(Debugger::new(frontend)));
+        debugger.borrow_mut().source("sum.rinha", SOURCE);
+        for breakpoint in breakpoints {
+            let breakpoint = breakpoint.parse().unwrap();
+            debugger
+                .borrow_mut()
+                .state_mut()
+                .breakpoints
+                .push(breakpoint);
+        }
+        let mut evaluator = Evaluator::new();
+        evaluator.observe(debugger.clone());
+        let result = evaluator.eval(&mut Default::default(), file.expression);
+        (debugger, result)
+    }
+
+    #[test]
+    fn debug_breakpoint_parse() {
+        let line = |file: Option<&str>, line| Breakpoint::Line {
+            file: file.map(Into::into),
+            line,
+        };
+        assert_eq!("3".parse(), Ok(line(None, 3)));
+        assert_eq!("a.rinha:3".parse(), Ok(line(Some("a.rinha"), 3)));
+        assert_eq!("fib".parse(), Ok(Breakpoint::Function("fib".into())));
+        assert!("a.rinha:x".parse::<Breakpoint>().is_err());
+    }
+
+    #[test]
+    fn debug_steps() {
+        let steps = vec![Step::Over, Step::Into, Step::Into, Step::Out];
+        let script = Script {
+            steps,
+            ..Default::default()
+        };
+        let (debugger, _) = debug(script, &[]);
+        let pauses = debugger.borrow().frontend().pauses.clone();
+        let sum = |reason, line| (reason, "sum".to_string(), line);
+        let main = |reason, line| (reason, "<main>".to_string(), line);
+        let expected = [
+            main(Reason::Entry, 1),
+            main(Reason::Step, 8),
+            sum(Reason::Step, 2),
+            sum(Reason::Step, 5),
+            main(Reason::Step, 9),
+        ];
+        assert_eq!(pauses, expected);
+    }
+
+    #[test]
+    fn debug_console() {
+        let commands = "\
+b sum
+c
+bt
+p n * 10
+env
+d 1
+c
+";
+        let console = Console::new(commands.as_bytes(), Vec::new());
+        let (debugger, result) = debug(console, &[]);
+        let output = debugger.borrow().frontend().output().clone();
+        let expected = "\
+stopped at sum.rinha:1
+>   1 | let sum = fn (n) => {
+(rinha) breakpoint 1 at function sum
+(rinha) breakpoint 1 (function sum) at sum.rinha:2
+>   2 |   if (n < 1) {
+(rinha) #0 sum at sum.rinha:2, called from sum.rinha:8
+#1 <main> at sum.rinha:8
+(rinha) 20
+(rinha) scope 0:
+  n = 2
+global scope:
+  sum = <#closure>
+(rinha) (rinha) ";
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+        assert!(!matches!(result, Term::Error(_)));
+    }
+}
@@ -0,0 +1,310 @@
+// Graphviz export of the AST, useful to inspect the shape of generated
+// programs.
+
+use std::fmt::Write;
+
+use crate::ast::*;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Options {
+    /// Maximum depth of nodes to render below the root.
+    pub depth: Option<usize>,
+    /// Render only the smallest subtree spanning these byte offsets.
+    pub at: Option<(usize, usize)>,
+}
+
+/// Renders `file` as a DOT graph, or returns `None` if `options.at` does
+/// not match any node.
+pub fn render(file: &File, options: &Options) -> Option<String> {
+    let root = match options.at {
+        Some((start, end)) => select(&file.expression, start, end)?,
+        None => &file.expression,
+    };
+
+    let mut graph = Graph {
+        output: String::new(),
+        nodes: 0,
+        depth: options.depth,
+    };
+
+    writeln!(graph.output, "digraph ast {{").unwrap();
+    writeln!(graph.output, "  label=\"{}\";", escape(&file.name)).unwrap();
+    writeln!(graph.output, "  node [shape=box, fontname=\"monospace\"];")
+        .unwrap();
+    graph.node(root);
+    writeln!(graph.output, "}}").unwrap();
+
+    Some(graph.output)
+}
+
+/// Finds the smallest term whose location contains `start..end`.
+pub fn select(term: &Term, start: usize, end: usize) -> Option<&Term> {
+    let contains = |term: &Term| {
+        let location = term.location();
+        location.start <= start && end <= location.end
+    };
+    if !contains(term) {
+        return None;
+    }
+    let mut term = term;
+    while let Some((_, child)) = children(term)
+        .into_iter()
+        .find(|(_, child)| contains(child))
+    {
+        term = child;
+    }
+    Some(term)
+}
+
+struct Graph {
+    output: String,
+    nodes: usize,
+    depth: Option<usize>,
+}
+
+/// Pending work of the traversal. The edge to a node is written once its
+/// whole subtree has been.
+enum Step<'a> {
+    Node(&'a Term, usize, Option<(usize, String)>),
+    Edge(usize, usize, String),
+}
+
+impl Graph {
+    /// Writes `root` and its subterms with an explicit stack, as generated
+    /// programs can be nested deeper than the native stack allows.
+    fn node(&mut self, root: &Term) {
+        let mut pending = vec![Step::Node(root, 0, None)];
+        while let Some(step) = pending.pop() {
+            match step {
+                Step::Node(term, depth, parent) => {
+                    let id = self.nodes;
+                    self.nodes += 1;
+                    if let Some((parent, edge)) = parent {
+                        pending.push(Step::Edge(parent, id, edge));
+                    }
+                    self.write(term, id, depth, &mut pending);
+                }
+                Step::Edge(parent, child, edge) => writeln!(
+                    self.output,
+                    "  n{parent} -> n{child} [label=\"{}\"];",
+                    escape(&edge)
+                )
+                .unwrap(),
+            }
+        }
+    }
+
+    fn write<'a>(
+        &mut self,
+        term: &'a Term,
+        id: usize,
+        depth: usize,
+        pending: &mut Vec<Step<'a>>,
+    ) {
+        let Location { start, end, .. } = term.location();
+        let label = match detail(term) {
+            Some(detail) => {
+                format!("{}\n{}\n{}..{}", kind(term), detail, start, end)
+            }
+            None => format!("{}\n{}..{}", kind(term), start, end),
+        };
+        writeln!(self.output, "  n{id} [label=\"{}\"];", escape(&label))
+            .unwrap();
+
+        let children = children(term);
+        if children.is_empty() {
+            return;
+        }
+
+        if self.depth.is_some_and(|limit| depth >= limit) {
+            let elided = self.nodes;
+            self.nodes += 1;
+            writeln!(
+                self.output,
+                "  n{elided} [label=\"...\", shape=plaintext];\n  \
+                 n{id} -> n{elided} [style=dashed];"
+            )
+            .unwrap();
+            return;
+        }
+
+        for (edge, child) in children.into_iter().rev() {
+            pending.push(Step::Node(child, depth + 1, Some((id, edge))));
+        }
+    }
+}
+
+fn kind(term: &Term) -> &'static str {
+    match term {
+        Term::Error(_) => "Error",
+        Term::Int(_) => "Int",
+        Term::Str(_) => "Str",
+        Term::Call(_) => "Call",
+        Term::Binary(_) => "Binary",
+        Term::Function(_) => "Function",
+        Term::Builtin(_) => "Builtin",
+        Term::Let(_) => "Let",
+        Term::Import(_) => "Import",
+        Term::If(_) => "If",
+        Term::Print(_) => "Print",
+        Term::First(_) => "First",
+        Term::Second(_) => "Second",
+        Term::Bool(_) => "Bool",
+        Term::Tuple(_) => "Tuple",
+        Term::Var(_) => "Var",
+    }
+}
+
+fn detail(term: &Term) -> Option<String> {
+    match term {
+        Term::Error(term) => Some(term.message.clone()),
+        Term::Int(term) => Some(term.value.to_string()),
+        Term::Str(term) => Some(format!("{:?}", term.value)),
+        Term::Bool(term) => Some(term.value.to_string()),
+        Term::Var(term) => Some(term.text.clone()),
+        Term::Builtin(term) => Some(term.name.clone()),
+        Term::Let(term) => Some(term.name.text.clone()),
+        Term::Import(term) => Some(format!("{:?}", term.path.value)),
+        Term::Binary(term) => Some(term.op.symbol().into()),
+        Term::Function(term) => {
+            let parameters = term.parameters.iter().map(|p| p.text.as_str());
+            let parameters = parameters.collect::<Vec<_>>().join(", ");
+            Some(format!("({parameters})"))
+        }
+        _ => None,
+    }
+}
+
+fn children(term: &Term) -> Vec<(String, &Term)> {
+    match term {
+        Term::Error(_)
+        | Term::Int(_)
+        | Term::Str(_)
+        | Term::Bool(_)
+        | Term::Builtin(_)
+        | Term::Var(_) => vec![],
+        Term::Call(term) => {
+            let mut children = vec![("callee".into(), term.callee.as_ref())];
+            for (i, argument) in term.arguments.iter().enumerate() {
+                children.push((format!("arguments[{i}]"), argument));
+            }
+            children
+        }
+        Term::Binary(term) => vec![
+            ("lhs".into(), term.lhs.as_ref()),
+            ("rhs".into(), term.rhs.as_ref()),
+        ],
+        Term::Function(term) => vec![("value".into(), term.value.as_ref())],
+        Term::Let(term) => vec![
+            ("value".into(), term.value.as_ref()),
+            ("next".into(), term.next.as_ref()),
+        ],
+        Term::Import(term) => vec![("next".into(), term.next.as_ref())],
+        Term::If(term) => vec![
+            ("condition".into(), term.condition.as_ref()),
+            ("then".into(), term.then.as_ref()),
+            ("otherwise".into(), term.otherwise.as_ref()),
+        ],
+        Term::Print(term) => vec![("value".into(), term.value.as_ref())],
+        Term::First(term) => vec![("value".into(), term.value.as_ref())],
+        Term::Second(term) => vec![("value".into(), term.value.as_ref())],
+        Term::Tuple(term) => vec![
+            ("first".into(), term.first.as_ref()),
+            ("second".into(), term.second.as_ref()),
+        ],
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+
+    const SOURCE: &str = "let x = 1 + 2;\nprint(x)";
+
+    #[test]
+    fn render_graph() {
+        let file = parser::parse(SOURCE, "x.rinha").unwrap();
+        let graph = render(&file, &Default::default()).unwrap();
+        let expected = r#"digraph ast {
+  label="x.rinha";
+  node [shape=box, fontname="monospace"];
+  n0 [label="Let\nx\n0..23"];
+  n1 [label="Binary\n+\n8..13"];
+  n2 [label="Int\n1\n8..9"];
+  n1 -> n2 [label="lhs"];
+  n3 [label="Int\n2\n12..13"];
+  n1 -> n3 [label="rhs"];
+  n0 -> n1 [label="value"];
+  n4 [label="Print\n15..23"];
+  n5 [label="Var\nx\n21..22"];
+  n4 -> n5 [label="value"];
+  n0 -> n4 [label="next"];
+}
+"#;
+        assert_eq!(graph, expected);
+    }
+
+    #[test]
+    fn render_depth() {
+        let file = parser::parse(SOURCE, "x.rinha").unwrap();
+        let options = Options {
+            depth: Some(0),
+            ..Default::default()
+        };
+        let graph = render(&file, &options).unwrap();
+        assert!(graph.contains("n0 -> n1 [style=dashed];"));
+        assert!(!graph.contains("Binary"));
+    }
+
+    #[test]
+    fn render_subtree() {
+        let file = parser::parse(SOURCE, "x.rinha").unwrap();
+        let options = Options {
+            at: Some((12, 12)),
+            ..Default::default()
+        };
+        let graph = render(&file, &options).unwrap();
+        assert!(graph.contains("n0 [label=\"Int\\n2\\n12..13\"];"));
+        assert!(!graph.contains("->"));
+
+        let options = Options {
+            at: Some((100, 100)),
+            ..Default::default()
+        };
+        assert_eq!(render(&file, &options), None);
+    }
+
+    #[test]
+    fn render_deep() {
+        use crate::ast::build::*;
+
+        let mut expression = int(0);
+        for _ in 0..200_000 {
+            expression = print(expression);
+        }
+        let file = File {
+            name: "deep.rinha".into(),
+            expression,
+            location: Default::default(),
+        };
+
+        let options = Options {
+            depth: Some(1),
+            ..Default::default()
+        };
+        let graph = render(&file, &options).unwrap();
+        assert_eq!(graph.matches("Print").count(), 2);
+
+        let graph = render(&file, &Default::default()).unwrap();
+        assert_eq!(graph.matches(" -> ").count(), 200_000);
+        assert!(select(&file.expression, 0, 0).is_some());
+    }
+}
@@ -22,12 +22,41 @@ impl Env {
         }
     }
 
+    /// Looks `name` up from this scope outwards. Each pending call adds a
+    /// scope, so the chain is walked in a loop rather than recursively.
     pub fn get(&self, name: &str) -> Option<Term> {
-        self.vars.get(name).cloned().or_else(|| {
-            self.parent
-                .as_ref()
-                .and_then(|o| o.borrow().get(name).clone())
-        })
+        if let Some(term) = self.vars.get(name) {
+            return Some(term.clone());
+        }
+        let mut parent = self.parent.clone();
+        while let Some(scope) = parent {
+            let scope = scope.borrow();
+            if let Some(term) = scope.vars.get(name) {
+                return Some(term.clone());
+            }
+            parent = scope.parent.clone();
+        }
+        None
+    }
+
+    /// The value bound to `name` in this scope alone.
+    pub fn local(&self, name: &str) -> Option<&Term> {
+        self.vars.get(name)
+    }
+
+    pub fn parent(&self) -> Option<Rc<RefCell<Env>>> {
+        self.parent.clone()
+    }
+
+    /// The bindings of this scope alone, sorted by name.
+    pub fn bindings(&self) -> Vec<(&str, &Term)> {
+        let mut bindings = self
+            .vars
+            .iter()
+            .map(|(name, term)| (name.as_str(), term))
+            .collect::<Vec<_>>();
+        bindings.sort_by_key(|(name, _)| *name);
+        bindings
     }
 
     pub fn set(&mut self, name: &str, term: Term) -> Option<Term> {
@@ -37,3 +66,17 @@ impl Env {
         }
     }
 }
+
+impl Drop for Env {
+    // Unlinks the scopes no one else holds one by one, as dropping them
+    // recursively could overflow the stack for deep call chains
+    fn drop(&mut self) {
+        let mut parent = self.parent.take();
+        while let Some(scope) = parent {
+            parent = match Rc::try_unwrap(scope) {
+                Ok(scope) => scope.into_inner().parent.take(),
+                Err(_) => None,
+            };
+        }
+    }
+}
@@ -0,0 +1,436 @@
+// Errors raised while evaluating a program. Each kind has a stable code that
+// is printed with the error and documented by `--explain`.
+
+use std::fmt;
+
+use crate::ast::*;
+use crate::integer::{Integer, Precision};
+
+/// The type of an evaluated term, as reported by runtime errors.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Type {
+    Unknown,
+    Int,
+    Str,
+    Bool,
+    Tuple,
+    Function,
+}
+
+impl Type {
+    pub fn of(term: &Term) -> Type {
+        match term {
+            Term::Int(_) => Type::Int,
+            Term::Str(_) => Type::Str,
+            Term::Bool(_) => Type::Bool,
+            Term::Tuple(_) => Type::Tuple,
+            Term::Function(_) | Term::Builtin(_) => Type::Function,
+            _ => Type::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Unknown => write!(f, "?"),
+            Type::Int => write!(f, "Int"),
+            Type::Str => write!(f, "Str"),
+            Type::Bool => write!(f, "Bool"),
+            Type::Tuple => write!(f, "Tuple"),
+            Type::Function => write!(f, "Function"),
+        }
+    }
+}
+
+/// Where a value of the wrong type was found.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Position {
+    Condition,
+    LeftOperand,
+    RightOperand,
+    First,
+    Second,
+    /// An argument of a builtin, counting from zero.
+    Argument {
+        function: &'static str,
+        index: usize,
+    },
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RuntimeError {
+    UndefinedVariable {
+        name: String,
+    },
+    Redefinition {
+        name: String,
+    },
+    TypeMismatch {
+        position: Position,
+        expected: Vec<Type>,
+        found: Type,
+    },
+    ArityMismatch {
+        expected: usize,
+        found: usize,
+    },
+    DivisionByZero,
+    NotCallable {
+        found: Type,
+    },
+    NotPrintable {
+        found: Type,
+    },
+    OutOfRange {
+        value: Integer,
+        precision: Precision,
+    },
+    Overflow {
+        op: BinaryOp,
+        lhs: Integer,
+        rhs: Integer,
+        precision: Precision,
+    },
+    BuiltinOverflow {
+        function: String,
+        arguments: Vec<Integer>,
+        precision: Precision,
+    },
+    ImportCycle {
+        /// The files importing each other, starting and ending with the same
+        /// one.
+        files: Vec<String>,
+    },
+    ImportFailed {
+        path: String,
+        reason: String,
+    },
+    InvalidArgument {
+        function: String,
+        reason: String,
+    },
+}
+
+const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "R0001",
+        "\
+A variable was used but no enclosing `let` or function parameter binds it
+at that point of the evaluation.
+
+    let f = fn () => { x };
+    f()
+
+Variables are looked up in the scope of the caller, so `x` must be bound
+before the call that reaches it.",
+    ),
+    (
+        "R0002",
+        "\
+A `let` bound a name that is already bound in the same scope.
+
+    let x = 1;
+    let x = 2;
+    x
+
+Use a different name, or bind the second value inside a function.",
+    ),
+    (
+        "R0003",
+        "\
+A value of the wrong type was used as an operand, as the condition of an
+`if`, or as the argument of `first` or `second`.
+
+    1 - true
+
+Arithmetic and comparisons expect `Int` operands, `&&` and `||` expect
+`Bool`, `+` also accepts `Str` on either side, conditions must be `Bool`
+and `first` and `second` expect a tuple.",
+    ),
+    (
+        "R0004",
+        "\
+A function was called with a different number of arguments than it has
+parameters.
+
+    let f = fn (a, b) => { a + b };
+    f(1)",
+    ),
+    (
+        "R0005",
+        "\
+The right operand of `/` or `%` evaluated to zero.
+
+    let n = 0;
+    10 / n
+
+Check the divisor before dividing.",
+    ),
+    (
+        "R0006",
+        "\
+A value that is not a function was called.
+
+    let x = 1;
+    x(2)",
+    ),
+    (
+        "R0007",
+        "\
+`print` was given a value it cannot print, such as a tuple.
+
+    print((1, 2))
+
+Print each element of the tuple with `first` and `second` instead.",
+    ),
+    (
+        "R0008",
+        "\
+An integer literal does not fit in the integers of the selected precision,
+which are 32-bit by default.
+
+    print(3000000000)
+
+Run the program with `--integers i64` or `--integers big` to use wider
+integers.",
+    ),
+    (
+        "R0009",
+        "\
+An arithmetic operation or a builtin such as `abs` or `pow` overflowed the
+integers of the selected precision while running with `--overflow error`.
+
+    2147483647 + 1
+
+Without `--overflow error` the result wraps around instead. Use
+`--integers i64` or `--integers big` for wider integers.",
+    ),
+    (
+        "R0010",
+        "\
+A file imports itself, directly or through the files it imports.
+
+    // a.rinha
+    import \"b.rinha\";
+    a_value
+
+    // b.rinha
+    import \"a.rinha\";
+    b_value
+
+Move the bindings both files need to a third file imported by both.",
+    ),
+    (
+        "R0011",
+        "\
+An imported file could not be read or loaded.
+
+    import \"missing.rinha\";
+    value
+
+Imported paths are relative to the directory of the importing file.",
+    ),
+    (
+        "R0012",
+        "\
+A builtin function was given an argument of the right type but with a
+value it cannot handle, such as an index past the end of a string or a
+string that is not an integer.
+
+    char_at(\"abc\", 3)
+
+Strings are indexed by character, from 0 to `len(s) - 1`.",
+    ),
+];
+
+impl RuntimeError {
+    /// The stable code identifying this kind of error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            RuntimeError::UndefinedVariable { .. } => "R0001",
+            RuntimeError::Redefinition { .. } => "R0002",
+            RuntimeError::TypeMismatch { .. } => "R0003",
+            RuntimeError::ArityMismatch { .. } => "R0004",
+            RuntimeError::DivisionByZero => "R0005",
+            RuntimeError::NotCallable { .. } => "R0006",
+            RuntimeError::NotPrintable { .. } => "R0007",
+            RuntimeError::OutOfRange { .. } => "R0008",
+            RuntimeError::Overflow { .. } => "R0009",
+            RuntimeError::BuiltinOverflow { .. } => "R0009",
+            RuntimeError::ImportCycle { .. } => "R0010",
+            RuntimeError::ImportFailed { .. } => "R0011",
+            RuntimeError::InvalidArgument { .. } => "R0012",
+        }
+    }
+
+    /// The short message printed in the header of the error.
+    pub fn title(&self) -> &'static str {
+        match self {
+            RuntimeError::UndefinedVariable { .. } => "Undefined variable",
+            RuntimeError::Redefinition { .. } => "Redefined variable",
+            RuntimeError::TypeMismatch { position, .. } => match position {
+                Position::LeftOperand => "Unexpected left operand",
+                Position::RightOperand => "Unexpected right operand",
+                _ => "Unexpected term",
+            },
+            RuntimeError::ArityMismatch { .. } => "Argument count mismatch",
+            RuntimeError::DivisionByZero => "Division by zero",
+            RuntimeError::NotCallable { .. } => "Unexpected term",
+            RuntimeError::NotPrintable { .. } => "Unexpected term",
+            RuntimeError::OutOfRange { .. } => "Integer out of range",
+            RuntimeError::Overflow { .. } => "Integer overflow",
+            RuntimeError::BuiltinOverflow { .. } => "Integer overflow",
+            RuntimeError::ImportCycle { .. } => "Import cycle",
+            RuntimeError::ImportFailed { .. } => "Import failed",
+            RuntimeError::InvalidArgument { .. } => "Invalid argument",
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::UndefinedVariable { name } => {
+                write!(f, "Undefined variable \"{name}\"")
+            }
+            RuntimeError::Redefinition { name } => {
+                write!(f, "Variable \"{name}\" is already defined")
+            }
+            RuntimeError::TypeMismatch {
+                position, expected, ..
+            } => match position {
+                Position::Condition => {
+                    write!(f, "Expected condition of type \"Bool\"")
+                }
+                Position::LeftOperand | Position::RightOperand => {
+                    write!(f, "Expected operand of type ")?;
+                    write_types(f, expected)
+                }
+                Position::First => {
+                    write!(f, "The first function expects a tuple")
+                }
+                Position::Second => {
+                    write!(f, "The second function expects a tuple")
+                }
+                Position::Argument { function, index } => {
+                    let n = index + 1;
+                    write!(f, "The {function} function expects argument {n} ")?;
+                    write!(f, "of type ")?;
+                    write_types(f, expected)
+                }
+            },
+            RuntimeError::ArityMismatch { expected, found } => {
+                write!(f, "Expected {expected} arguments, found {found}")
+            }
+            RuntimeError::DivisionByZero => {
+                write!(f, "Attempted to divide by zero")
+            }
+            RuntimeError::NotCallable { .. } => {
+                write!(f, "Expected function body or reference")
+            }
+            RuntimeError::NotPrintable { .. } => {
+                write!(f, "The term is not a first class value")
+            }
+            RuntimeError::OutOfRange { value, precision } => {
+                write!(f, "Integer literal {value} does not fit in {precision}")
+            }
+            RuntimeError::Overflow {
+                op,
+                lhs,
+                rhs,
+                precision,
+            } => {
+                let op = op.symbol();
+                write!(f, "Computing {lhs} {op} {rhs} overflows {precision}")
+            }
+            RuntimeError::BuiltinOverflow {
+                function,
+                arguments,
+                precision,
+            } => {
+                let arguments = arguments.iter().map(ToString::to_string);
+                let arguments = arguments.collect::<Vec<_>>().join(", ");
+                write!(f, "Computing {function}({arguments}) overflows ")?;
+                write!(f, "{precision}")
+            }
+            RuntimeError::ImportCycle { files } => {
+                write!(f, "{}", files.join(" imports "))
+            }
+            RuntimeError::ImportFailed { path, reason } => {
+                write!(f, "Cannot import {path}: {reason}")
+            }
+            RuntimeError::InvalidArgument { function, reason } => {
+                write!(f, "Invalid argument to {function}: {reason}")
+            }
+        }
+    }
+}
+
+/// Writes `types` as a list like `"Int", "Str", or "Bool"`.
+fn write_types(f: &mut fmt::Formatter<'_>, types: &[Type]) -> fmt::Result {
+    for (i, ty) in types.iter().enumerate() {
+        match i {
+            0 => {}
+            _ if i + 1 < types.len() => write!(f, ", ")?,
+            1 => write!(f, " or ")?,
+            _ => write!(f, ", or ")?,
+        }
+        write!(f, "\"{ty}\"")?;
+    }
+    Ok(())
+}
+
+/// The long explanation of an error code, as shown by `--explain`.
+pub fn explain(code: &str) -> Option<&'static str> {
+    let code = code.to_ascii_uppercase();
+    EXPLANATIONS
+        .iter()
+        .find(|(known, _)| *known == code)
+        .map(|(_, explanation)| *explanation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runtime_error_messages() {
+        let expected = vec![Type::Bool, Type::Int, Type::Str];
+        let error = RuntimeError::TypeMismatch {
+            position: Position::RightOperand,
+            expected,
+            found: Type::Tuple,
+        };
+        assert_eq!(error.code(), "R0003");
+        assert_eq!(error.title(), "Unexpected right operand");
+        assert_eq!(
+            error.to_string(),
+            "Expected operand of type \"Bool\", \"Int\", or \"Str\""
+        );
+
+        let error = RuntimeError::TypeMismatch {
+            position: Position::LeftOperand,
+            expected: vec![Type::Int, Type::Str],
+            found: Type::Bool,
+        };
+        assert_eq!(
+            error.to_string(),
+            "Expected operand of type \"Int\" or \"Str\""
+        );
+
+        let error = RuntimeError::ArityMismatch {
+            expected: 2,
+            found: 1,
+        };
+        assert_eq!(error.to_string(), "Expected 2 arguments, found 1");
+    }
+
+    #[test]
+    fn explain_codes() {
+        let error = RuntimeError::DivisionByZero;
+        assert!(explain(error.code()).unwrap().contains("`/` or `%`"));
+        assert!(explain("r0001").is_some());
+        assert!(explain("R9999").is_none());
+    }
+}
@@ -1,276 +1,789 @@
 use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::mem;
+use std::path::PathBuf;
 use std::rc::Rc;
 
 use crate::ast::*;
+use crate::check::{self, ParseErrors};
 use crate::env::Env;
+use crate::error::{Position, RuntimeError, Type};
+use crate::integer::{Integer, Overflow, Precision};
+use crate::{prelude, source};
 
-#[derive(Debug)]
-pub struct Evaluator;
+/// Stack space left when `eval` moves to a new stack segment.
+pub const RED_ZONE: usize = 128 * 1024;
+
+/// Size of each new stack segment.
+pub const STACK_SIZE: usize = 4 * 1024 * 1024;
+
+/// A function call, named after the `let` that bound the function.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Frame {
+    pub name: String,
+    /// Location of the call site.
+    pub location: Location,
+}
+
+/// The calls in progress when an error was first produced.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Backtrace {
+    pub error: Error,
+    /// Each call with the values of its arguments, innermost first.
+    pub frames: Vec<(Frame, Vec<Term>)>,
+}
+
+/// How many consecutive calls to the same function are shown before the
+/// rest are summarized.
+const REPEATED_FRAMES: usize = 3;
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut frames = self.frames.iter().peekable();
+        while let Some((frame, arguments)) = frames.next() {
+            let mut repeated = 1;
+            let mut write = |frame: &Frame, arguments: &[Term]| {
+                let arguments =
+                    arguments.iter().map(inspect).collect::<Vec<_>>();
+                let Location {
+                    start,
+                    end,
+                    filename,
+                } = &frame.location;
+                writeln!(
+                    f,
+                    "  at {}({}) called from {filename}:{start}:{end}",
+                    frame.name,
+                    arguments.join(", "),
+                )
+            };
+            write(frame, arguments)?;
+            while let Some((next, arguments)) =
+                frames.next_if(|(next, _)| next.name == frame.name)
+            {
+                if repeated < REPEATED_FRAMES {
+                    write(next, arguments)?;
+                }
+                repeated += 1;
+            }
+            if repeated > REPEATED_FRAMES {
+                let hidden = repeated - REPEATED_FRAMES;
+                writeln!(f, "  ... {hidden} more calls to {}", frame.name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Hooks into the evaluation performed by an [`Evaluator`].
+pub trait Observer {
+    /// Called before a term is evaluated in `env`. Returning an error stops
+    /// the evaluation of the term, which evaluates to the error instead.
+    fn visit(
+        &mut self,
+        _env: &Rc<RefCell<Env>>,
+        _term: &Term,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called once the arguments are evaluated, before the body.
+    fn enter(&mut self, _frame: &Frame, _arguments: &[Term]) {}
+
+    /// Called with the result of the body, which may be an error.
+    fn exit(&mut self, _frame: &Frame, _result: &Term) {}
+}
+
+impl<T: Observer> Observer for Rc<RefCell<T>> {
+    fn visit(
+        &mut self,
+        env: &Rc<RefCell<Env>>,
+        term: &Term,
+    ) -> Result<(), Error> {
+        self.borrow_mut().visit(env, term)
+    }
+
+    fn enter(&mut self, frame: &Frame, arguments: &[Term]) {
+        self.borrow_mut().enter(frame, arguments)
+    }
+
+    fn exit(&mut self, frame: &Frame, result: &Term) {
+        self.borrow_mut().exit(frame, result)
+    }
+}
+
+#[derive(Default)]
+pub struct Evaluator {
+    names: HashMap<Location, String>,
+    observers: Vec<Box<dyn Observer>>,
+    /// Where `print` writes to, or stdout if unset.
+    output: Option<Box<dyn Write>>,
+    /// The calls in progress, innermost last.
+    stack: Vec<Frame>,
+    backtrace: Option<Backtrace>,
+    precision: Precision,
+    overflow: Overflow,
+    /// The bindings exported by each imported file, which is only evaluated
+    /// the first time it is imported.
+    modules: HashMap<PathBuf, Rc<Vec<(String, Term)>>>,
+    /// The files being imported, outermost first, with the names they are
+    /// reported by.
+    importing: Vec<(PathBuf, String)>,
+}
 
 impl Evaluator {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn observe(&mut self, observer: impl Observer + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// Redirects the output of `print` from stdout to `output`.
+    pub fn output(&mut self, output: impl Write + 'static) {
+        self.output = Some(Box::new(output));
+    }
+
+    /// Evaluates integers at `precision` instead of as 32-bit integers.
+    pub fn precision(&mut self, precision: Precision) {
+        self.precision = precision;
+    }
+
+    /// Whether arithmetic that overflows wraps around or fails.
+    pub fn overflow(&mut self, overflow: Overflow) {
+        self.overflow = overflow;
+    }
+
+    /// The calls in progress when the first error was produced.
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        self.backtrace.as_ref()
+    }
+
+    fn print(&mut self, value: &dyn fmt::Display) {
+        match &mut self.output {
+            Some(output) => {
+                let _ = writeln!(output, "{value}");
+            }
+            None => println!("{value}"),
+        }
+    }
+
+    pub fn eval(&mut self, env: &mut Rc<RefCell<Env>>, term: Term) -> Term {
+        // Deeply nested terms recurse once per level, so the stack grows on
+        // the heap instead of overflowing
+        stacker::maybe_grow(RED_ZONE, STACK_SIZE, || self.eval_term(env, term))
+    }
+
     #[inline]
-    pub fn eval(env: &mut Rc<RefCell<Env>>, term: Term) -> Term {
-        match term {
-            term @ Term::Int(_) => term,
-            term @ Term::Str(_) => term,
-            term @ Term::Bool(_) => term,
-            term @ Term::Error(_) => term,
-            term @ Term::Function(_) => term,
-            Term::If(term) => Self::eval_if(env, term),
-            Term::Let(term) => Self::eval_let(env, term),
-            Term::Var(term) => Self::eval_var(env, term),
-            Term::Call(term) => Self::eval_call(env, term),
-            Term::First(term) => Self::eval_first(env, term),
-            Term::Print(term) => Self::eval_print(env, term),
-            Term::Tuple(term) => Self::eval_tuple(env, term),
-            Term::Binary(term) => Self::eval_binary(env, term),
-            Term::Second(term) => Self::eval_second(env, term),
-        }
-    }
-
-    fn eval_if(env: &mut Rc<RefCell<Env>>, term: If) -> Term {
-        match Self::eval(env, *term.condition) {
-            Term::Bool(Bool { value: true, .. }) => Self::eval(env, *term.then),
+    fn eval_term(
+        &mut self,
+        env: &mut Rc<RefCell<Env>>,
+        mut term: Term,
+    ) -> Term {
+        for observer in &mut self.observers {
+            if let Err(error) = observer.visit(env, &term) {
+                return Term::Error(error);
+            }
+        }
+
+        let result = match &mut term {
+            Term::Int(term) => self.eval_int(mem::take(term)),
+            Term::Str(_)
+            | Term::Bool(_)
+            | Term::Error(_)
+            | Term::Function(_)
+            | Term::Builtin(_) => term,
+            Term::If(term) => self.eval_if(env, term),
+            Term::Let(term) => self.eval_let(env, term),
+            Term::Import(term) => self.eval_import(env, term),
+            Term::Var(term) => self.eval_var(env, mem::take(term)),
+            Term::Call(term) => self.eval_call(env, term),
+            Term::First(term) => self.eval_first(env, term),
+            Term::Print(term) => self.eval_print(env, term),
+            Term::Tuple(term) => self.eval_tuple(env, term),
+            Term::Binary(term) => self.eval_binary(env, term),
+            Term::Second(term) => self.eval_second(env, term),
+        };
+
+        if let Term::Error(error) = &result {
+            if self.backtrace.is_none() {
+                // Arguments are filled in as the error leaves each call
+                self.backtrace = Some(Backtrace {
+                    error: error.clone(),
+                    frames: self
+                        .stack
+                        .iter()
+                        .rev()
+                        .map(|frame| (frame.clone(), Vec::new()))
+                        .collect(),
+                });
+            }
+        }
+
+        result
+    }
+
+    /// `term`, or an error if it doesn't fit the precision.
+    pub(crate) fn eval_int(&mut self, term: Int) -> Term {
+        if self.precision.contains(&term.value) {
+            return Term::Int(term);
+        }
+        let kind = RuntimeError::OutOfRange {
+            value: term.value,
+            precision: self.precision,
+        };
+        Term::Error(Error::runtime(kind, term.location))
+    }
+
+    fn eval_if(&mut self, env: &mut Rc<RefCell<Env>>, term: &mut If) -> Term {
+        match self.eval(env, term.condition.take()) {
+            Term::Bool(Bool { value: true, .. }) => {
+                self.eval(env, term.then.take())
+            }
             Term::Bool(Bool { value: false, .. }) => {
-                Self::eval(env, *term.otherwise)
+                self.eval(env, term.otherwise.take())
             }
             term => {
-                let message = "Unexpected term".into();
-                let full_text = "Expected condition of type \"Bool\"".into();
-                error(term, message, full_text)
+                let found = Type::of(&term);
+                let kind = RuntimeError::TypeMismatch {
+                    position: Position::Condition,
+                    expected: vec![Type::Bool],
+                    found,
+                };
+                error(term, kind)
             }
         }
     }
 
-    fn eval_call(env: &mut Rc<RefCell<Env>>, term: Call) -> Term {
-        match Self::eval(env, *term.callee) {
-            Term::Function(Function {
-                parameters,
-                value,
-                location,
-            }) => {
+    fn eval_call(
+        &mut self,
+        env: &mut Rc<RefCell<Env>>,
+        term: &mut Call,
+    ) -> Term {
+        let callee = match *term.callee {
+            Term::Var(ref var) => Some(var.text.clone()),
+            _ => None,
+        };
+
+        match &mut self.eval(env, term.callee.take()) {
+            Term::Function(function) => {
+                let parameters = mem::take(&mut function.parameters);
+                let location = mem::take(&mut function.location);
+                let value = function.value.take();
+
                 let expected_args = parameters.len();
                 let found_args = term.arguments.len();
 
                 if expected_args != found_args {
-                    let message = "Argument count mismatch".into();
-                    let full_text = format!(
-                        "Expected {expected_args} arguments, found {found_args}"
-                    );
-                    let term = Term::Call(Call {
-                        callee: Box::new(Term::Function(Function {
-                            parameters,
-                            value,
-                            location,
-                        })),
-                        arguments: term.arguments,
-                        location: term.location,
-                    });
-                    return error(term, message, full_text);
+                    let kind = RuntimeError::ArityMismatch {
+                        expected: expected_args,
+                        found: found_args,
+                    };
+                    let location = mem::take(&mut term.location);
+                    return Term::Error(Error::runtime(kind, location));
                 }
 
+                let frame = Frame {
+                    name: self.function_name(&location, callee.as_deref()),
+                    location: mem::take(&mut term.location),
+                };
+
                 let mut env = Rc::new(RefCell::new(Env::extend(env.clone())));
-                let pairs = term.arguments.into_iter().zip(parameters.iter());
+                let pairs = mem::take(&mut term.arguments)
+                    .into_iter()
+                    .zip(parameters.iter());
 
                 for (arg, param) in pairs {
                     let name = &param.text;
-                    let value = Self::eval(&mut env, arg);
+                    let value = match self.eval(&mut env, arg) {
+                        value @ Term::Error(_) => return value,
+                        value => value,
+                    };
                     env.borrow_mut().set(name, value);
                 }
 
-                Self::eval(&mut env, *value)
+                if !self.observers.is_empty() {
+                    let arguments = arguments(&env, &parameters);
+                    for observer in &mut self.observers {
+                        observer.enter(&frame, &arguments);
+                    }
+                }
+
+                self.stack.push(frame);
+                let result = self.eval(&mut env, value);
+                let frame = self.stack.pop().unwrap();
+
+                if let Term::Error(error) = &result {
+                    self.unwind(error, &env, &parameters);
+                }
+
+                for observer in &mut self.observers {
+                    observer.exit(&frame, &result);
+                }
+
+                result
+            }
+            Term::Builtin(builtin) => {
+                let builtin = mem::take(builtin);
+                let arguments = mem::take(&mut term.arguments);
+                let location = mem::take(&mut term.location);
+                self.eval_builtin(env, builtin, arguments, location)
             }
             term => {
-                let message = "Unexpected term".into();
-                let full_text = "Expected function body or reference".into();
-                error(term, message, full_text)
+                let found = Type::of(term);
+                error(term.take(), RuntimeError::NotCallable { found })
+            }
+        }
+    }
+
+    /// Calls a builtin with its arguments evaluated in the caller's scope.
+    fn eval_builtin(
+        &mut self,
+        env: &mut Rc<RefCell<Env>>,
+        builtin: Builtin,
+        arguments: Vec<Term>,
+        location: Location,
+    ) -> Term {
+        let expected = prelude::signature(&builtin.name)
+            .map_or(0, |signature| signature.parameters.len());
+        if expected != arguments.len() {
+            let kind = RuntimeError::ArityMismatch {
+                expected,
+                found: arguments.len(),
+            };
+            return Term::Error(Error::runtime(kind, location));
+        }
+
+        let mut values = Vec::with_capacity(arguments.len());
+        for argument in arguments {
+            match self.eval(env, argument) {
+                value @ Term::Error(_) => return value,
+                value => values.push(value),
             }
         }
+        prelude::call(self, env, &builtin, values, location)
+    }
+
+    /// Fills in the arguments of the call the backtrace's error is leaving,
+    /// which is the innermost call still on the stack.
+    fn unwind(
+        &mut self,
+        error: &Error,
+        env: &Rc<RefCell<Env>>,
+        parameters: &[Var],
+    ) {
+        let depth = self.stack.len();
+        let Some(backtrace) = &mut self.backtrace else {
+            return;
+        };
+        let Some(index) = backtrace.frames.len().checked_sub(depth + 1) else {
+            return;
+        };
+        if backtrace.error == *error {
+            backtrace.frames[index].1 = arguments(env, parameters);
+        }
+    }
+
+    /// Names a function after the `let` that bound it, falling back to the
+    /// variable it was called through.
+    fn function_name(
+        &self,
+        location: &Location,
+        callee: Option<&str>,
+    ) -> String {
+        match (self.names.get(location), callee) {
+            (Some(name), _) => name.clone(),
+            (None, Some(callee)) => callee.into(),
+            (None, None) => "<anonymous>".into(),
+        }
     }
 
     #[inline]
-    fn eval_binary(env: &mut Rc<RefCell<Env>>, term: Binary) -> Term {
+    fn eval_binary(
+        &mut self,
+        env: &mut Rc<RefCell<Env>>,
+        term: &mut Binary,
+    ) -> Term {
         match term.op {
-            BinaryOp::Add => Self::eval_add(env, term),
-            BinaryOp::Sub => Self::eval_sub(env, term),
-            BinaryOp::Mul => Self::eval_mul(env, term),
-            BinaryOp::Div => Self::eval_div(env, term),
-            BinaryOp::Rem => Self::eval_rem(env, term),
-            BinaryOp::Eq => Self::eval_eq(env, term),
-            BinaryOp::Neq => Self::eval_neq(env, term),
-            BinaryOp::Lt => Self::eval_lt(env, term),
-            BinaryOp::Gt => Self::eval_gt(env, term),
-            BinaryOp::Lte => Self::eval_lte(env, term),
-            BinaryOp::Gte => Self::eval_gte(env, term),
-            BinaryOp::And => Self::eval_and(env, term),
-            BinaryOp::Or => Self::eval_or(env, term),
-        }
-    }
-
-    fn eval_eq(env: &mut Rc<RefCell<Env>>, term: Binary) -> Term {
-        let value = Self::eval(env, *term.lhs) == Self::eval(env, *term.rhs);
-        let location = term.location;
-        Term::Bool(Bool { value, location })
+            BinaryOp::Add => self.eval_add(env, term),
+            BinaryOp::Sub => self.eval_sub(env, term),
+            BinaryOp::Mul => self.eval_mul(env, term),
+            BinaryOp::Div => self.eval_div(env, term),
+            BinaryOp::Rem => self.eval_rem(env, term),
+            BinaryOp::Eq => self.eval_eq(env, term),
+            BinaryOp::Neq => self.eval_neq(env, term),
+            BinaryOp::Lt => self.eval_lt(env, term),
+            BinaryOp::Gt => self.eval_gt(env, term),
+            BinaryOp::Lte => self.eval_lte(env, term),
+            BinaryOp::Gte => self.eval_gte(env, term),
+            BinaryOp::And => self.eval_and(env, term),
+            BinaryOp::Or => self.eval_or(env, term),
+        }
     }
 
-    fn eval_neq(env: &mut Rc<RefCell<Env>>, term: Binary) -> Term {
-        let value = Self::eval(env, *term.lhs) != Self::eval(env, *term.rhs);
-        let location = term.location;
+    fn eval_eq(
+        &mut self,
+        env: &mut Rc<RefCell<Env>>,
+        term: &mut Binary,
+    ) -> Term {
+        let lhs = match self.eval(env, term.lhs.take()) {
+            term @ Term::Error(_) => return term,
+            term => term,
+        };
+        let rhs = match self.eval(env, term.rhs.take()) {
+            term @ Term::Error(_) => return term,
+            term => term,
+        };
+        let value = equals(&lhs, &rhs);
+        let location = mem::take(&mut term.location);
         Term::Bool(Bool { value, location })
     }
 
-    fn eval_let(env: &mut Rc<RefCell<Env>>, term: Let) -> Term {
-        match Self::eval(env, *term.value) {
+    fn eval_neq(
+        &mut self,
+        env: &mut Rc<RefCell<Env>>,
+        term: &mut Binary,
+    ) -> Term {
+        let mut result = self.eval_eq(env, term);
+        if let Term::Bool(result) = &mut result {
+            result.value = !result.value;
+        }
+        result
+    }
+
+    fn eval_let(&mut self, env: &mut Rc<RefCell<Env>>, term: &mut Let) -> Term {
+        match self.eval(env, term.value.take()) {
             value @ Term::Error(_) => value,
             value => {
                 let name = &term.name.text;
 
+                if let Term::Function(Function { ref location, .. }) = value {
+                    if !self.names.contains_key(location) {
+                        self.names.insert(location.clone(), name.clone());
+                    }
+                }
+
                 if name != "_" {
                     if let Some(value) = env.borrow_mut().set(name, value) {
-                        let message = "Redefined variable".into();
-                        let full_text =
-                            format!("Variable \"{name}\" is already defined");
-                        return error(value, message, full_text);
+                        let name = name.clone();
+                        return error(
+                            value,
+                            RuntimeError::Redefinition { name },
+                        );
                     }
                 }
 
-                Self::eval(env, *term.next)
+                self.eval(env, term.next.take())
+            }
+        }
+    }
+
+    fn eval_import(
+        &mut self,
+        env: &mut Rc<RefCell<Env>>,
+        term: &mut Import,
+    ) -> Term {
+        let exports = match self.import(&term.path) {
+            Ok(exports) => exports,
+            Err(error) => return Term::Error(error),
+        };
+
+        for (name, value) in exports.iter() {
+            let mut env = env.borrow_mut();
+            match env.local(name) {
+                // Files imported through several others bind the same values
+                Some(bound) if equals(bound, value) => {}
+                Some(_) => {
+                    let kind =
+                        RuntimeError::Redefinition { name: name.clone() };
+                    let location = mem::take(&mut term.path.location);
+                    return Term::Error(Error::runtime(kind, location));
+                }
+                None => {
+                    env.set(name, value.clone());
+                }
             }
         }
+
+        self.eval(env, term.next.take())
     }
 
-    fn eval_print(env: &mut Rc<RefCell<Env>>, term: Print) -> Term {
-        let term = Self::eval(env, *term.value);
+    /// The bindings exported by the file imported as `path`, evaluating it
+    /// if it wasn't imported before.
+    fn import(&mut self, path: &Str) -> Result<Rc<Vec<(String, Term)>>, Error> {
+        let importer = &path.location.filename;
+        let file = source::resolve(importer, &path.value);
+        let key = fs::canonicalize(&file).unwrap_or_else(|_| file.clone());
+        if let Some(exports) = self.modules.get(&key) {
+            return Ok(exports.clone());
+        }
+
+        // The file being run takes part in any cycle back to it
+        let outermost = self.importing.is_empty();
+        if outermost {
+            let root = PathBuf::from(importer);
+            let root = fs::canonicalize(&root).unwrap_or(root);
+            self.importing.push((root, importer.clone()));
+        }
+        let exports = self.import_file(path, key, file);
+        if outermost {
+            self.importing.clear();
+        }
+        exports
+    }
+
+    fn import_file(
+        &mut self,
+        path: &Str,
+        key: PathBuf,
+        file: PathBuf,
+    ) -> Result<Rc<Vec<(String, Term)>>, Error> {
+        let name = file.to_string_lossy().into_owned();
+        let importing = &self.importing;
+        if let Some(start) = importing.iter().position(|(file, _)| *file == key)
+        {
+            let mut files = importing[start..]
+                .iter()
+                .map(|(_, name)| name.clone())
+                .collect::<Vec<_>>();
+            files.push(name);
+            let kind = RuntimeError::ImportCycle { files };
+            return Err(Error::runtime(kind, path.location.clone()));
+        }
+
+        let module = load(path, &name)?;
+        self.importing.push((key.clone(), name));
+        let mut env = prelude::env();
+        let mut result = self.eval(&mut env, module.expression);
+        self.importing.pop();
+        if let Term::Error(error) = &mut result {
+            return Err(mem::take(error));
+        }
+
+        // Functions see the scope of their caller, so everything the file
+        // binds at the top level, imports included, is exported for them
+        let exports = env
+            .borrow()
+            .bindings()
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect::<Vec<_>>();
+        let exports = Rc::new(exports);
+        self.modules.insert(key, exports.clone());
+        Ok(exports)
+    }
+
+    fn eval_print(
+        &mut self,
+        env: &mut Rc<RefCell<Env>>,
+        term: &mut Print,
+    ) -> Term {
+        let term = self.eval(env, term.value.take());
 
         match term {
             Term::Error(_) => term,
             Term::Int(Int { ref value, .. }) => {
-                println!("{value}");
+                self.print(value);
                 term
             }
             Term::Str(Str { ref value, .. }) => {
-                println!("{value}");
+                self.print(value);
                 term
             }
             Term::Bool(Bool { ref value, .. }) => {
-                println!("{value}");
+                self.print(value);
                 term
             }
-            Term::Function(Function { .. }) => {
-                println!("<#closure>");
+            Term::Function(Function { .. }) | Term::Builtin(Builtin { .. }) => {
+                self.print(&"<#closure>");
                 term
             }
             _ => {
-                let message = "Unexpected term".into();
-                let full_text = "The term is not a first class value".into();
-                error(term, message, full_text)
+                let found = Type::of(&term);
+                error(term, RuntimeError::NotPrintable { found })
             }
         }
     }
 
-    fn eval_first(env: &mut Rc<RefCell<Env>>, term: First) -> Term {
-        match Self::eval(env, *term.value) {
-            Term::Tuple(v) => Self::eval(env, *v.first),
+    fn eval_first(
+        &mut self,
+        env: &mut Rc<RefCell<Env>>,
+        term: &mut First,
+    ) -> Term {
+        match self.eval(env, term.value.take()) {
+            // Tuples only hold values, so the field is returned as is
+            Term::Tuple(ref mut v) => v.first.take(),
             term => {
-                let message = "Unexpected term".into();
-                let full_text = "The first function expects a tuple".into();
-                error(term, message, full_text)
+                let found = Type::of(&term);
+                let kind = RuntimeError::TypeMismatch {
+                    position: Position::First,
+                    expected: vec![Type::Tuple],
+                    found,
+                };
+                error(term, kind)
             }
         }
     }
 
-    fn eval_second(env: &mut Rc<RefCell<Env>>, term: Second) -> Term {
-        match Self::eval(env, *term.value) {
-            Term::Tuple(v) => Self::eval(env, *v.second),
+    fn eval_second(
+        &mut self,
+        env: &mut Rc<RefCell<Env>>,
+        term: &mut Second,
+    ) -> Term {
+        match self.eval(env, term.value.take()) {
+            Term::Tuple(ref mut v) => v.second.take(),
             term => {
-                let message = "Unexpected term".into();
-                let full_text = "The second function expects a tuple".into();
-                error(term, message, full_text)
+                let found = Type::of(&term);
+                let kind = RuntimeError::TypeMismatch {
+                    position: Position::Second,
+                    expected: vec![Type::Tuple],
+                    found,
+                };
+                error(term, kind)
             }
         }
     }
 
-    fn eval_tuple(env: &mut Rc<RefCell<Env>>, term: Tuple) -> Term {
+    fn eval_tuple(
+        &mut self,
+        env: &mut Rc<RefCell<Env>>,
+        term: &mut Tuple,
+    ) -> Term {
+        let first = match self.eval(env, term.first.take()) {
+            term @ Term::Error(_) => return term,
+            term => term,
+        };
+        let second = match self.eval(env, term.second.take()) {
+            term @ Term::Error(_) => return term,
+            term => term,
+        };
         Term::Tuple(Tuple {
-            first: Box::new(Self::eval(env, *term.first)),
-            second: Box::new(Self::eval(env, *term.second)),
-            location: term.location,
+            first: Box::new(first),
+            second: Box::new(second),
+            location: mem::take(&mut term.location),
         })
     }
 
-    fn eval_var(env: &mut Rc<RefCell<Env>>, term: Var) -> Term {
+    fn eval_var(&mut self, env: &mut Rc<RefCell<Env>>, term: Var) -> Term {
         let Var { text, location } = term;
         let value = env.borrow().get(&text);
+        // Bindings always hold values, so there is nothing left to evaluate
         match value {
-            Some(term) => Self::eval(env, term),
+            Some(term) => term,
             None => {
-                let message = "Undefined variable".into();
-                let full_text = format!("Undefined variable \"{text}\"");
-                error(Term::Var(Var { text, location }), message, full_text)
+                let kind = RuntimeError::UndefinedVariable { name: text };
+                Term::Error(Error::runtime(kind, location))
             }
         }
     }
 
-    fn eval_add(env: &mut Rc<RefCell<Env>>, term: Binary) -> Term {
-        let lhs = Self::eval(env, *term.lhs);
-        let rhs = Self::eval(env, *term.rhs);
-        match (lhs, rhs) {
-            (term @ Term::Error(_), _) => term,
-            (_, term @ Term::Error(_)) => term,
-            (Term::Int(lhs @ Int { .. }), Term::Int(rhs @ Int { .. })) => {
-                let value = lhs.value.wrapping_add(rhs.value);
-                let location = lhs.location.merge(rhs.location);
-                Term::Int(Int { value, location })
+    /// Applies an arithmetic `op` at the evaluator's precision, failing on
+    /// division by zero and, unless wrapping, on overflow.
+    pub(crate) fn arithmetic(
+        &self,
+        op: BinaryOp,
+        lhs: Integer,
+        rhs: Integer,
+    ) -> Result<Integer, RuntimeError> {
+        let precision = self.precision;
+        let (value, overflowed) = match op {
+            BinaryOp::Div | BinaryOp::Rem if rhs.is_zero() => {
+                return Err(RuntimeError::DivisionByZero);
             }
-            (Term::Str(lhs @ Str { .. }), Term::Str(rhs @ Str { .. })) => {
+            BinaryOp::Add => precision.overflowing_add(&lhs, &rhs),
+            BinaryOp::Sub => precision.overflowing_sub(&lhs, &rhs),
+            BinaryOp::Mul => precision.overflowing_mul(&lhs, &rhs),
+            BinaryOp::Div => precision.overflowing_div(&lhs, &rhs),
+            BinaryOp::Rem => precision.overflowing_rem(&lhs, &rhs),
+            _ => unreachable!("{op:?} is not an arithmetic operator"),
+        };
+        match overflowed && self.overflow == Overflow::Error {
+            true => Err(RuntimeError::Overflow {
+                op,
+                lhs,
+                rhs,
+                precision,
+            }),
+            false => Ok(value),
+        }
+    }
+
+    fn eval_add(
+        &mut self,
+        env: &mut Rc<RefCell<Env>>,
+        term: &mut Binary,
+    ) -> Term {
+        let mut lhs = match self.eval(env, term.lhs.take()) {
+            term @ Term::Error(_) => return term,
+            term => term,
+        };
+        let mut rhs = match self.eval(env, term.rhs.take()) {
+            term @ Term::Error(_) => return term,
+            term => term,
+        };
+        match (&mut lhs, &mut rhs) {
+            (Term::Int(lhs), Term::Int(rhs)) => {
+                let (lhs, rhs) = (mem::take(lhs), mem::take(rhs));
+                match self.arithmetic(BinaryOp::Add, lhs.value, rhs.value) {
+                    Ok(value) => {
+                        let location = lhs.location.merge(rhs.location);
+                        Term::Int(Int { value, location })
+                    }
+                    Err(kind) => {
+                        let location = mem::take(&mut term.location);
+                        Term::Error(Error::runtime(kind, location))
+                    }
+                }
+            }
+            (Term::Str(lhs), Term::Str(rhs)) => {
+                let (lhs, rhs) = (mem::take(lhs), mem::take(rhs));
                 let value = lhs.value + &rhs.value;
                 let location = lhs.location.merge(rhs.location);
                 Term::Str(Str { value, location })
             }
-            (Term::Str(lhs @ Str { .. }), Term::Int(rhs @ Int { .. })) => {
+            (Term::Str(lhs), Term::Int(rhs)) => {
+                let (lhs, rhs) = (mem::take(lhs), mem::take(rhs));
                 let value = lhs.value + &rhs.value.to_string();
                 let location = lhs.location.merge(rhs.location);
                 Term::Str(Str { value, location })
             }
-            (Term::Str(lhs @ Str { .. }), Term::Bool(rhs @ Bool { .. })) => {
+            (Term::Str(lhs), Term::Bool(rhs)) => {
+                let (lhs, rhs) = (mem::take(lhs), mem::take(rhs));
                 let value = lhs.value + &rhs.value.to_string();
                 let location = lhs.location.merge(rhs.location);
                 Term::Str(Str { value, location })
             }
-            (Term::Int(lhs @ Int { .. }), Term::Str(rhs @ Str { .. })) => {
+            (Term::Int(lhs), Term::Str(rhs)) => {
+                let (lhs, rhs) = (mem::take(lhs), mem::take(rhs));
                 let value = lhs.value.to_string() + &rhs.value;
                 let location = lhs.location.merge(rhs.location);
                 Term::Str(Str { value, location })
             }
-            (Term::Bool(lhs @ Bool { .. }), Term::Str(rhs @ Str { .. })) => {
+            (Term::Bool(lhs), Term::Str(rhs)) => {
+                let (lhs, rhs) = (mem::take(lhs), mem::take(rhs));
                 let value = lhs.value.to_string() + &rhs.value;
                 let location = lhs.location.merge(rhs.location);
                 Term::Str(Str { value, location })
             }
             (Term::Bool(Bool { .. }), rhs) => {
-                let message = "Unexpected right operand".into();
-                let full_text = "Expected operand of type \"Str\"".into();
-                error(rhs, message, full_text)
+                let expected = vec![Type::Str];
+                mismatch(rhs.take(), Position::RightOperand, expected)
             }
             (Term::Int(Int { .. }), rhs) => {
-                let message = "Unexpected right operand".into();
-                let full_text =
-                    "Expected operand of type \"Int\" or \"Str\"".into();
-                error(rhs, message, full_text)
+                let expected = vec![Type::Int, Type::Str];
+                mismatch(rhs.take(), Position::RightOperand, expected)
             }
             (Term::Str(Str { .. }), rhs) => {
-                let message = "Unexpected right operand".into();
-                let full_text =
-                    "Expected operand of type \"Bool\", \"Int\", or \"Str\""
-                        .into();
-                error(rhs, message, full_text)
+                let expected = vec![Type::Bool, Type::Int, Type::Str];
+                mismatch(rhs.take(), Position::RightOperand, expected)
             }
             (lhs, _) => {
-                let message = "Unexpected left operand".into();
-                let full_text =
-                    "Expected operand of type \"Bool\", \"Int\", or \"Str\""
-                        .into();
-                error(lhs, message, full_text)
+                let expected = vec![Type::Bool, Type::Int, Type::Str];
+                mismatch(lhs.take(), Position::LeftOperand, expected)
             }
         }
     }
@@ -279,257 +792,322 @@ impl Evaluator {
 macro_rules! impl_binary_op {
     ($($id:ident [($lhs:ident, $rhs:ident) => $out:ident] = $ev:expr;)*) => {
         impl Evaluator {$(
-            fn $id(env: &mut Rc<RefCell<Env>>, term: Binary) -> Term {
-                let lhs = match Self::eval(env, *term.lhs) {
+            fn $id(
+                &mut self,
+                env: &mut Rc<RefCell<Env>>,
+                term: &mut Binary,
+            ) -> Term {
+                let lhs = match self.eval(env, term.lhs.take()) {
                     term @ Term::Error(_) => return term,
-                    Term::$lhs($lhs { value, .. }) => value,
+                    Term::$lhs(ref mut lhs) => mem::take(&mut lhs.value),
                     term => {
-                        let message = "Unexpected left operand".into();
-                        let full_text = format!(
-                            "Expected operand of type \"{}\"",
-                            stringify!($lhs),
-                        );
-                        return error(term, message, full_text);
+                        let expected = vec![Type::$lhs];
+                        return mismatch(term, Position::LeftOperand, expected);
                     }
                 };
-                let rhs = match Self::eval(env, *term.rhs) {
+                let rhs = match self.eval(env, term.rhs.take()) {
                     term @ Term::Error(_) => return term,
-                    Term::$rhs($rhs { value, .. }) => value,
+                    Term::$rhs(ref mut rhs) => mem::take(&mut rhs.value),
                     term => {
-                        let message = "Unexpected right operand".into();
-                        let full_text = format!(
-                            "Expected operand of type \"{}\"",
-                            stringify!($rhs),
-                        );
-                        return error(term, message, full_text);
+                        let expected = vec![Type::$rhs];
+                        return mismatch(term, Position::RightOperand, expected);
                     }
                 };
                 #[allow(clippy::redundant_closure_call)]
-                let value = $ev(lhs, rhs);
-                let location = term.location;
-                Term::$out($out { value, location })
+                let value: Result<_, RuntimeError> = $ev(&*self, lhs, rhs);
+                let location = mem::take(&mut term.location);
+                match value {
+                    Ok(value) => Term::$out($out { value, location }),
+                    Err(kind) => Term::Error(Error::runtime(kind, location)),
+                }
             }
         )*}
     };
 }
 
 impl_binary_op! {
-    eval_sub[(Int, Int) => Int] = i32::wrapping_sub;
-    eval_mul[(Int, Int) => Int] = i32::wrapping_mul;
-    eval_div[(Int, Int) => Int] = i32::wrapping_div;
-    eval_rem[(Int, Int) => Int] = i32::wrapping_rem;
-    eval_lt[(Int, Int) => Bool] = |lhs, rhs| lhs < rhs;
-    eval_gt[(Int, Int) => Bool] = |lhs, rhs| lhs > rhs;
-    eval_lte[(Int, Int) => Bool] = |lhs, rhs| lhs <= rhs;
-    eval_gte[(Int, Int) => Bool] = |lhs, rhs| lhs >= rhs;
-    eval_or[(Bool, Bool) => Bool] = |lhs, rhs| lhs || rhs;
-    eval_and[(Bool, Bool) => Bool] = |lhs, rhs| lhs && rhs;
+    eval_sub[(Int, Int) => Int] = |ev: &Self, lhs, rhs| ev.arithmetic(BinaryOp::Sub, lhs, rhs);
+    eval_mul[(Int, Int) => Int] = |ev: &Self, lhs, rhs| ev.arithmetic(BinaryOp::Mul, lhs, rhs);
+    eval_div[(Int, Int) => Int] = |ev: &Self, lhs, rhs| ev.arithmetic(BinaryOp::Div, lhs, rhs);
+    eval_rem[(Int, Int) => Int] = |ev: &Self, lhs, rhs| ev.arithmetic(BinaryOp::Rem, lhs, rhs);
+    eval_lt[(Int, Int) => Bool] = |_, lhs, rhs| Ok(lhs < rhs);
+    eval_gt[(Int, Int) => Bool] = |_, lhs, rhs| Ok(lhs > rhs);
+    eval_lte[(Int, Int) => Bool] = |_, lhs, rhs| Ok(lhs <= rhs);
+    eval_gte[(Int, Int) => Bool] = |_, lhs, rhs| Ok(lhs >= rhs);
+    eval_or[(Bool, Bool) => Bool] = |_, lhs, rhs| Ok(lhs || rhs);
+    eval_and[(Bool, Bool) => Bool] = |_, lhs, rhs| Ok(lhs && rhs);
+}
+
+/// Loads the file imported as `path` from `file`, failing with the first
+/// syntax error in it, located in that file.
+fn load(path: &Str, file: &str) -> Result<File, Error> {
+    let module = source::load(file).map_err(|error| {
+        match error.downcast::<Error>() {
+            Ok(error) => *error,
+            Err(error) => {
+                let kind = RuntimeError::ImportFailed {
+                    path: path.value.clone(),
+                    reason: error.to_string(),
+                };
+                Error::runtime(kind, path.location.clone())
+            }
+        }
+    })?;
+    if let Err(ParseErrors(mut errors)) = check::parse_errors(&module) {
+        return Err(errors.swap_remove(0));
+    }
+    Ok(module)
 }
 
-pub fn error(term: Term, message: String, full_text: String) -> Term {
+/// Renders an evaluated term the way it would be written in source code.
+pub fn inspect(term: &Term) -> String {
+    match term {
+        Term::Int(Int { value, .. }) => value.to_string(),
+        Term::Str(Str { value, .. }) => crate::parser::escape(value),
+        Term::Bool(Bool { value, .. }) => value.to_string(),
+        Term::Function(Function { .. }) | Term::Builtin(Builtin { .. }) => {
+            "<#closure>".into()
+        }
+        Term::Tuple(Tuple { first, second, .. }) => {
+            format!("({}, {})", inspect(first), inspect(second))
+        }
+        Term::Error(Error { message, .. }) => format!("<error: {message}>"),
+        _ => "<term>".into(),
+    }
+}
+
+/// Compares two values regardless of where they were produced. Closures are
+/// only equal to themselves.
+fn equals(lhs: &Term, rhs: &Term) -> bool {
+    match (lhs, rhs) {
+        (Term::Int(lhs), Term::Int(rhs)) => lhs.value == rhs.value,
+        (Term::Str(lhs), Term::Str(rhs)) => lhs.value == rhs.value,
+        (Term::Bool(lhs), Term::Bool(rhs)) => lhs.value == rhs.value,
+        (Term::Tuple(lhs), Term::Tuple(rhs)) => {
+            equals(&lhs.first, &rhs.first) && equals(&lhs.second, &rhs.second)
+        }
+        (Term::Function(lhs), Term::Function(rhs)) => {
+            lhs.location == rhs.location
+        }
+        (Term::Builtin(lhs), Term::Builtin(rhs)) => lhs.name == rhs.name,
+        _ => false,
+    }
+}
+
+fn mismatch(term: Term, position: Position, expected: Vec<Type>) -> Term {
+    let found = Type::of(&term);
+    let kind = RuntimeError::TypeMismatch {
+        position,
+        expected,
+        found,
+    };
+    error(term, kind)
+}
+
+/// Replaces `term` with an error of the given kind at its location, unless
+/// it is already an error.
+pub fn error(term: Term, kind: RuntimeError) -> Term {
     match term {
         term @ Term::Error(_) => term,
-        Term::If(If { location, .. })
-        | Term::Let(Let { location, .. })
-        | Term::Int(Int { location, .. })
-        | Term::Str(Str { location, .. })
-        | Term::Var(Var { location, .. })
-        | Term::Bool(Bool { location, .. })
-        | Term::Call(Call { location, .. })
-        | Term::First(First { location, .. })
-        | Term::Print(Print { location, .. })
-        | Term::Tuple(Tuple { location, .. })
-        | Term::Binary(Binary { location, .. })
-        | Term::Second(Second { location, .. })
-        | Term::Function(Function { location, .. }) => Term::Error(Error {
-            message,
-            full_text,
-            location,
-        }),
+        mut term => {
+            let location = mem::take(term.location_mut());
+            Term::Error(Error::runtime(kind, location))
+        }
     }
 }
 
+/// The values bound to `parameters` in the scope of a call.
+fn arguments(env: &Rc<RefCell<Env>>, parameters: &[Var]) -> Vec<Term> {
+    let env = env.borrow();
+    let values = parameters
+        .iter()
+        .filter_map(|parameter| env.local(&parameter.text));
+    values.cloned().collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ast::build::*;
 
     #[test]
     fn eval_int() {
         let mut env = Default::default();
-        let value = Default::default();
-        let location = Default::default();
-        let term = Term::Int(Int { value, location });
-        let result = Evaluator::eval(&mut env, term.clone());
+        let term = int(0);
+        let result = Evaluator::new().eval(&mut env, term.clone());
         assert_eq!(term, result);
     }
 
     #[test]
     fn eval_str() {
         let mut env = Default::default();
-        let value = Default::default();
-        let location = Default::default();
-        let term = Term::Str(Str { value, location });
-        let result = Evaluator::eval(&mut env, term.clone());
+        let term = string("");
+        let result = Evaluator::new().eval(&mut env, term.clone());
         assert_eq!(term, result);
     }
 
     #[test]
     fn eval_bool() {
         let mut env = Default::default();
-        let value = Default::default();
-        let location = Default::default();
-        let term = Term::Bool(Bool { value, location });
-        let result = Evaluator::eval(&mut env, term.clone());
+        let term = boolean(false);
+        let result = Evaluator::new().eval(&mut env, term.clone());
         assert_eq!(term, result);
     }
 
     #[test]
     fn eval_error() {
         let mut env = Default::default();
-        let message = Default::default();
-        let full_text = Default::default();
-        let location = Default::default();
-        let term = Term::Error(Error {
-            message,
-            full_text,
-            location,
-        });
-        let result = Evaluator::eval(&mut env, term.clone());
+        let term = parse_error("Unexpected token", "expected an expression");
+        let result = Evaluator::new().eval(&mut env, term.clone());
         assert_eq!(term, result);
     }
 
     #[test]
     fn eval_tuple() {
         let mut env = Default::default();
-        let term = Term::Tuple(Tuple {
-            first: Box::new(Term::Int(Int {
-                value: 1,
-                location: Default::default(),
-            })),
-            second: Box::new(Term::Int(Int {
-                value: 2,
-                location: Default::default(),
-            })),
-            location: Default::default(),
-        });
-        let result = Evaluator::eval(&mut env, term.clone());
+        let term = tuple(int(1), int(2));
+        let result = Evaluator::new().eval(&mut env, term.clone());
         assert_eq!(term, result);
     }
 
     #[test]
     fn eval_first() {
         let mut env = Default::default();
-        let term = Term::First(First {
-            value: Box::new(Term::Tuple(Tuple {
-                first: Box::new(Term::Int(Int {
-                    value: 1,
-                    location: Default::default(),
-                })),
-                second: Box::new(Term::Int(Int {
-                    value: 2,
-                    location: Default::default(),
-                })),
-                location: Default::default(),
-            })),
-            location: Default::default(),
-        });
-        let result = Evaluator::eval(&mut env, term.clone());
-        let term = Term::Int(Int {
-            value: 1,
-            location: Default::default(),
-        });
-        assert_eq!(term, result);
+        let term = first(tuple(int(1), int(2)));
+        let result = Evaluator::new().eval(&mut env, term);
+        assert_eq!(int(1), result);
     }
 
     #[test]
     fn eval_second() {
         let mut env = Default::default();
-        let term = Term::Second(Second {
-            value: Box::new(Term::Tuple(Tuple {
-                first: Box::new(Term::Int(Int {
-                    value: 1,
-                    location: Default::default(),
-                })),
-                second: Box::new(Term::Int(Int {
-                    value: 2,
-                    location: Default::default(),
-                })),
-                location: Default::default(),
-            })),
-            location: Default::default(),
-        });
-        let result = Evaluator::eval(&mut env, term.clone());
-        let term = Term::Int(Int {
-            value: 2,
-            location: Default::default(),
-        });
-        assert_eq!(term, result);
+        let term = second(tuple(int(1), int(2)));
+        let result = Evaluator::new().eval(&mut env, term);
+        assert_eq!(int(2), result);
     }
 
     #[test]
     fn eval_let_var() {
         let mut env = Default::default();
-        let term = Term::Let(Let {
-            name: Var {
-                text: "foo".into(),
-                location: Default::default(),
-            },
-            value: Box::new(Term::Int(Int {
-                value: 42,
-                location: Default::default(),
-            })),
-            next: Box::new(Term::Var(Var {
-                text: "foo".into(),
-                location: Default::default(),
-            })),
-            location: Default::default(),
-        });
-        let result = Evaluator::eval(&mut env, term);
-        let term = Term::Int(Int {
-            value: 42,
-            location: Default::default(),
-        });
-        assert_eq!(term, result);
+        let term = let_("foo", int(42), var("foo"));
+        let result = Evaluator::new().eval(&mut env, term);
+        assert_eq!(int(42), result);
     }
 
     #[test]
     fn eval_let_call() {
         let mut env = Default::default();
-        let term = Term::Let(Let {
-            name: Var {
-                text: "id".into(),
-                location: Default::default(),
-            },
-            value: Box::new(Term::Function(Function {
-                parameters: vec![Var {
-                    text: "x".into(),
-                    location: Default::default(),
-                }],
-                value: Box::new(Term::Var(Var {
-                    text: "x".into(),
-                    location: Default::default(),
-                })),
-                location: Default::default(),
-            })),
-            next: Box::new(Term::Call(Call {
-                callee: Box::new(Term::Var(Var {
-                    text: "id".into(),
-                    location: Default::default(),
-                })),
-                arguments: vec![Term::Int(Int {
-                    value: 42,
-                    location: Default::default(),
-                })],
-                location: Default::default(),
-            })),
-            location: Default::default(),
-        });
-        let result = Evaluator::eval(&mut env, term);
-        let term = Term::Int(Int {
-            value: 42,
-            location: Default::default(),
-        });
-        assert_eq!(term, result);
+        let term = let_("id", fun(["x"], var("x")), call(var("id"), [int(42)]));
+        let result = Evaluator::new().eval(&mut env, term);
+        assert_eq!(int(42), result);
+    }
+
+    #[test]
+    fn eval_var_value() {
+        #[derive(Default)]
+        struct Visits(usize);
+
+        impl Observer for Visits {
+            fn visit(
+                &mut self,
+                _env: &Rc<RefCell<Env>>,
+                _term: &Term,
+            ) -> Result<(), Error> {
+                self.0 += 1;
+                Ok(())
+            }
+        }
+
+        // The tuple and its elements are visited once, when bound
+        let source = "let pair = (1, 2); pair";
+        let file = crate::parser::parse(source, "pair.rinha").unwrap();
+        let visits = Rc::new(RefCell::new(Visits::default()));
+        let mut evaluator = Evaluator::new();
+        evaluator.observe(visits.clone());
+        evaluator.eval(&mut Default::default(), file.expression);
+        assert_eq!(visits.borrow().0, 5);
+    }
+
+    #[test]
+    fn eval_deep_recursion() {
+        // Every pending call adds a scope that lookups and drops walk through
+        let source = "let sum = fn (total, n) => {
+          if (n == 0) { total } else { sum(total + n, n - 1) }
+        };
+        sum(0, 3000)";
+        let file = crate::parser::parse(source, "sum.rinha").unwrap();
+        let result =
+            Evaluator::new().eval(&mut Default::default(), file.expression);
+        let Term::Int(result) = &result else {
+            panic!("evaluated to {}", inspect(&result));
+        };
+        assert_eq!(result.value, 4501500.into());
+    }
+
+    #[test]
+    fn eval_imports() {
+        let dir = std::env::temp_dir()
+            .join(format!("rinha-imports-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let files = [
+            (
+                "lib.rinha",
+                "let double = fn (n) => { n * 2 };\nlet base = 10;\n0",
+            ),
+            (
+                "uses.rinha",
+                "import \"./lib.rinha\";\nlet four = double(2);\n0",
+            ),
+            ("self.rinha", "import \"self.rinha\";\n0"),
+            ("syntax.rinha", "let x = ;\n0"),
+        ];
+        for (name, source) in files {
+            fs::write(dir.join(name), source).unwrap();
+        }
+        let main = dir.join("main.rinha");
+        let main = main.to_str().unwrap();
+
+        // Files imported twice are evaluated once and bind the same values
+        let source = "import \"lib.rinha\"; import \"uses.rinha\"; \
+                      double(base) + four";
+        let file = crate::parser::parse(source, main).unwrap();
+        let mut evaluator = Evaluator::new();
+        let result = evaluator.eval(&mut Default::default(), file.expression);
+        assert_eq!(inspect(&result), "24");
+        assert_eq!(evaluator.modules.len(), 2);
+
+        let cases = [
+            (
+                "let base = 1; import \"lib.rinha\"; 0",
+                "R0002",
+                "main.rinha",
+            ),
+            ("import \"missing.rinha\"; 0", "R0011", "main.rinha"),
+            ("import \"self.rinha\"; 0", "R0010", "self.rinha"),
+            ("import \"main.rinha\"; 0", "R0010", "main.rinha"),
+        ];
+        for (source, code, filename) in cases {
+            let file = crate::parser::parse(source, main).unwrap();
+            let result =
+                Evaluator::new().eval(&mut Default::default(), file.expression);
+            let Term::Error(error) = &result else {
+                panic!("{source} evaluated to {}", inspect(&result));
+            };
+            assert_eq!(error.kind.as_ref().map(|kind| kind.code()), Some(code));
+            assert!(error.location.filename.ends_with(filename), "{source}");
+        }
+
+        // Syntax errors are reported in the imported file
+        let file = crate::parser::parse("import \"syntax.rinha\"; 0", main);
+        let result = Evaluator::new()
+            .eval(&mut Default::default(), file.unwrap().expression);
+        let Term::Error(error) = &result else {
+            panic!("evaluated to {}", inspect(&result));
+        };
+        assert_eq!(error.message, "Unexpected token");
+        assert!(error.location.filename.ends_with("syntax.rinha"));
+
+        fs::remove_dir_all(&dir).unwrap();
     }
 
     macro_rules! impl_eval_binary {
@@ -539,141 +1117,343 @@ mod tests {
             #[test]
             fn $id() {$(
                 let mut env = Default::default();
-                let op = BinaryOp::$opd;
-                let value = $op1;
-                let location = Default::default();
-                let lhs = Box::new(Term::$lhs($lhs { value, location }));
-                let value = $op2;
-                let location = Default::default();
-                let rhs = Box::new(Term::$rhs($rhs { value, location }));
-                let location = Default::default();
-                let term = Term::Binary(Binary {
-                    lhs,
-                    op,
-                    rhs,
-                    location,
-                });
-                let result = Evaluator::eval(&mut env, term);
-                let value = $res;
-                let location = Default::default();
-                let term = Term::$out($out { value, location });
-                assert_eq!(term, result);
+                let term = binary($lhs($op1), BinaryOp::$opd, $rhs($op2));
+                let result = Evaluator::new().eval(&mut env, term);
+                assert_eq!($out($res), result);
             )*})*
         };
     }
 
     impl_eval_binary! {
-        eval_sub[Sub; (Int, Int) => Int] = {
+        eval_sub[Sub; (int, int) => int] = {
             (1, 1) => 0;
             (1, -1) => 2;
             (-1, -1) => 0;
             (-1, i32::MAX) => i32::MIN;
         };
 
-        eval_mul[Mul; (Int, Int) => Int] = {
+        eval_mul[Mul; (int, int) => int] = {
             (2, 1) => 2;
             (1, -1) => -1;
             (-1, -1) => 1;
             (2, i32::MAX) => -2;
         };
 
-        eval_div[Div; (Int, Int) => Int] = {
+        eval_div[Div; (int, int) => int] = {
             (2, 2) => 1;
             (1, -1) => -1;
             (-1, -1) => 1;
             (2, i32::MAX) => 0;
         };
 
-        eval_rem[Rem; (Int, Int) => Int] = {
+        eval_rem[Rem; (int, int) => int] = {
             (0, 2) => 0;
             (1, 2) => 1;
             (2, 2) => 0;
             (3, 2) => 1;
         };
 
-        eval_eq_int[Eq; (Int, Int) => Bool] = {
+        eval_eq_int[Eq; (int, int) => boolean] = {
             (1, 1) => true;
             (1, 2) => false;
         };
 
-        eval_eq_bool[Eq; (Bool, Bool) => Bool] = {
+        eval_eq_bool[Eq; (boolean, boolean) => boolean] = {
             (true, true) => true;
             (true, false) => false;
         };
 
-        eval_neq_int[Neq; (Int, Int) => Bool] = {
+        eval_neq_int[Neq; (int, int) => boolean] = {
             (1, 1) => false;
             (1, 2) => true;
         };
 
-        eval_neq_bool[Neq; (Bool, Bool) => Bool] = {
+        eval_neq_bool[Neq; (boolean, boolean) => boolean] = {
             (true, true) => false;
             (true, false) => true;
         };
 
-        eval_lt[Lt; (Int, Int) => Bool] = {
+        eval_lt[Lt; (int, int) => boolean] = {
             (1, 1) => false;
             (2, 1) => false;
             (1, 2) => true;
         };
 
-        eval_gt[Gt; (Int, Int) => Bool] = {
+        eval_gt[Gt; (int, int) => boolean] = {
             (1, 1) => false;
             (2, 1) => true;
             (1, 2) => false;
         };
 
-        eval_lte[Lte; (Int, Int) => Bool] = {
+        eval_lte[Lte; (int, int) => boolean] = {
             (1, 1) => true;
             (2, 1) => false;
             (1, 2) => true;
         };
 
-        eval_gte[Gte; (Int, Int) => Bool] = {
+        eval_gte[Gte; (int, int) => boolean] = {
             (1, 1) => true;
             (2, 1) => true;
             (1, 2) => false;
         };
 
-        eval_or[Or; (Bool, Bool) => Bool] = {
+        eval_or[Or; (boolean, boolean) => boolean] = {
             (false, false) => false;
             (false, true) => true;
             (true, false) => true;
             (true, true) => true;
         };
 
-        eval_and[And; (Bool, Bool) => Bool] = {
+        eval_and[And; (boolean, boolean) => boolean] = {
             (false, false) => false;
             (false, true) => false;
             (true, false) => false;
             (true, true) => true;
         };
 
-        eval_add_int_int[Add; (Int, Int) => Int] = {
+        eval_add_int_int[Add; (int, int) => int] = {
             (1, 1) => 2;
             (1, -1) => 0;
             (-1, -1) => -2;
             (1, i32::MAX) => i32::MIN;
         };
 
-        eval_add_int_str[Add; (Int, Str) => Str] = {
-            (1, "bar".into()) => "1bar".into();
+        eval_add_int_str[Add; (int, string) => string] = {
+            (1, "bar") => "1bar";
         };
 
-        eval_add_str_int[Add; (Str, Int) => Str] = {
-            ("foo".into(), 1) => "foo1".into();
+        eval_add_str_int[Add; (string, int) => string] = {
+            ("foo", 1) => "foo1";
         };
 
-        eval_add_str_str[Add; (Str, Str) => Str] = {
-            ("foo".into(), "bar".into()) => "foobar".into();
+        eval_add_str_str[Add; (string, string) => string] = {
+            ("foo", "bar") => "foobar";
         };
 
-        eval_add_str_bool[Add; (Str, Bool) => Str] = {
-            ("foo".into(), true) => "footrue".into();
+        eval_add_str_bool[Add; (string, boolean) => string] = {
+            ("foo", true) => "footrue";
         };
 
-        eval_add_bool_str[Add; (Bool, Str) => Str] = {
-            (false, "bar".into()) => "falsebar".into();
+        eval_add_bool_str[Add; (boolean, string) => string] = {
+            (false, "bar") => "falsebar";
         };
     }
+
+    #[test]
+    fn eval_backtrace() {
+        let source = "\
+let f = fn (n) => { if (n < 1) { 1 + true } else { f(n - 1) } };
+let main = fn () => { f(4) };
+main()";
+        let file = crate::parser::parse(source, "f.rinha").unwrap();
+        let mut evaluator = Evaluator::new();
+        let result = evaluator.eval(&mut Default::default(), file.expression);
+        let backtrace = evaluator.backtrace().unwrap();
+        assert_eq!(result, Term::Error(backtrace.error.clone()));
+
+        let expected = [
+            "  at f(0) called from f.rinha:51:59",
+            "  at f(1) called from f.rinha:51:59",
+            "  at f(2) called from f.rinha:51:59",
+            "  ... 2 more calls to f",
+            "  at main() called from f.rinha:95:101",
+        ];
+        let backtrace = backtrace.to_string();
+        assert_eq!(backtrace.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn eval_runtime_errors() {
+        let cases = [
+            ("1 / (2 - 2)", "R0005", "[Error (e.rinha:0:10)]"),
+            ("7 % 0", "R0005", "[Error (e.rinha:0:5)]"),
+            ("x", "R0001", "[Error (e.rinha:0:1)]"),
+            (
+                "let f = fn (a) => { a }; f()",
+                "R0004",
+                "[Error (e.rinha:25:28)]",
+            ),
+            ("let x = 1; x(2)", "R0006", "[Error (e.rinha:8:9)]"),
+            ("first(1)", "R0003", "[Error (e.rinha:6:7)]"),
+        ];
+        for (source, code, header) in cases {
+            let file = crate::parser::parse(source, "e.rinha").unwrap();
+            let result =
+                Evaluator::new().eval(&mut Default::default(), file.expression);
+            let Term::Error(error) = &result else {
+                panic!("{source} should fail");
+            };
+            assert_eq!(error.kind.as_ref().map(|kind| kind.code()), Some(code));
+            assert!(error.to_string().starts_with(header), "{error}");
+        }
+    }
+
+    #[test]
+    fn eval_precision() {
+        let source = "\
+let fact = fn (n) => { if (n < 2) { 1 } else { n * fact(n - 1) } };
+\"25! = \" + fact(25)";
+        let cases = [
+            (Precision::I32, "\"25! = 2076180480\""),
+            (Precision::I64, "\"25! = 7034535277573963776\""),
+            (Precision::Big, "\"25! = 15511210043330985984000000\""),
+        ];
+        for (precision, expected) in cases {
+            let file = crate::parser::parse(source, "e.rinha").unwrap();
+            let mut evaluator = Evaluator::new();
+            evaluator.precision(precision);
+            let result =
+                evaluator.eval(&mut Default::default(), file.expression);
+            assert_eq!(inspect(&result), expected, "{precision}");
+        }
+
+        let cases = [
+            ("3000000000 > 1", Precision::I32, Some("R0008")),
+            ("3000000000 > 1", Precision::I64, None),
+            ("99999999999999999999 > 1", Precision::I64, Some("R0008")),
+            ("99999999999999999999 > 1", Precision::Big, None),
+        ];
+        for (source, precision, code) in cases {
+            let file = crate::parser::parse(source, "e.rinha").unwrap();
+            let mut evaluator = Evaluator::new();
+            evaluator.precision(precision);
+            let result =
+                evaluator.eval(&mut Default::default(), file.expression);
+            let found = match &result {
+                Term::Error(error) => {
+                    error.kind.as_ref().map(|kind| kind.code())
+                }
+                _ => None,
+            };
+            assert_eq!(found, code, "{source} at {precision}");
+        }
+    }
+
+    #[test]
+    fn eval_overflow() {
+        let cases = [
+            (
+                "1 + 2147483647",
+                Precision::I32,
+                "1 + 2147483647 overflows i32",
+            ),
+            (
+                "65536 * 65536",
+                Precision::I32,
+                "65536 * 65536 overflows i32",
+            ),
+            ("-2147483648 / -1", Precision::I32, "/ -1 overflows i32"),
+            ("-2147483648 % -1", Precision::I32, "% -1 overflows i32"),
+            (
+                "0 - 9223372036854775807 - 2",
+                Precision::I64,
+                "overflows i64",
+            ),
+        ];
+        for (source, precision, message) in cases {
+            let file = crate::parser::parse(source, "e.rinha").unwrap();
+            let location = file.expression.location().clone();
+            let mut evaluator = Evaluator::new();
+            evaluator.precision(precision);
+            evaluator.overflow(Overflow::Error);
+            let result =
+                evaluator.eval(&mut Default::default(), file.expression);
+            let Term::Error(error) = &result else {
+                panic!("{source} should overflow");
+            };
+            assert_eq!(
+                error.kind.as_ref().map(|kind| kind.code()),
+                Some("R0009")
+            );
+            assert!(error.full_text.ends_with(message), "{error}");
+            assert_eq!(error.location, location);
+        }
+
+        let file = crate::parser::parse("1 + 2147483647", "e.rinha").unwrap();
+        let mut evaluator = Evaluator::new();
+        evaluator.overflow(Overflow::Error);
+        evaluator.precision(Precision::Big);
+        let result = evaluator.eval(&mut Default::default(), file.expression);
+        assert_eq!(inspect(&result), "2147483648");
+
+        let file = crate::parser::parse("1 + 2147483647", "e.rinha").unwrap();
+        let result =
+            Evaluator::new().eval(&mut Default::default(), file.expression);
+        assert_eq!(inspect(&result), "-2147483648");
+    }
+
+    #[test]
+    fn eval_propagates_first_error() {
+        let cases = [
+            ("(x, y)", "Undefined variable \"x\""),
+            ("(1, (2, y))", "Undefined variable \"y\""),
+            ("let f = fn (a) => { 1 }; f(x)", "Undefined variable \"x\""),
+            (
+                "let f = fn (a, b) => { a }; f(1 / 0, x)",
+                "Attempted to divide by zero",
+            ),
+            ("first((1, x))", "Undefined variable \"x\""),
+            ("second((x, 1))", "Undefined variable \"x\""),
+            ("first(x)", "Undefined variable \"x\""),
+            ("x == 1", "Undefined variable \"x\""),
+            ("1 != y", "Undefined variable \"y\""),
+            ("(1, x) == (1, y)", "Undefined variable \"x\""),
+            ("x + print(\"side effect\")", "Undefined variable \"x\""),
+            ("x - print(1)", "Undefined variable \"x\""),
+        ];
+        for (source, full_text) in cases {
+            let file = crate::parser::parse(source, "e.rinha").unwrap();
+            let output = Output::default();
+            let mut evaluator = Evaluator::new();
+            evaluator.output(output.clone());
+            let result =
+                evaluator.eval(&mut Default::default(), file.expression);
+            match &result {
+                Term::Error(error) => assert_eq!(error.full_text, full_text),
+                term => panic!("{source} evaluated to {}", inspect(term)),
+            }
+            // Nothing after the failing operand is evaluated
+            assert_eq!(output.0.borrow().as_slice(), b"", "{source}");
+        }
+    }
+
+    /// Printed output kept for inspection after the evaluator takes it.
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn eval_eq_ignores_locations() {
+        let cases = [
+            ("1 == 1", true),
+            ("\"a\" == \"a\"", true),
+            ("(1, (true, \"a\")) == (1, (true, \"a\"))", true),
+            ("(1, 2) == (1, 3)", false),
+            ("1 == \"1\"", false),
+            ("let f = fn () => { 1 }; f == f", true),
+            ("fn () => { 1 } == fn () => { 1 }", false),
+            ("2 != 2", false),
+            ("(1, 2) != (2, 1)", true),
+        ];
+        for (source, expected) in cases {
+            let file = crate::parser::parse(source, "e.rinha").unwrap();
+            let result =
+                Evaluator::new().eval(&mut Default::default(), file.expression);
+            match result {
+                Term::Bool(Bool { value, .. }) => {
+                    assert_eq!(value, expected, "{source}")
+                }
+                term => panic!("{source} evaluated to {}", inspect(&term)),
+            }
+        }
+    }
 }
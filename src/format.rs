@@ -0,0 +1,612 @@
+// Source formatter for the textual `rinha` syntax, built on a small
+// Wadler-style document printer.
+
+use crate::ast::*;
+use crate::parser::{self, Comment};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Config {
+    /// Maximum line width the printer tries to respect.
+    pub width: usize,
+    /// Number of spaces per indentation level.
+    pub indent: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            width: 80,
+            indent: 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Doc {
+    Text(String),
+    /// A space in flat mode, a line break in break mode.
+    Line,
+    /// Nothing in flat mode, a line break in break mode.
+    SoftLine,
+    /// Always a line break, forcing enclosing groups to break.
+    HardLine,
+    /// Forces enclosing groups to break without printing anything.
+    BreakParent,
+    Concat(Vec<Doc>),
+    Nest(Box<Doc>),
+    Group(Box<Doc>, bool),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+fn text(value: impl Into<String>) -> Doc {
+    Doc::Text(value.into())
+}
+
+fn nest(doc: Doc) -> Doc {
+    Doc::Nest(Box::new(doc))
+}
+
+fn group(doc: Doc) -> Doc {
+    let hard = has_hardline(&doc);
+    Doc::Group(Box::new(doc), hard)
+}
+
+fn has_hardline(doc: &Doc) -> bool {
+    match doc {
+        Doc::HardLine | Doc::BreakParent => true,
+        Doc::Text(_) | Doc::Line | Doc::SoftLine => false,
+        Doc::Concat(docs) => docs.iter().any(has_hardline),
+        Doc::Nest(doc) => has_hardline(doc),
+        Doc::Group(_, hard) => *hard,
+    }
+}
+
+/// Formats `source`, preserving comments. The result is checked to parse
+/// back into the same program before it is returned.
+pub fn format(
+    source: &str,
+    filename: &str,
+    config: &Config,
+) -> Result<String, Error> {
+    let (file, comments) = parser::parse_with_comments(source, filename)?;
+    let mut formatter = Formatter {
+        source,
+        comments: &comments,
+        next: 0,
+    };
+    let doc = formatter.file(&file);
+    let mut output = render(&doc, config);
+
+    if !output.ends_with('\n') {
+        output.push('\n');
+    }
+
+    match parser::parse_with_comments(&output, filename) {
+        Ok((formatted, formatted_comments))
+            if equivalent(&file.expression, &formatted.expression)
+                && comments.len() == formatted_comments.len() =>
+        {
+            Ok(output)
+        }
+        _ => Err(Error {
+            message: "Formatting failed".into(),
+            full_text: "The formatted output does not preserve the program"
+                .into(),
+            location: file.location,
+            kind: None,
+        }),
+    }
+}
+
+struct Formatter<'a> {
+    source: &'a str,
+    comments: &'a [Comment],
+    next: usize,
+}
+
+impl<'a> Formatter<'a> {
+    fn file(&mut self, file: &File) -> Doc {
+        let expression = &file.expression;
+        Doc::Concat(vec![
+            self.leading(expression.location().start, false),
+            self.statement(expression),
+            self.trailing(usize::MAX),
+            self.dangling(usize::MAX),
+        ])
+    }
+
+    /// Comments that start before `pos`, each followed by a line break.
+    /// Empty lines in the source are kept when `blank` is set.
+    fn leading(&mut self, pos: usize, mut blank: bool) -> Doc {
+        let mut docs = Vec::new();
+        while let Some(comment) = self.comments.get(self.next) {
+            if comment.location.start >= pos {
+                break;
+            }
+            if blank && self.blank_before(comment.location.start) {
+                docs.push(Doc::HardLine);
+            }
+            docs.push(text(&comment.text));
+            docs.push(Doc::HardLine);
+            blank = true;
+            self.next += 1;
+        }
+        if blank && self.blank_before(pos) {
+            docs.push(Doc::HardLine);
+        }
+        Doc::Concat(docs)
+    }
+
+    /// Comments that start before `pos` and follow code on the same line.
+    /// The caller is responsible for the line break after them.
+    fn trailing(&mut self, pos: usize) -> Doc {
+        let mut docs = Vec::new();
+        while let Some(comment) = self.comments.get(self.next) {
+            if comment.location.start >= pos || !comment.trailing {
+                break;
+            }
+            docs.push(text(" "));
+            docs.push(text(&comment.text));
+            docs.push(Doc::BreakParent);
+            self.next += 1;
+        }
+        Doc::Concat(docs)
+    }
+
+    /// Comments that start before the closing delimiter at `pos`, each
+    /// preceded by a line break.
+    fn dangling(&mut self, pos: usize) -> Doc {
+        let mut docs = Vec::new();
+        while let Some(comment) = self.comments.get(self.next) {
+            if comment.location.start >= pos {
+                break;
+            }
+            docs.push(Doc::HardLine);
+            if self.blank_before(comment.location.start) {
+                docs.push(Doc::HardLine);
+            }
+            docs.push(text(&comment.text));
+            self.next += 1;
+        }
+        Doc::Concat(docs)
+    }
+
+    /// Whether the whitespace right before `pos` contains an empty line.
+    fn blank_before(&self, pos: usize) -> bool {
+        let before = &self.source[..pos.min(self.source.len())];
+        let whitespace = &before[before.trim_end().len()..];
+        whitespace.matches('\n').count() >= 2
+    }
+
+    /// A term in statement position, where a `let` or an `import` needs no
+    /// parentheses.
+    fn statement(&mut self, term: &Term) -> Doc {
+        match term {
+            Term::Let(term) => self.let_(term),
+            Term::Import(term) => self.import(term),
+            term => self.term(term),
+        }
+    }
+
+    fn term(&mut self, term: &Term) -> Doc {
+        match term {
+            Term::Error(term) => text(&term.full_text),
+            Term::Int(term) => text(term.value.to_string()),
+            Term::Str(term) => text(parser::escape(&term.value)),
+            Term::Bool(term) => text(term.value.to_string()),
+            Term::Var(term) => text(&term.text),
+            Term::Builtin(term) => text(&term.name),
+            Term::Let(_) | Term::Import(_) => {
+                let doc = self.statement(term);
+                Doc::Concat(vec![
+                    text("("),
+                    nest(Doc::Concat(vec![Doc::HardLine, doc])),
+                    Doc::HardLine,
+                    text(")"),
+                ])
+            }
+            Term::Function(term) => self.function(term),
+            Term::If(term) => self.if_(term),
+            Term::Call(term) => {
+                let callee = match *term.callee {
+                    Term::Var(_)
+                    | Term::Call(_)
+                    | Term::Print(_)
+                    | Term::First(_)
+                    | Term::Second(_) => self.term(&term.callee),
+                    ref callee => self.parenthesized(callee),
+                };
+                let arguments = term.arguments.iter().collect::<Vec<_>>();
+                let arguments = self.arguments(&arguments, term.location.end);
+                Doc::Concat(vec![callee, arguments])
+            }
+            Term::Print(term) => {
+                let arguments =
+                    self.arguments(&[&*term.value], term.location.end);
+                Doc::Concat(vec![text("print"), arguments])
+            }
+            Term::First(term) => {
+                let arguments =
+                    self.arguments(&[&*term.value], term.location.end);
+                Doc::Concat(vec![text("first"), arguments])
+            }
+            Term::Second(term) => {
+                let arguments =
+                    self.arguments(&[&*term.value], term.location.end);
+                Doc::Concat(vec![text("second"), arguments])
+            }
+            Term::Tuple(term) => {
+                let elements = [&*term.first, &*term.second];
+                self.arguments(&elements, term.location.end)
+            }
+            Term::Binary(term) => self.binary(term),
+        }
+    }
+
+    fn parenthesized(&mut self, term: &Term) -> Doc {
+        match term {
+            Term::Let(_) | Term::Import(_) => self.term(term),
+            term => Doc::Concat(vec![text("("), self.term(term), text(")")]),
+        }
+    }
+
+    fn let_(&mut self, term: &Let) -> Doc {
+        let next = term.next.location().start;
+        Doc::Concat(vec![
+            text(format!("let {} = ", term.name.text)),
+            self.term(&term.value),
+            text(";"),
+            self.trailing(next),
+            Doc::HardLine,
+            self.leading(next, true),
+            self.statement(&term.next),
+        ])
+    }
+
+    fn import(&mut self, term: &Import) -> Doc {
+        let next = term.next.location().start;
+        Doc::Concat(vec![
+            text(format!("import {};", parser::escape(&term.path.value))),
+            self.trailing(next),
+            Doc::HardLine,
+            self.leading(next, true),
+            self.statement(&term.next),
+        ])
+    }
+
+    /// A `{ ... }` body ending before `end`, which is the position of the
+    /// closing brace.
+    fn block(&mut self, term: &Term, end: usize) -> Doc {
+        let start = term.location().start;
+        let body = Doc::Concat(vec![
+            self.trailing(start),
+            Doc::Line,
+            self.leading(start, false),
+            self.statement(term),
+            self.trailing(end),
+            self.dangling(end),
+        ]);
+        Doc::Concat(vec![text("{"), nest(body), Doc::Line, text("}")])
+    }
+
+    fn function(&mut self, term: &Function) -> Doc {
+        let parameters = term.parameters.iter().map(|p| p.text.as_str());
+        let parameters = parameters.collect::<Vec<_>>().join(", ");
+        let end = term.location.end.saturating_sub(1);
+        group(Doc::Concat(vec![
+            text(format!("fn ({parameters}) => ")),
+            self.block(&term.value, end),
+        ]))
+    }
+
+    /// Conditionals always span multiple lines, as in the upstream examples.
+    fn if_(&mut self, term: &If) -> Doc {
+        let condition = self.term(&term.condition);
+        let then_end = term.otherwise.location().start;
+        let then = self.block(&term.then, then_end);
+        let end = term.location.end.saturating_sub(1);
+        let otherwise = self.block(&term.otherwise, end);
+        group(Doc::Concat(vec![
+            Doc::BreakParent,
+            text("if ("),
+            condition,
+            text(") "),
+            then,
+            text(" else "),
+            otherwise,
+        ]))
+    }
+
+    /// A parenthesized, comma-separated list closing at `end`.
+    fn arguments(&mut self, terms: &[&Term], end: usize) -> Doc {
+        let close = end.saturating_sub(1);
+        let mut docs = vec![Doc::SoftLine];
+        for (i, term) in terms.iter().enumerate() {
+            let start = term.location().start;
+            if i > 0 {
+                docs.push(text(","));
+                docs.push(self.trailing(start));
+                docs.push(Doc::Line);
+            }
+            docs.push(self.leading(start, false));
+            docs.push(self.term(term));
+        }
+        docs.push(self.trailing(close));
+        docs.push(self.dangling(close));
+        group(Doc::Concat(vec![
+            text("("),
+            nest(Doc::Concat(docs)),
+            Doc::SoftLine,
+            text(")"),
+        ]))
+    }
+
+    /// Left-nested operators of the same precedence share a single group,
+    /// so a broken chain puts every operand on its own line.
+    fn binary(&mut self, term: &Binary) -> Doc {
+        let precedence = parser::precedence(term.op);
+        let mut chain = vec![term];
+        while let Term::Binary(lhs) = chain[chain.len() - 1].lhs.as_ref() {
+            if parser::precedence(lhs.op) != precedence {
+                break;
+            }
+            chain.push(lhs);
+        }
+
+        let first = self.operand(&chain[chain.len() - 1].lhs, precedence);
+        let mut rest = Vec::new();
+        for binary in chain.iter().rev() {
+            rest.push(text(format!(" {}", binary.op.symbol())));
+            rest.push(Doc::Line);
+            rest.push(self.operand(&binary.rhs, precedence + 1));
+        }
+
+        group(Doc::Concat(vec![first, nest(Doc::Concat(rest))]))
+    }
+
+    fn operand(&mut self, term: &Term, min_precedence: u8) -> Doc {
+        match term {
+            Term::Binary(binary)
+                if parser::precedence(binary.op) < min_precedence =>
+            {
+                self.parenthesized(term)
+            }
+            Term::Let(_) | Term::Import(_) => self.parenthesized(term),
+            term => self.term(term),
+        }
+    }
+}
+
+fn render(doc: &Doc, config: &Config) -> String {
+    let mut output = String::new();
+    let mut column = 0;
+    let mut pending_indent = None;
+    let mut stack = vec![(0, Mode::Break, doc)];
+
+    while let Some((indent, mode, doc)) = stack.pop() {
+        match doc {
+            Doc::Text(value) => {
+                if let Some(indent) = pending_indent.take() {
+                    output.push_str(&" ".repeat(indent));
+                    column = indent;
+                }
+                output.push_str(value);
+                column += value.chars().count();
+            }
+            Doc::Line | Doc::SoftLine if mode == Mode::Flat => {
+                if let Doc::Line = doc {
+                    output.push(' ');
+                    column += 1;
+                }
+            }
+            Doc::BreakParent => {}
+            Doc::Line | Doc::SoftLine | Doc::HardLine => {
+                output.push('\n');
+                column = 0;
+                pending_indent = Some(indent);
+            }
+            Doc::Concat(docs) => {
+                for doc in docs.iter().rev() {
+                    stack.push((indent, mode, doc));
+                }
+            }
+            Doc::Nest(doc) => stack.push((indent + config.indent, mode, doc)),
+            Doc::Group(doc, hard) => {
+                let width = config.width as isize - column as isize;
+                let mode = match mode == Mode::Flat
+                    || (!hard && fits(width, (indent, Mode::Flat, doc), &stack))
+                {
+                    true => Mode::Flat,
+                    false => Mode::Break,
+                };
+                stack.push((indent, mode, doc));
+            }
+        }
+    }
+
+    output
+}
+
+/// Whether `doc` fits in `width` columns when laid out flat, taking into
+/// account the text that follows it up to the next line break.
+fn fits(
+    mut width: isize,
+    doc: (usize, Mode, &Doc),
+    rest: &[(usize, Mode, &Doc)],
+) -> bool {
+    let mut rest = rest.iter().rev();
+    let mut stack = vec![doc];
+
+    while width >= 0 {
+        let (indent, mode, doc) = match stack.pop() {
+            Some(item) => item,
+            None => match rest.next() {
+                Some(&item) => item,
+                None => return true,
+            },
+        };
+        match doc {
+            Doc::Text(value) => width -= value.chars().count() as isize,
+            Doc::Line if mode == Mode::Flat => width -= 1,
+            Doc::SoftLine if mode == Mode::Flat => {}
+            Doc::BreakParent => {}
+            Doc::Line | Doc::SoftLine | Doc::HardLine => return true,
+            Doc::Concat(docs) => {
+                stack.extend(docs.iter().rev().map(|doc| (indent, mode, doc)))
+            }
+            Doc::Nest(doc) | Doc::Group(doc, _) => {
+                stack.push((indent, mode, doc))
+            }
+        }
+    }
+
+    false
+}
+
+/// Structural equality that ignores locations.
+fn equivalent(lhs: &Term, rhs: &Term) -> bool {
+    match (lhs, rhs) {
+        (Term::Error(lhs), Term::Error(rhs)) => {
+            lhs.message == rhs.message && lhs.full_text == rhs.full_text
+        }
+        (Term::Int(lhs), Term::Int(rhs)) => lhs.value == rhs.value,
+        (Term::Str(lhs), Term::Str(rhs)) => lhs.value == rhs.value,
+        (Term::Bool(lhs), Term::Bool(rhs)) => lhs.value == rhs.value,
+        (Term::Var(lhs), Term::Var(rhs)) => lhs.text == rhs.text,
+        (Term::Call(lhs), Term::Call(rhs)) => {
+            equivalent(&lhs.callee, &rhs.callee)
+                && lhs.arguments.len() == rhs.arguments.len()
+                && lhs
+                    .arguments
+                    .iter()
+                    .zip(&rhs.arguments)
+                    .all(|(lhs, rhs)| equivalent(lhs, rhs))
+        }
+        (Term::Binary(lhs), Term::Binary(rhs)) => {
+            lhs.op == rhs.op
+                && equivalent(&lhs.lhs, &rhs.lhs)
+                && equivalent(&lhs.rhs, &rhs.rhs)
+        }
+        (Term::Function(lhs), Term::Function(rhs)) => {
+            lhs.parameters.len() == rhs.parameters.len()
+                && lhs
+                    .parameters
+                    .iter()
+                    .zip(&rhs.parameters)
+                    .all(|(lhs, rhs)| lhs.text == rhs.text)
+                && equivalent(&lhs.value, &rhs.value)
+        }
+        (Term::Let(lhs), Term::Let(rhs)) => {
+            lhs.name.text == rhs.name.text
+                && equivalent(&lhs.value, &rhs.value)
+                && equivalent(&lhs.next, &rhs.next)
+        }
+        (Term::Import(lhs), Term::Import(rhs)) => {
+            lhs.path.value == rhs.path.value && equivalent(&lhs.next, &rhs.next)
+        }
+        (Term::If(lhs), Term::If(rhs)) => {
+            equivalent(&lhs.condition, &rhs.condition)
+                && equivalent(&lhs.then, &rhs.then)
+                && equivalent(&lhs.otherwise, &rhs.otherwise)
+        }
+        (Term::Print(lhs), Term::Print(rhs)) => {
+            equivalent(&lhs.value, &rhs.value)
+        }
+        (Term::First(lhs), Term::First(rhs)) => {
+            equivalent(&lhs.value, &rhs.value)
+        }
+        (Term::Second(lhs), Term::Second(rhs)) => {
+            equivalent(&lhs.value, &rhs.value)
+        }
+        (Term::Tuple(lhs), Term::Tuple(rhs)) => {
+            equivalent(&lhs.first, &rhs.first)
+                && equivalent(&lhs.second, &rhs.second)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIB: &str = "\
+let fib = fn (n) => {
+  if (n < 2) {
+    n
+  } else {
+    fib(n - 1) + fib(n - 2)
+  }
+};
+
+print(\"fib: \" + fib(10))
+";
+
+    fn fmt(source: &str) -> String {
+        format(source, "test.rinha", &Default::default()).unwrap()
+    }
+
+    #[test]
+    fn format_canonical() {
+        assert_eq!(fmt(FIB), FIB);
+    }
+
+    #[test]
+    fn format_messy() {
+        let source = "let fib=fn(n)=>{if(n<2){n}else{fib(n-1)+fib(n-2)}};\n\n\
+                      print(\"fib: \"+fib(10))";
+        assert_eq!(fmt(source), FIB);
+    }
+
+    #[test]
+    fn format_idempotent() {
+        let source = "let f = fn (a, b) => { let c = (a + b) * (a - b); \
+                      // square difference\n c };\n\n\n\
+                      /* entry */ print((f(1, 2), first((1, 2))))";
+        let once = fmt(source);
+        assert_eq!(fmt(&once), once);
+    }
+
+    #[test]
+    fn format_comments() {
+        let source =
+            "// header\nlet x = 1; // one\n\n// the result\nx\n// end\n";
+        assert_eq!(fmt(source), source);
+    }
+
+    #[test]
+    fn format_width() {
+        let source = "print(\"a long string literal\" + \"another long one\")";
+        let config = Config {
+            width: 30,
+            indent: 4,
+        };
+        let output = format(source, "", &config).unwrap();
+        let expected = "print(\n    \"a long string literal\" +\n        \"another long one\"\n)\n";
+        assert_eq!(output, expected);
+        assert_eq!(format(&output, "", &config).unwrap(), output);
+    }
+
+    #[test]
+    fn format_parentheses() {
+        let source = "let x = (1 + 2) * 3 - (4 - 5);\n(fn (y) => { y })(x)\n";
+        assert_eq!(fmt(source), source);
+    }
+
+    #[test]
+    fn format_imports() {
+        let source = "import\"a.rinha\";// a\nimport \"b.rinha\";\n\nf(x)";
+        let expected =
+            "import \"a.rinha\"; // a\nimport \"b.rinha\";\n\nf(x)\n";
+        assert_eq!(fmt(source), expected);
+        // Nested like a `let`
+        let nested = fmt("1 + (import \"a.rinha\"; x)");
+        let let_ = fmt("1 + (let a = 1; x)");
+        assert_eq!(nested.replace("import \"a.rinha\"", "let a = 1"), let_);
+    }
+}
@@ -0,0 +1,554 @@
+// Random programs built from fuzzer input, and the property they are checked
+// against: evaluating any program ends in a value or a rinha error, never in
+// a Rust panic. The `fuzz` directory has the libFuzzer target that drives
+// `run`, and the regressions it found, which are replayed by the tests here.
+// Inputs that are rinha programs themselves are run as they are, so that the
+// golden programs can seed the corpus.
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+use arbitrary::{Result, Unstructured};
+
+use crate::ast::build::*;
+use crate::ast::{build, BinaryOp, Element, Error, Location, Term};
+use crate::env::Env;
+use crate::eval::{Evaluator, Observer};
+use crate::integer::{Integer, Overflow, Precision};
+use crate::{check, error, parser, prelude, source};
+
+/// How many terms a program may evaluate before it is stopped.
+pub const STEPS: usize = 10_000;
+
+/// How deeply generated terms nest.
+const DEPTH: usize = 8;
+
+/// Names used by ill-typed terms, so that some are defined and some aren't,
+/// and some shadow builtins.
+const NAMES: [&str; 7] = ["a", "b", "f", "_", "len", "swap", "map_pair"];
+
+/// Terms come from different files, as they would with imports.
+const FILES: [&str; 2] = ["a.rinha", "b.rinha"];
+
+const ARITHMETIC: [BinaryOp; 5] = [
+    BinaryOp::Add,
+    BinaryOp::Sub,
+    BinaryOp::Mul,
+    BinaryOp::Div,
+    BinaryOp::Rem,
+];
+
+const COMPARISON: [BinaryOp; 6] = [
+    BinaryOp::Eq,
+    BinaryOp::Neq,
+    BinaryOp::Lt,
+    BinaryOp::Gt,
+    BinaryOp::Lte,
+    BinaryOp::Gte,
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Type {
+    Int,
+    Str,
+    Bool,
+    Tuple(Box<Type>, Box<Type>),
+}
+
+enum Binding {
+    Value(String, Type),
+    Function(String, Vec<Type>, Type),
+}
+
+/// A generated program and the modes it is evaluated with.
+#[derive(Debug)]
+pub struct Program {
+    pub term: Term,
+    pub precision: Precision,
+    pub overflow: Overflow,
+}
+
+impl Program {
+    /// Builds a program from `u`. The first choice decides whether the term
+    /// is well-typed, with an occasional ill-typed subterm, or entirely
+    /// random.
+    pub fn generate(u: &mut Unstructured) -> Result<Self> {
+        let precision =
+            *u.choose(&[Precision::I32, Precision::I64, Precision::Big])?;
+        let overflow = *u.choose(&[Overflow::Wrap, Overflow::Error])?;
+        let mut generator = Generator {
+            typed: u.arbitrary()?,
+            u,
+            scope: Vec::new(),
+        };
+        let term = if generator.typed {
+            let ty = generator.ty(2)?;
+            generator.typed(&ty, DEPTH)?
+        } else {
+            generator.any(DEPTH)?
+        };
+        Ok(Self {
+            term,
+            precision,
+            overflow,
+        })
+    }
+}
+
+struct Generator<'a, 'b> {
+    u: &'a mut Unstructured<'b>,
+    typed: bool,
+    /// The variables in scope, innermost last.
+    scope: Vec<Binding>,
+}
+
+impl Generator<'_, '_> {
+    fn ty(&mut self, depth: usize) -> Result<Type> {
+        Ok(match self.u.int_in_range(0..=3)? {
+            0 => Type::Int,
+            1 => Type::Str,
+            2 => Type::Bool,
+            _ if depth == 0 => Type::Int,
+            _ => Type::Tuple(
+                Box::new(self.ty(depth - 1)?),
+                Box::new(self.ty(depth - 1)?),
+            ),
+        })
+    }
+
+    fn locate(&mut self, mut term: Term) -> Result<Term> {
+        let filename = *self.u.choose(&FILES)?;
+        let start = self.u.int_in_range(0..=64)?;
+        let end = start + self.u.int_in_range(0..=16)?;
+        *term.location_mut() = Location::new(start, end, filename);
+        Ok(term)
+    }
+
+    /// A name that no enclosing `let` or parameter uses.
+    fn fresh(&self) -> String {
+        format!("x{}", self.scope.len())
+    }
+
+    fn integer(&mut self) -> Result<Integer> {
+        Ok(match self.u.int_in_range(0..=4)? {
+            0 => self.u.int_in_range(-3..=3)?.into(),
+            1 => (*self.u.choose(&[i32::MIN, i32::MAX])?).into(),
+            2 => (*self.u.choose(&[i64::MIN, i64::MAX])?).into(),
+            3 => "-99999999999999999999".parse().unwrap(),
+            _ => self.u.arbitrary::<i32>()?.into(),
+        })
+    }
+
+    fn literal(&mut self, ty: &Type) -> Result<Term> {
+        let term = match ty {
+            Type::Int => int(self.integer()?),
+            Type::Str => string(*self.u.choose(&["", "a", "ção"])?),
+            Type::Bool => boolean(self.u.arbitrary()?),
+            Type::Tuple(a, b) => tuple(self.literal(a)?, self.literal(b)?),
+        };
+        self.locate(term)
+    }
+
+    /// A term of type `ty`, or a literal once `depth` runs out.
+    fn typed(&mut self, ty: &Type, depth: usize) -> Result<Term> {
+        if depth == 0 {
+            return self.literal(ty);
+        }
+        let depth = depth - 1;
+        let term = match self.u.int_in_range(0..=9)? {
+            0 => return self.literal(ty),
+            1 => return self.any(depth),
+            2 => {
+                let variables: Vec<String> = self
+                    .scope
+                    .iter()
+                    .filter_map(|binding| match binding {
+                        Binding::Value(name, t) if t == ty => {
+                            Some(name.clone())
+                        }
+                        _ => None,
+                    })
+                    .collect();
+                match self.u.choose(&variables) {
+                    Ok(name) => var(name),
+                    Err(_) => return self.literal(ty),
+                }
+            }
+            3 => if_(
+                self.typed(&Type::Bool, depth)?,
+                self.typed(ty, depth)?,
+                self.typed(ty, depth)?,
+            ),
+            4 => return self.let_(ty, depth),
+            5 => return self.call(ty, depth),
+            6 => {
+                let other = Box::new(self.ty(1)?);
+                let pair = Type::Tuple(Box::new(ty.clone()), other);
+                first(self.typed(&pair, depth)?)
+            }
+            7 => {
+                let other = Box::new(self.ty(1)?);
+                let pair = Type::Tuple(other, Box::new(ty.clone()));
+                second(self.typed(&pair, depth)?)
+            }
+            8 if !matches!(ty, Type::Tuple(..)) => {
+                print(self.typed(ty, depth)?)
+            }
+            _ => self.operation(ty, depth)?,
+        };
+        self.locate(term)
+    }
+
+    /// An operation whose result has type `ty`.
+    fn operation(&mut self, ty: &Type, depth: usize) -> Result<Term> {
+        Ok(match ty {
+            Type::Int => {
+                let op = *self.u.choose(&ARITHMETIC)?;
+                binary(
+                    self.typed(&Type::Int, depth)?,
+                    op,
+                    self.typed(&Type::Int, depth)?,
+                )
+            }
+            Type::Str => {
+                let other = [Type::Int, Type::Str, Type::Bool];
+                let other = self.u.choose(&other)?.clone();
+                let other = self.typed(&other, depth)?;
+                let string = self.typed(&Type::Str, depth)?;
+                match self.u.arbitrary()? {
+                    true => binary(string, BinaryOp::Add, other),
+                    false => binary(other, BinaryOp::Add, string),
+                }
+            }
+            Type::Bool => {
+                let (operand, op) = match self.u.int_in_range(0..=2)? {
+                    0 => (Type::Int, *self.u.choose(&COMPARISON)?),
+                    1 => {
+                        let op =
+                            *self.u.choose(&[BinaryOp::And, BinaryOp::Or])?;
+                        (Type::Bool, op)
+                    }
+                    _ => {
+                        let op =
+                            *self.u.choose(&[BinaryOp::Eq, BinaryOp::Neq])?;
+                        (self.ty(1)?, op)
+                    }
+                };
+                binary(
+                    self.typed(&operand, depth)?,
+                    op,
+                    self.typed(&operand, depth)?,
+                )
+            }
+            Type::Tuple(a, b) => {
+                tuple(self.typed(a, depth)?, self.typed(b, depth)?)
+            }
+        })
+    }
+
+    /// A `let` of a value or a function, followed by a term of type `ty`
+    /// that may use it.
+    fn let_(&mut self, ty: &Type, depth: usize) -> Result<Term> {
+        let name = self.fresh();
+        let value = if self.u.arbitrary()? {
+            let value = self.ty(1)?;
+            let term = self.typed(&value, depth)?;
+            self.scope.push(Binding::Value(name.clone(), value));
+            term
+        } else {
+            let count = self.u.int_in_range(0..=2)?;
+            let parameters =
+                (0..count).map(|_| self.ty(1)).collect::<Result<Vec<_>>>()?;
+            let result = self.ty(1)?;
+            // The function is in scope in its own body, so it may recurse
+            self.scope.push(Binding::Function(
+                name.clone(),
+                parameters.clone(),
+                result.clone(),
+            ));
+            let names: Vec<String> = parameters
+                .into_iter()
+                .map(|ty| {
+                    let name = self.fresh();
+                    self.scope.push(Binding::Value(name.clone(), ty));
+                    name
+                })
+                .collect();
+            let body = self.typed(&result, depth);
+            self.scope.truncate(self.scope.len() - names.len());
+            fun(names.iter().map(String::as_str), body?)
+        };
+        let next = self.typed(ty, depth);
+        self.scope.pop();
+        let value = self.locate(value)?;
+        self.locate(let_(&name, value, next?))
+    }
+
+    /// A call to a function in scope or to a builtin that returns `ty`.
+    fn call(&mut self, ty: &Type, depth: usize) -> Result<Term> {
+        if self.u.arbitrary()? {
+            if let Some(term) = self.builtin(ty, depth)? {
+                return Ok(term);
+            }
+        }
+        let functions: Vec<(String, Vec<Type>)> = self
+            .scope
+            .iter()
+            .filter_map(|binding| match binding {
+                Binding::Function(name, parameters, result) if result == ty => {
+                    Some((name.clone(), parameters.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        let Ok((name, parameters)) = self.u.choose(&functions) else {
+            return self.literal(ty);
+        };
+        let (name, parameters) = (name.clone(), parameters.clone());
+        let arguments = parameters
+            .iter()
+            .map(|ty| self.typed(ty, depth))
+            .collect::<Result<Vec<_>>>()?;
+        let callee = self.locate(var(&name))?;
+        self.locate(call(callee, arguments))
+    }
+
+    /// A call to a builtin that returns `ty`, if there is one.
+    fn builtin(&mut self, ty: &Type, depth: usize) -> Result<Option<Term>> {
+        let (name, arguments) = match ty {
+            Type::Tuple(a, b) => {
+                let pair = Type::Tuple(b.clone(), a.clone());
+                ("swap", vec![self.typed(&pair, depth)?])
+            }
+            _ => {
+                let builtins: Vec<&prelude::Signature> = prelude::BUILTINS
+                    .iter()
+                    .filter(|builtin| {
+                        scalar(&builtin.result).as_ref() == Some(ty)
+                    })
+                    .filter(|builtin| {
+                        builtin.parameters.iter().all(|(_, parameter)| {
+                            *parameter == error::Type::Unknown
+                                || scalar(parameter).is_some()
+                        })
+                    })
+                    .collect();
+                let Ok(builtin) = self.u.choose(&builtins) else {
+                    return Ok(None);
+                };
+                let mut arguments = Vec::new();
+                for (parameter, expected) in builtin.parameters {
+                    let argument = match (builtin.name, *parameter) {
+                        // Arbitrary precision powers with huge exponents
+                        // only exhaust memory
+                        ("pow", "exponent") => {
+                            let exponent = self.u.int_in_range(-2..=40)?;
+                            self.locate(int(exponent))?
+                        }
+                        _ => {
+                            let ty = match scalar(expected) {
+                                Some(ty) => ty,
+                                None => self.ty(0)?,
+                            };
+                            self.typed(&ty, depth)?
+                        }
+                    };
+                    arguments.push(argument);
+                }
+                (builtin.name, arguments)
+            }
+        };
+        let callee = self.locate(var(name))?;
+        self.locate(call(callee, arguments)).map(Some)
+    }
+
+    /// Any term, with no regard for types or scopes.
+    fn any(&mut self, depth: usize) -> Result<Term> {
+        if depth == 0 {
+            let ty = self.ty(1)?;
+            return self.literal(&ty);
+        }
+        let depth = depth - 1;
+        let name = *self.u.choose(&NAMES)?;
+        let term = match self.u.int_in_range(0..=12)? {
+            0 => {
+                let ty = self.ty(1)?;
+                return self.literal(&ty);
+            }
+            1 => var(name),
+            2 => let_(name, self.any(depth)?, self.any(depth)?),
+            3 => {
+                let count = self.u.int_in_range(0..=2)?;
+                let names = (0..count)
+                    .map(|_| self.u.choose(&NAMES).copied())
+                    .collect::<Result<Vec<_>>>()?;
+                fun(names, self.any(depth)?)
+            }
+            4 => {
+                let count = self.u.int_in_range(0..=2)?;
+                let arguments = (0..count)
+                    .map(|_| self.any(depth))
+                    .collect::<Result<Vec<_>>>()?;
+                call(self.any(depth)?, arguments)
+            }
+            5 => if_(self.any(depth)?, self.any(depth)?, self.any(depth)?),
+            6 => {
+                let op = *self.u.choose(&BinaryOp::ALL)?;
+                binary(self.any(depth)?, op, self.any(depth)?)
+            }
+            7 => tuple(self.any(depth)?, self.any(depth)?),
+            8 => first(self.any(depth)?),
+            9 => second(self.any(depth)?),
+            10 => print(self.any(depth)?),
+            11 => build::parse_error("Generated error", ""),
+            _ if self.typed => {
+                let ty = self.ty(1)?;
+                return self.typed(&ty, depth);
+            }
+            _ => var(name),
+        };
+        self.locate(term)
+    }
+}
+
+/// The generated type of builtin parameters and results that aren't tuples
+/// or functions.
+fn scalar(ty: &error::Type) -> Option<Type> {
+    match ty {
+        error::Type::Int => Some(Type::Int),
+        error::Type::Str => Some(Type::Str),
+        error::Type::Bool => Some(Type::Bool),
+        _ => None,
+    }
+}
+
+/// Stops the evaluation once it has visited `STEPS` terms.
+struct Fuel(usize);
+
+impl Observer for Fuel {
+    fn visit(
+        &mut self,
+        _env: &Rc<RefCell<Env>>,
+        term: &Term,
+    ) -> std::result::Result<(), Error> {
+        match self.0.checked_sub(1) {
+            Some(left) => {
+                self.0 = left;
+                Ok(())
+            }
+            None => Err(Error {
+                message: "Out of fuel".into(),
+                full_text: format!("Stopped after {STEPS} steps"),
+                location: term.location().clone(),
+                kind: None,
+            }),
+        }
+    }
+}
+
+fn is_value(term: &Term) -> bool {
+    match term {
+        Term::Int(_)
+        | Term::Str(_)
+        | Term::Bool(_)
+        | Term::Function(_)
+        | Term::Builtin(_)
+        | Term::Error(_) => true,
+        Term::Tuple(tuple) => is_value(&tuple.first) && is_value(&tuple.second),
+        _ => false,
+    }
+}
+
+/// Reads `data` as a program in rinha source or as a JSON AST, if it is
+/// one without syntax errors.
+fn parse(data: &[u8]) -> Option<Term> {
+    let data = std::str::from_utf8(data).ok()?;
+    let file = match data.trim_start().starts_with('{') {
+        true => source::from_json(data).ok()?,
+        false => parser::parse(data, "fuzz.rinha").ok()?,
+    };
+    check::parse_errors(&file).ok()?;
+    Some(file.expression)
+}
+
+/// Generates a program from `data`, unless it is a program already, and
+/// evaluates it, panicking if the evaluator panics or produces something
+/// that is neither a value nor an error.
+pub fn run(data: &[u8]) {
+    let program = match parse(data) {
+        Some(term) => Program {
+            term,
+            precision: Precision::I32,
+            overflow: Overflow::Wrap,
+        },
+        None => match Program::generate(&mut Unstructured::new(data)) {
+            Ok(program) => program,
+            Err(_) => return,
+        },
+    };
+    let mut evaluator = Evaluator::new();
+    evaluator.precision(program.precision);
+    evaluator.overflow(program.overflow);
+    evaluator.output(io::sink());
+    evaluator.observe(Fuel(STEPS));
+    let mut env = prelude::env();
+    let result = evaluator.eval(&mut env, program.term);
+    assert!(is_value(&result), "evaluated to {result:?}");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+
+    const REGRESSIONS: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/fuzz/regressions/eval");
+
+    #[test]
+    fn fuzz_regressions() {
+        let mut count = 0;
+        for entry in fs::read_dir(Path::new(REGRESSIONS)).unwrap() {
+            let path = entry.unwrap().path();
+            let data = fs::read(&path).unwrap();
+            let result = std::panic::catch_unwind(|| run(&data));
+            assert!(result.is_ok(), "{} panicked", path.display());
+            count += 1;
+        }
+        assert!(count > 0, "no regressions in {REGRESSIONS}");
+    }
+
+    #[test]
+    fn fuzz_golden_programs() {
+        let programs = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/programs");
+        for entry in fs::read_dir(programs).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.to_string_lossy();
+            if name.ends_with(".rinha") || name.ends_with(".rinha.json") {
+                let data = fs::read(&path).unwrap();
+                let result = std::panic::catch_unwind(|| run(&data));
+                assert!(result.is_ok(), "{} panicked", path.display());
+            }
+        }
+    }
+
+    #[test]
+    fn fuzz_random_inputs() {
+        // A small xorshift generator, so that plain `cargo test` covers a
+        // fixed sample of inputs without a fuzzer
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        for _ in 0..500 {
+            let data: Vec<u8> = (0..256)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect();
+            run(&data);
+        }
+    }
+}
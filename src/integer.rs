@@ -0,0 +1,410 @@
+// Integer values, and the arithmetic performed on them at each precision.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use num_bigint::{BigInt, Sign};
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+
+/// An integer of any size. Values that fit in an `i64` are kept unboxed.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Integer(Repr);
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+enum Repr {
+    Small(i64),
+    /// Never holds a value that fits in an `i64`.
+    Big(Box<BigInt>),
+}
+
+impl Integer {
+    pub fn to_i64(&self) -> Option<i64> {
+        match self.0 {
+            Repr::Small(value) => Some(value),
+            Repr::Big(_) => None,
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == Repr::Small(0)
+    }
+
+    /// The value modulo 2^64, as two's complement.
+    fn wrapped(&self) -> i64 {
+        match &self.0 {
+            Repr::Small(value) => *value,
+            Repr::Big(value) => {
+                let (sign, digits) = value.to_u64_digits();
+                let low = digits.first().copied().unwrap_or(0);
+                match sign {
+                    Sign::Minus => low.wrapping_neg() as i64,
+                    _ => low as i64,
+                }
+            }
+        }
+    }
+
+    fn to_big(&self) -> BigInt {
+        match &self.0 {
+            Repr::Small(value) => BigInt::from(*value),
+            Repr::Big(value) => value.as_ref().clone(),
+        }
+    }
+}
+
+impl Default for Integer {
+    fn default() -> Self {
+        Integer(Repr::Small(0))
+    }
+}
+
+impl From<i32> for Integer {
+    fn from(value: i32) -> Self {
+        Integer(Repr::Small(value.into()))
+    }
+}
+
+impl From<i64> for Integer {
+    fn from(value: i64) -> Self {
+        Integer(Repr::Small(value))
+    }
+}
+
+impl From<BigInt> for Integer {
+    fn from(value: BigInt) -> Self {
+        match i64::try_from(&value) {
+            Ok(value) => Integer(Repr::Small(value)),
+            Err(_) => Integer(Repr::Big(Box::new(value))),
+        }
+    }
+}
+
+impl Ord for Integer {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (&self.0, &other.0) {
+            (Repr::Small(lhs), Repr::Small(rhs)) => lhs.cmp(rhs),
+            (Repr::Big(lhs), Repr::Big(rhs)) => lhs.cmp(rhs),
+            // A big value lies beyond every small one, on the side of its sign
+            (Repr::Big(lhs), Repr::Small(_)) => match lhs.sign() {
+                Sign::Minus => Ordering::Less,
+                _ => Ordering::Greater,
+            },
+            (Repr::Small(_), Repr::Big(rhs)) => match rhs.sign() {
+                Sign::Minus => Ordering::Greater,
+                _ => Ordering::Less,
+            },
+        }
+    }
+}
+
+impl PartialOrd for Integer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Integer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Repr::Small(value) => value.fmt(f),
+            Repr::Big(value) => value.fmt(f),
+        }
+    }
+}
+
+impl FromStr for Integer {
+    type Err = num_bigint::ParseBigIntError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.parse::<i64>() {
+            Ok(value) => Ok(value.into()),
+            Err(_) => text.parse::<BigInt>().map(Into::into),
+        }
+    }
+}
+
+impl Serialize for Integer {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match &self.0 {
+            Repr::Small(value) => serializer.serialize_i64(*value),
+            Repr::Big(value) => match u64::try_from(value.as_ref()) {
+                Ok(value) => serializer.serialize_u64(value),
+                // JSON numbers beyond 64 bits aren't portable
+                Err(_) => serializer.serialize_str(&value.to_string()),
+            },
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Integer {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        struct IntegerVisitor;
+
+        impl<'de> Visitor<'de> for IntegerVisitor {
+            type Value = Integer;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an integer")
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Integer, E> {
+                Ok(value.into())
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Integer, E> {
+                Ok(BigInt::from(value).into())
+            }
+        }
+
+        deserializer.deserialize_i64(IntegerVisitor)
+    }
+}
+
+/// How integers are represented while evaluating a program.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Precision {
+    /// 32-bit integers that wrap around on overflow, as in the spec.
+    #[default]
+    I32,
+    /// 64-bit integers that wrap around on overflow.
+    I64,
+    /// Integers that grow as needed and never overflow.
+    Big,
+}
+
+impl FromStr for Precision {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "i32" => Ok(Precision::I32),
+            "i64" => Ok(Precision::I64),
+            "big" => Ok(Precision::Big),
+            _ => Err(format!("invalid integer precision {value}")),
+        }
+    }
+}
+
+impl fmt::Display for Precision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Precision::I32 => write!(f, "i32"),
+            Precision::I64 => write!(f, "i64"),
+            Precision::Big => write!(f, "big"),
+        }
+    }
+}
+
+/// What happens when an arithmetic operation overflows.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Overflow {
+    /// The result wraps around, as in the spec.
+    #[default]
+    Wrap,
+    /// The operation fails with a runtime error.
+    Error,
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "wrap" => Ok(Overflow::Wrap),
+            "error" => Ok(Overflow::Error),
+            _ => Err(format!("invalid overflow mode {value}")),
+        }
+    }
+}
+
+/// The implementations of an operation at each precision.
+struct Op {
+    i32: fn(i32, i32) -> (i32, bool),
+    i64: fn(i64, i64) -> (i64, bool),
+    checked: fn(i64, i64) -> Option<i64>,
+    big: fn(BigInt, BigInt) -> BigInt,
+}
+
+const ADD: Op = Op {
+    i32: i32::overflowing_add,
+    i64: i64::overflowing_add,
+    checked: i64::checked_add,
+    big: |lhs, rhs| lhs + rhs,
+};
+
+const SUB: Op = Op {
+    i32: i32::overflowing_sub,
+    i64: i64::overflowing_sub,
+    checked: i64::checked_sub,
+    big: |lhs, rhs| lhs - rhs,
+};
+
+const MUL: Op = Op {
+    i32: i32::overflowing_mul,
+    i64: i64::overflowing_mul,
+    checked: i64::checked_mul,
+    big: |lhs, rhs| lhs * rhs,
+};
+
+const DIV: Op = Op {
+    i32: i32::overflowing_div,
+    i64: i64::overflowing_div,
+    checked: i64::checked_div,
+    big: |lhs, rhs| lhs / rhs,
+};
+
+const REM: Op = Op {
+    i32: i32::overflowing_rem,
+    i64: i64::overflowing_rem,
+    checked: i64::checked_rem,
+    big: |lhs, rhs| lhs % rhs,
+};
+
+impl Precision {
+    /// Whether `value` can be written as a literal at this precision.
+    pub fn contains(self, value: &Integer) -> bool {
+        match self {
+            Precision::I32 => value
+                .to_i64()
+                .is_some_and(|value| i32::try_from(value).is_ok()),
+            Precision::I64 => value.to_i64().is_some(),
+            Precision::Big => true,
+        }
+    }
+
+    // Like their counterparts on primitive integers, these return the
+    // wrapped result along with whether it overflowed. Arbitrary precision
+    // integers never overflow.
+
+    pub fn overflowing_add(
+        self,
+        lhs: &Integer,
+        rhs: &Integer,
+    ) -> (Integer, bool) {
+        self.apply(&ADD, lhs, rhs)
+    }
+
+    pub fn overflowing_sub(
+        self,
+        lhs: &Integer,
+        rhs: &Integer,
+    ) -> (Integer, bool) {
+        self.apply(&SUB, lhs, rhs)
+    }
+
+    pub fn overflowing_mul(
+        self,
+        lhs: &Integer,
+        rhs: &Integer,
+    ) -> (Integer, bool) {
+        self.apply(&MUL, lhs, rhs)
+    }
+
+    /// Truncating division. `rhs` must not be zero.
+    pub fn overflowing_div(
+        self,
+        lhs: &Integer,
+        rhs: &Integer,
+    ) -> (Integer, bool) {
+        self.apply(&DIV, lhs, rhs)
+    }
+
+    /// The remainder of the truncating division. `rhs` must not be zero.
+    pub fn overflowing_rem(
+        self,
+        lhs: &Integer,
+        rhs: &Integer,
+    ) -> (Integer, bool) {
+        self.apply(&REM, lhs, rhs)
+    }
+
+    fn apply(self, op: &Op, lhs: &Integer, rhs: &Integer) -> (Integer, bool) {
+        match self {
+            Precision::I32 => {
+                let (lhs, rhs) = (lhs.wrapped() as i32, rhs.wrapped() as i32);
+                let (value, overflowed) = (op.i32)(lhs, rhs);
+                (value.into(), overflowed)
+            }
+            Precision::I64 => {
+                let (value, overflowed) =
+                    (op.i64)(lhs.wrapped(), rhs.wrapped());
+                (value.into(), overflowed)
+            }
+            Precision::Big => {
+                let small = match (&lhs.0, &rhs.0) {
+                    (Repr::Small(lhs), Repr::Small(rhs)) => {
+                        (op.checked)(*lhs, *rhs)
+                    }
+                    _ => None,
+                };
+                let value = match small {
+                    Some(value) => value.into(),
+                    None => (op.big)(lhs.to_big(), rhs.to_big()).into(),
+                };
+                (value, false)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(text: &str) -> Integer {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn integer_arithmetic() {
+        let max = Integer::from(i32::MAX);
+        let one = Integer::from(1);
+        let (narrow, wide, big) =
+            (Precision::I32, Precision::I64, Precision::Big);
+        assert_eq!(narrow.overflowing_add(&max, &one), (i32::MIN.into(), true));
+        assert_eq!(
+            wide.overflowing_add(&max, &one),
+            (int("2147483648"), false)
+        );
+        assert_eq!(big.overflowing_add(&max, &one), (int("2147483648"), false));
+
+        let max = Integer::from(i64::MAX);
+        assert_eq!(wide.overflowing_add(&max, &one), (i64::MIN.into(), true));
+        let (product, overflowed) = big.overflowing_mul(&max, &max);
+        assert_eq!(product, int("85070591730234615847396907784232501249"));
+        assert!(!overflowed);
+        assert_eq!(big.overflowing_div(&product, &max), (max.clone(), false));
+        assert_eq!(big.overflowing_rem(&product, &max).0, Integer::default());
+        assert_eq!(wide.overflowing_mul(&product, &one).0, int("1"));
+
+        let min = Integer::from(i32::MIN);
+        let minus_one = Integer::from(-1);
+        assert_eq!(
+            narrow.overflowing_div(&min, &minus_one),
+            (min.clone(), true)
+        );
+        assert_eq!(narrow.overflowing_sub(&min, &one), (i32::MAX.into(), true));
+        assert_eq!(big.overflowing_div(&min, &minus_one).0, int("2147483648"));
+        assert_eq!(big.overflowing_rem(&int("-7"), &int("2")).0, int("-1"));
+    }
+
+    #[test]
+    fn integer_ordering() {
+        let big = int("100000000000000000000");
+        let negative = int("-100000000000000000000");
+        assert!(big > Integer::from(i64::MAX));
+        assert!(negative < Integer::from(i64::MIN));
+        assert!(negative < big);
+        assert_eq!(big.to_string(), "100000000000000000000");
+        assert!(!Precision::I64.contains(&big));
+        assert!(!Precision::I32.contains(&int("2147483648")));
+        assert!(Precision::I32.contains(&int("-2147483648")));
+    }
+}
@@ -0,0 +1,54 @@
+pub mod ast;
+pub mod binary;
+pub mod check;
+pub mod cli;
+pub mod coverage;
+pub mod dap;
+pub mod debug;
+pub mod dot;
+pub mod env;
+pub mod error;
+pub mod eval;
+pub mod format;
+#[cfg(feature = "fuzzing")]
+pub mod fuzz;
+pub mod integer;
+pub mod lsp;
+pub mod parser;
+pub mod prelude;
+pub mod profile;
+pub mod report;
+pub mod rpc;
+pub mod source;
+pub mod trace;
+pub mod validate;
+
+use std::fmt;
+
+use crate::eval::Evaluator;
+
+#[derive(Debug)]
+pub struct EvalError(ast::Error, Option<eval::Backtrace>);
+
+impl EvalError {
+    /// The error produced by `evaluator`, with the calls that led to it.
+    pub fn new(error: ast::Error, evaluator: &Evaluator) -> Self {
+        let backtrace = evaluator
+            .backtrace()
+            .filter(|backtrace| backtrace.error == error)
+            .cloned();
+        Self(error, backtrace)
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)?;
+        match &self.1 {
+            Some(backtrace) => backtrace.fmt(f),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
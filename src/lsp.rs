@@ -0,0 +1,506 @@
+// Language Server Protocol server, publishing diagnostics and answering
+// hover, definition and symbol requests for open `.rinha` documents.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use serde_json::{json, Value};
+
+use crate::ast::*;
+use crate::check::{self, Analysis, Kind, Severity};
+use crate::source::LineIndex;
+use crate::{parser, rpc};
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// An open document, parsed and checked after every change.
+struct Document {
+    text: String,
+    index: LineIndex,
+    file: Option<File>,
+    analysis: Analysis,
+    /// The parse error, if the document does not parse.
+    error: Option<Error>,
+}
+
+impl Document {
+    fn new(uri: &str, text: String) -> Self {
+        let index = LineIndex::new(&text);
+        let (file, analysis, error) = match parser::parse(&text, uri) {
+            Ok(file) => {
+                let analysis = check::check(&file);
+                (Some(file), analysis, None)
+            }
+            Err(error) => (None, Analysis::default(), Some(error)),
+        };
+        Self {
+            text,
+            index,
+            file,
+            analysis,
+            error,
+        }
+    }
+
+    /// The LSP position of a byte offset, counting UTF-16 code units.
+    fn position(&self, offset: usize) -> Value {
+        let offset = offset.min(self.text.len());
+        let line = self.index.line(offset);
+        let start = self.index.offset(line).unwrap_or_default();
+        let character = match self.text.get(start..offset) {
+            Some(text) => text.encode_utf16().count(),
+            None => offset - start,
+        };
+        json!({ "line": line - 1, "character": character })
+    }
+
+    fn range(&self, location: &Location) -> Value {
+        json!({
+            "start": self.position(location.start),
+            "end": self.position(location.end),
+        })
+    }
+
+    /// The byte offset of an LSP position.
+    fn offset(&self, position: &Value) -> Option<usize> {
+        let line = position["line"].as_u64()? as usize + 1;
+        let character = position["character"].as_u64()? as usize;
+        let start = self.index.offset(line)?;
+        let end = self.index.offset(line + 1).unwrap_or(self.text.len());
+        let mut units = 0;
+        for (i, c) in self.text[start..end].char_indices() {
+            if units >= character {
+                return Some(start + i);
+            }
+            units += c.len_utf16();
+        }
+        Some(end)
+    }
+
+    fn diagnostics(&self) -> Vec<Value> {
+        if let Some(error) = &self.error {
+            let diagnostic = json!({
+                "range": self.range(&error.location),
+                "severity": 1,
+                "source": "rinha",
+                "message": error.full_text,
+            });
+            return vec![diagnostic];
+        }
+
+        self.analysis
+            .diagnostics
+            .iter()
+            .map(|diagnostic| {
+                let severity = match diagnostic.severity {
+                    Severity::Error => 1,
+                    Severity::Warning => 2,
+                };
+                json!({
+                    "range": self.range(&diagnostic.location),
+                    "severity": severity,
+                    "source": "rinha",
+                    "message": diagnostic.message,
+                })
+            })
+            .collect()
+    }
+
+    fn hover(&self, offset: usize) -> Value {
+        let Some(binding) = self.analysis.binding_at(offset) else {
+            return Value::Null;
+        };
+        let kind = match binding.kind {
+            Kind::Let => "let",
+            Kind::Parameter => "parameter",
+            Kind::Import => "import",
+            Kind::Builtin => "builtin",
+        };
+        let signature =
+            format!("```rinha\n{kind} {}: {}\n```", binding.name, binding.ty);
+        let contents = match binding.kind {
+            Kind::Builtin => {
+                format!("{signature}\nProvided by the interpreter")
+            }
+            _ => {
+                let (line, _) = self.index.position(binding.location.start);
+                let definition = self
+                    .text
+                    .get(binding.definition.start..binding.definition.end)
+                    .and_then(|text| text.lines().next())
+                    .unwrap_or_default();
+                format!(
+                    "{signature}\nDefined on line {line}: `{}`",
+                    definition.trim()
+                )
+            }
+        };
+        json!({
+            "contents": { "kind": "markdown", "value": contents },
+        })
+    }
+
+    fn definition(&self, uri: &str, offset: usize) -> Value {
+        match self.analysis.binding_at(offset) {
+            Some(binding) if binding.kind != Kind::Builtin => json!({
+                "uri": uri,
+                "range": self.range(&binding.location),
+            }),
+            _ => Value::Null,
+        }
+    }
+
+    fn symbols(&self) -> Vec<Value> {
+        match &self.file {
+            Some(file) => self.symbols_in(&file.expression),
+            None => Vec::new(),
+        }
+    }
+
+    /// The `let`-bound functions in `term`, with the functions they bind
+    /// as children.
+    fn symbols_in(&self, term: &Term) -> Vec<Value> {
+        let mut symbols = Vec::new();
+        let mut terms = vec![term];
+        while let Some(term) = terms.pop() {
+            match term {
+                Term::Let(Let {
+                    name,
+                    value,
+                    next,
+                    location,
+                }) if matches!(**value, Term::Function(_)) => {
+                    // The function itself, without the rest of the program
+                    let range = Location {
+                        start: location.start,
+                        end: value.location().end,
+                        filename: location.filename.clone(),
+                    };
+                    let detail = self
+                        .analysis
+                        .binding(&name.location)
+                        .map(|binding| binding.ty.to_string());
+                    symbols.push(json!({
+                        "name": name.text,
+                        "detail": detail,
+                        "kind": 12,
+                        "range": self.range(&range),
+                        "selectionRange": self.range(&name.location),
+                        "children": self.symbols_in(value),
+                    }));
+                    terms.push(next);
+                }
+                term => {
+                    terms.extend(term.children().into_iter().rev());
+                }
+            }
+        }
+        symbols
+    }
+}
+
+struct Server<R, W> {
+    input: R,
+    output: W,
+    documents: HashMap<String, Document>,
+}
+
+/// Serves requests until the client asks the server to exit.
+pub fn serve<R: BufRead, W: Write>(input: R, output: W) -> io::Result<()> {
+    let mut server = Server {
+        input,
+        output,
+        documents: HashMap::new(),
+    };
+
+    while let Some(message) = rpc::read(&mut server.input)? {
+        let message = match message {
+            Ok(message) => message,
+            Err(error) => {
+                server.parse_error(&error)?;
+                continue;
+            }
+        };
+        let method = message["method"].as_str().unwrap_or_default();
+        if method == "exit" {
+            break;
+        }
+        server.handle(method, &message)?;
+    }
+
+    Ok(())
+}
+
+impl<R: BufRead, W: Write> Server<R, W> {
+    fn respond(&mut self, request: &Value, result: Value) -> io::Result<()> {
+        let response = json!({
+            "jsonrpc": "2.0",
+            "id": request["id"],
+            "result": result,
+        });
+        rpc::write(&mut self.output, &response)
+    }
+
+    /// Answers a message that isn't JSON, whose id can't be known.
+    fn parse_error(&mut self, error: &serde_json::Error) -> io::Result<()> {
+        let response = json!({
+            "jsonrpc": "2.0",
+            "id": null,
+            "error": {
+                "code": PARSE_ERROR,
+                "message": format!("Parse error: {error}"),
+            },
+        });
+        rpc::write(&mut self.output, &response)
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> io::Result<()> {
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+        });
+        rpc::write(&mut self.output, &notification)
+    }
+
+    fn publish(&mut self, uri: &str) -> io::Result<()> {
+        let diagnostics = match self.documents.get(uri) {
+            Some(document) => document.diagnostics(),
+            None => Vec::new(),
+        };
+        let params = json!({ "uri": uri, "diagnostics": diagnostics });
+        self.notify("textDocument/publishDiagnostics", params)
+    }
+
+    /// The document and byte offset of a text document position request.
+    fn locate<'a>(
+        &'a self,
+        params: &'a Value,
+    ) -> Option<(&'a str, &'a Document, usize)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let document = self.documents.get(uri)?;
+        let offset = document.offset(&params["position"])?;
+        Some((uri, document, offset))
+    }
+
+    fn handle(&mut self, method: &str, message: &Value) -> io::Result<()> {
+        let params = &message["params"];
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+
+        match method {
+            "initialize" => {
+                let result = json!({
+                    "capabilities": {
+                        "textDocumentSync": 1,
+                        "hoverProvider": true,
+                        "definitionProvider": true,
+                        "documentSymbolProvider": true,
+                    },
+                    "serverInfo": { "name": "rinha-interpreter" },
+                });
+                self.respond(message, result)
+            }
+            "shutdown" => self.respond(message, Value::Null),
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str();
+                let document =
+                    Document::new(uri, text.unwrap_or_default().into());
+                self.documents.insert(uri.into(), document);
+                self.publish(uri)
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                let text = changes
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str());
+                if let Some(text) = text {
+                    let document = Document::new(uri, text.into());
+                    self.documents.insert(uri.into(), document);
+                }
+                self.publish(uri)
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                self.publish(uri)
+            }
+            "textDocument/hover" => {
+                let result = match self.locate(params) {
+                    Some((_, document, offset)) => document.hover(offset),
+                    None => Value::Null,
+                };
+                self.respond(message, result)
+            }
+            "textDocument/definition" => {
+                let result = match self.locate(params) {
+                    Some((uri, document, offset)) => {
+                        document.definition(uri, offset)
+                    }
+                    None => Value::Null,
+                };
+                self.respond(message, result)
+            }
+            "textDocument/documentSymbol" => {
+                let symbols = match self.documents.get(uri) {
+                    Some(document) => document.symbols(),
+                    None => Vec::new(),
+                };
+                self.respond(message, json!(symbols))
+            }
+            // Requests need a response, notifications may be ignored
+            _ if message.get("id").is_some() => {
+                let response = json!({
+                    "jsonrpc": "2.0",
+                    "id": message["id"],
+                    "error": {
+                        "code": METHOD_NOT_FOUND,
+                        "message": format!("unsupported method {method}"),
+                    },
+                });
+                rpc::write(&mut self.output, &response)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///fib.rinha";
+
+    const SOURCE: &str = "\
+// Números de Fibonacci
+let fib = fn (n) => {
+  if (n < 2) { n } else { fib(n - 1) + fib(n - 2) }
+};
+print(fib(x))
+";
+
+    /// Runs a session with the given messages, returning every message
+    /// sent by the server.
+    fn session(messages: &[Value]) -> Vec<Value> {
+        let mut input = Vec::new();
+        for message in messages {
+            rpc::write(&mut input, message).unwrap();
+        }
+        replies(&input)
+    }
+
+    /// Runs a session on the raw `input`, returning every message sent by
+    /// the server.
+    fn replies(input: &[u8]) -> Vec<Value> {
+        let mut output = Vec::new();
+        serve(input, &mut output).unwrap();
+
+        let mut output = &output[..];
+        let read = || rpc::read(&mut output).unwrap().map(Result::unwrap);
+        std::iter::from_fn(read).collect()
+    }
+
+    fn request(id: u64, method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params })
+    }
+
+    fn notification(method: &str, params: Value) -> Value {
+        json!({ "jsonrpc": "2.0", "method": method, "params": params })
+    }
+
+    fn position(line: u64, character: u64) -> Value {
+        json!({
+            "textDocument": { "uri": URI },
+            "position": { "line": line, "character": character },
+        })
+    }
+
+    #[test]
+    fn lsp_session() {
+        let open = json!({
+            "textDocument": {
+                "uri": URI,
+                "languageId": "rinha",
+                "version": 1,
+                "text": SOURCE,
+            },
+        });
+        let messages = session(&[
+            request(1, "initialize", json!({ "capabilities": {} })),
+            notification("initialized", json!({})),
+            notification("textDocument/didOpen", open),
+            request(2, "textDocument/hover", position(2, 30)),
+            request(3, "textDocument/definition", position(2, 40)),
+            request(4, "textDocument/documentSymbol", position(0, 0)),
+            request(5, "textDocument/formatting", json!({})),
+            request(6, "shutdown", Value::Null),
+            notification("exit", Value::Null),
+        ]);
+
+        assert_eq!(messages.len(), 7);
+        assert_eq!(
+            messages[0]["result"]["capabilities"]["hoverProvider"],
+            true
+        );
+
+        let diagnostics = &messages[1]["params"]["diagnostics"];
+        assert_eq!(
+            diagnostics,
+            &json!([{
+                "range": {
+                    "start": { "line": 4, "character": 10 },
+                    "end": { "line": 4, "character": 11 },
+                },
+                "severity": 1,
+                "source": "rinha",
+                "message": "Undefined variable \"x\"",
+            }])
+        );
+
+        let hover = messages[2]["result"]["contents"]["value"].as_str();
+        let expected = "\
+```rinha
+parameter n: Int
+```
+Defined on line 2: `fn (n) => {`";
+        assert_eq!(hover, Some(expected));
+
+        let range = &messages[3]["result"]["range"];
+        let expected = json!({
+            "start": { "line": 1, "character": 4 },
+            "end": { "line": 1, "character": 7 },
+        });
+        assert_eq!(range, &expected);
+
+        let symbols = messages[4]["result"].as_array().unwrap();
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(symbols[0]["name"], "fib");
+        assert_eq!(symbols[0]["detail"], "fn (n: Int) => Int");
+        assert_eq!(symbols[0]["range"]["start"]["line"], 1);
+
+        assert_eq!(messages[5]["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(messages[6]["result"], Value::Null);
+    }
+
+    #[test]
+    fn lsp_malformed_message() {
+        let mut input = b"Content-Length: 5\r\n\r\n{id:}".to_vec();
+        let symbols = json!({ "textDocument": { "uri": URI } });
+        let request = request(1, "textDocument/documentSymbol", symbols);
+        rpc::write(&mut input, &request).unwrap();
+
+        let messages = replies(&input);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["id"], Value::Null);
+        assert_eq!(messages[0]["error"]["code"], PARSE_ERROR);
+        assert_eq!(messages[1]["id"], 1);
+        assert_eq!(messages[1]["result"], json!([]));
+    }
+
+    #[test]
+    fn lsp_parse_error() {
+        let document = Document::new(URI, "let x = ;\nx".into());
+        let diagnostics = document.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0]["range"]["start"]["character"], 8);
+    }
+}
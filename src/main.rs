@@ -1,218 +1,246 @@
-pub mod ast;
-pub mod env;
-pub mod eval;
-
-use std::fmt;
-
-use crate::ast::Term;
-use crate::eval::Evaluator;
-
-#[derive(Debug)]
-pub struct EvalError(ast::Error);
-
-impl fmt::Display for EvalError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let ast::Location {
-            start,
-            end,
-            filename,
-        } = &self.0.location;
-        let message = &self.0.message;
-        let full_text = &self.0.full_text;
-        writeln!(f, "[Error ({}:{}:{})] {}", filename, start, end, message)?;
-        writeln!(f, "{}", full_text)?;
-        Ok(())
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::rc::Rc;
+
+use rinha_interpreter::ast::{Element, Term};
+use rinha_interpreter::cli::{Command, CompileArgs, DebugArgs, DotArgs};
+use rinha_interpreter::cli::{FmtArgs, RunArgs};
+use rinha_interpreter::eval::Evaluator;
+use rinha_interpreter::EvalError;
+use rinha_interpreter::{binary, check, cli, coverage, dap, debug, dot, error};
+use rinha_interpreter::{format, lsp, parser, prelude, profile, report};
+use rinha_interpreter::{source, trace};
+
+fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let command = cli::parse(&args);
+
+    let (format, program, expression) = match &command {
+        Ok(Command::Run(args)) => (
+            args.error_format,
+            args.path.clone(),
+            args.expression.clone(),
+        ),
+        _ => Default::default(),
+    };
+
+    let result = match command {
+        Ok(Command::Usage) => {
+            eprintln!("{}", cli::USAGE);
+            Ok(())
+        }
+        Ok(Command::Run(args)) => run(args),
+        Ok(Command::Fmt(args)) => fmt(args),
+        Ok(Command::Dot(args)) => dot(args),
+        Ok(Command::Debug(args)) => debug(args),
+        Ok(Command::CompileAst(args)) => compile_ast(args),
+        Ok(Command::Dap) => {
+            let input = std::io::stdin().lock();
+            dap::serve(input, std::io::stdout()).map_err(Into::into)
+        }
+        Ok(Command::Lsp) => {
+            let input = std::io::stdin().lock();
+            lsp::serve(input, std::io::stdout()).map_err(Into::into)
+        }
+        Ok(Command::Explain(code)) => match error::explain(&code) {
+            Some(explanation) => {
+                println!("{explanation}");
+                Ok(())
+            }
+            None => Err(format!("error: unknown error code {code}").into()),
+        },
+        Err(message) => {
+            eprintln!("error: {message}\n\n{}", cli::USAGE);
+            std::process::exit(report::EXIT_USAGE);
+        }
+    };
+
+    if let Err(e) = result {
+        match format {
+            report::Format::Human => eprintln!("{}", e.to_string().trim_end()),
+            report::Format::Json => {
+                let mut locator = report::Locator::new(&program);
+                if let Some(expression) = &expression {
+                    locator.source(source::EXPRESSION_NAME, expression);
+                }
+                for diagnostic in locator.diagnostics(e.as_ref()) {
+                    eprintln!("{diagnostic}");
+                }
+            }
+        }
+        std::process::exit(report::Kind::of(e.as_ref()).exit_code());
     }
 }
 
-impl std::error::Error for EvalError {}
+fn run(args: RunArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let file = match &args.expression {
+        Some(expression) => parser::parse(expression, source::EXPRESSION_NAME)?,
+        None => source::load(&args.path)?,
+    };
+    check::parse_errors(&file)?;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut args = std::env::args().collect::<Vec<_>>();
+    let mut evaluator = Evaluator::new();
+    evaluator.precision(args.precision);
+    evaluator.overflow(args.overflow);
 
-    if args.len() != 2 {
-        eprintln!("Usage: {} <json-file>", args[0]);
-        return Ok(());
+    if let Some(options) = &args.trace {
+        let output: Box<dyn std::io::Write> = match &options.output {
+            Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+            None => Box::new(std::io::stderr()),
+        };
+        evaluator.observe(trace::Tracer::new(options, output));
     }
 
-    let contents = std::fs::read_to_string(args.pop().unwrap())?;
-    let file: ast::File = serde_json::from_str(&contents)?;
+    let profiler = Rc::new(RefCell::new(profile::Profiler::new()));
+
+    if args.profile.is_some() {
+        evaluator.observe(profiler.clone());
+    }
+
+    let mut coverage = None;
+
+    if args.coverage.is_some() {
+        let observer = Rc::new(RefCell::new(coverage::Coverage::new(&file)));
+        evaluator.observe(observer.clone());
+        coverage = Some(observer);
+    }
 
-    let mut env = Default::default();
+    let mut env = prelude::env();
     let term = file.expression;
-    let result = Evaluator::eval(&mut env, term);
+    let result = evaluator.eval(&mut env, term);
+
+    if let Some(options) = &args.profile {
+        let profiler = profiler.borrow();
+        eprint!("{}", profiler.summary());
+        std::fs::write(&options.output, profiler.folded())?;
+    }
+
+    if let (Some(options), Some(coverage)) = (&args.coverage, &coverage) {
+        let coverage = coverage.borrow();
+        let mut sources = HashMap::new();
+        for filename in coverage.files() {
+            let (_, source) = source::read(&args.path, filename)?;
+            sources
+                .insert(filename.to_string(), source::LineIndex::new(&source));
+        }
+        eprint!("{}", coverage.summary(&sources));
+        std::fs::write(&options.output, coverage.lcov(&sources))?;
+    }
+
+    match &result {
+        Term::Error(e) => Err(Box::new(EvalError::new(e.clone(), &evaluator))),
+        _ => Ok(()),
+    }
+}
+
+fn debug(args: DebugArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let file = source::load(&args.path)?;
+    check::parse_errors(&file)?;
 
-    match result {
-        Term::Error(e) => Err(Box::new(EvalError(e))),
+    let console =
+        debug::Console::new(std::io::stdin().lock(), std::io::stdout());
+    let debugger = Rc::new(RefCell::new(debug::Debugger::new(console)));
+
+    {
+        let mut debugger = debugger.borrow_mut();
+        let filename = &file.expression.location().filename;
+        if let Ok((_, source)) = source::read(&args.path, filename) {
+            debugger.source(filename, &source);
+        }
+        debugger.state_mut().breakpoints = args.breakpoints;
+        debugger.state_mut().precision = args.precision;
+        debugger.state_mut().overflow = args.overflow;
+    }
+
+    let mut evaluator = Evaluator::new();
+    evaluator.precision(args.precision);
+    evaluator.overflow(args.overflow);
+    evaluator.observe(debugger.clone());
+
+    let mut env = prelude::env();
+    let result = evaluator.eval(&mut env, file.expression);
+
+    match &result {
+        _ if debugger.borrow().state().quit() => Ok(()),
+        Term::Error(e) => Err(Box::new(EvalError::new(e.clone(), &evaluator))),
         _ => Ok(()),
     }
 }
 
+fn dot(args: DotArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let file = source::load(&args.path)?;
+    let graph = dot::render(&file, &args.options)
+        .ok_or("no term found at the given location")?;
+    print!("{graph}");
+    Ok(())
+}
+
+fn compile_ast(args: CompileArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let file = source::load(&args.path)?;
+    let bytes = binary::encode(&file);
+    match args.output().as_str() {
+        source::STDIO => std::io::stdout().write_all(&bytes)?,
+        output => std::fs::write(output, bytes)?,
+    }
+    Ok(())
+}
+
+fn fmt(args: FmtArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let mut unformatted = Vec::new();
+
+    for path in &args.paths {
+        let source = std::fs::read_to_string(path)?;
+        let formatted = format::format(&source, path, &args.config)?;
+
+        if args.check {
+            if formatted != source {
+                unformatted.push(path.as_str());
+            }
+        } else if args.stdout {
+            print!("{formatted}");
+        } else if formatted != source {
+            std::fs::write(path, formatted)?;
+        }
+    }
+
+    match unformatted.is_empty() {
+        true => Ok(()),
+        false => {
+            Err(format!("not formatted: {}", unformatted.join(", ")).into())
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{ast::*, eval::Evaluator};
+    use rinha_interpreter::ast::build::*;
+    use rinha_interpreter::ast::BinaryOp::*;
+    use rinha_interpreter::eval::Evaluator;
 
     #[test]
     fn factorial() {
         let mut env = Default::default();
-        let term = Term::Let(Let {
-            name: Var {
-                text: "factorial".into(),
-                location: Location {
-                    start: 0,
-                    end: 0,
-                    filename: Default::default(),
-                },
-            },
-            value: Box::new(Term::Function(Function {
-                parameters: vec![Var {
-                    text: "n".into(),
-                    location: Location {
-                        start: 1,
-                        end: 1,
-                        filename: Default::default(),
-                    },
-                }],
-                value: Box::new(Term::If(If {
-                    condition: Box::new(Term::Binary(Binary {
-                        lhs: Box::new(Term::Var(Var {
-                            text: "n".into(),
-                            location: Location {
-                                start: 2,
-                                end: 2,
-                                filename: Default::default(),
-                            },
-                        })),
-                        op: BinaryOp::Lte,
-                        rhs: Box::new(Term::Int(Int {
-                            value: 1,
-                            location: Location {
-                                start: 3,
-                                end: 3,
-                                filename: Default::default(),
-                            },
-                        })),
-                        location: Location {
-                            start: 4,
-                            end: 4,
-                            filename: Default::default(),
-                        },
-                    })),
-                    then: Box::new(Term::Int(Int {
-                        value: 1,
-                        location: Location {
-                            start: 5,
-                            end: 5,
-                            filename: Default::default(),
-                        },
-                    })),
-                    otherwise: Box::new(Term::Binary(Binary {
-                        lhs: Box::new(Term::Var(Var {
-                            text: "n".into(),
-                            location: Location {
-                                start: 6,
-                                end: 6,
-                                filename: Default::default(),
-                            },
-                        })),
-                        op: BinaryOp::Mul,
-                        rhs: Box::new(Term::Call(Call {
-                            callee: Box::new(Term::Var(Var {
-                                text: "factorial".into(),
-                                location: Location {
-                                    start: 7,
-                                    end: 7,
-                                    filename: Default::default(),
-                                },
-                            })),
-                            arguments: vec![Term::Binary(Binary {
-                                lhs: Box::new(Term::Var(Var {
-                                    text: "n".into(),
-                                    location: Location {
-                                        start: 8,
-                                        end: 8,
-                                        filename: Default::default(),
-                                    },
-                                })),
-                                op: BinaryOp::Sub,
-                                rhs: Box::new(Term::Int(Int {
-                                    value: 1,
-                                    location: Location {
-                                        start: 9,
-                                        end: 9,
-                                        filename: Default::default(),
-                                    },
-                                })),
-                                location: Location {
-                                    start: 10,
-                                    end: 10,
-                                    filename: Default::default(),
-                                },
-                            })],
-                            location: Location {
-                                start: 11,
-                                end: 11,
-                                filename: Default::default(),
-                            },
-                        })),
-                        location: Location {
-                            start: 12,
-                            end: 12,
-                            filename: Default::default(),
-                        },
-                    })),
-                    location: Location {
-                        start: 13,
-                        end: 13,
-                        filename: Default::default(),
-                    },
-                })),
-                location: Location {
-                    start: 14,
-                    end: 14,
-                    filename: Default::default(),
-                },
-            })),
-            next: Box::new(Term::Call(Call {
-                callee: Box::new(Term::Var(Var {
-                    text: "factorial".into(),
-                    location: Location {
-                        start: 15,
-                        end: 15,
-                        filename: Default::default(),
-                    },
-                })),
-                arguments: vec![Term::Int(Int {
-                    value: 4,
-                    location: Location {
-                        start: 16,
-                        end: 16,
-                        filename: Default::default(),
-                    },
-                })],
-                location: Location {
-                    start: 17,
-                    end: 17,
-                    filename: Default::default(),
-                },
-            })),
-            location: Location {
-                start: 18,
-                end: 18,
-                filename: Default::default(),
-            },
-        });
-
-        let result = Evaluator::eval(&mut env, term);
-        let term = Term::Int(Int {
-            value: 24,
-            location: Location {
-                start: 12,
-                end: 12,
-                filename: Default::default(),
-            },
-        });
-
-        assert_eq!(term, result);
+        let term = let_(
+            "factorial",
+            fun(
+                ["n"],
+                if_(
+                    binary(var("n"), Lte, int(1)),
+                    int(1),
+                    binary(
+                        var("n"),
+                        Mul,
+                        call(var("factorial"), [binary(var("n"), Sub, int(1))]),
+                    ),
+                ),
+            ),
+            call(var("factorial"), [int(4)]),
+        );
+
+        let result = Evaluator::new().eval(&mut env, term);
+
+        assert_eq!(int(24), result);
     }
 }
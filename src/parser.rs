@@ -0,0 +1,729 @@
+// Parser for the textual `rinha` syntax, producing the same AST that the
+// upstream parser serializes to JSON.
+
+use crate::ast::*;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Comment {
+    pub text: String,
+    pub location: Location,
+    /// Whether the comment follows code on the same line.
+    pub trailing: bool,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Kind {
+    Int,
+    Str,
+    Ident,
+    Let,
+    Import,
+    Fn,
+    If,
+    Else,
+    Print,
+    First,
+    Second,
+    True,
+    False,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    Comma,
+    Semi,
+    Assign,
+    Arrow,
+    Op(BinaryOp),
+    Eof,
+}
+
+impl Kind {
+    fn describe(&self) -> &'static str {
+        match self {
+            Kind::Int => "integer",
+            Kind::Str => "string",
+            Kind::Ident => "identifier",
+            Kind::Let => "\"let\"",
+            Kind::Import => "\"import\"",
+            Kind::Fn => "\"fn\"",
+            Kind::If => "\"if\"",
+            Kind::Else => "\"else\"",
+            Kind::Print => "\"print\"",
+            Kind::First => "\"first\"",
+            Kind::Second => "\"second\"",
+            Kind::True => "\"true\"",
+            Kind::False => "\"false\"",
+            Kind::LParen => "\"(\"",
+            Kind::RParen => "\")\"",
+            Kind::LBrace => "\"{\"",
+            Kind::RBrace => "\"}\"",
+            Kind::Comma => "\",\"",
+            Kind::Semi => "\";\"",
+            Kind::Assign => "\"=\"",
+            Kind::Arrow => "\"=>\"",
+            Kind::Op(_) => "operator",
+            Kind::Eof => "end of file",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Token {
+    kind: Kind,
+    start: usize,
+    end: usize,
+}
+
+/// Binding strength of a binary operator, higher binds tighter.
+pub fn precedence(op: BinaryOp) -> u8 {
+    match op {
+        BinaryOp::Or => 1,
+        BinaryOp::And => 2,
+        BinaryOp::Eq | BinaryOp::Neq => 3,
+        BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Lte | BinaryOp::Gte => 4,
+        BinaryOp::Add | BinaryOp::Sub => 5,
+        BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 6,
+    }
+}
+
+pub fn parse(source: &str, filename: &str) -> Result<File, Error> {
+    parse_with_comments(source, filename).map(|(file, _)| file)
+}
+
+pub fn parse_with_comments(
+    source: &str,
+    filename: &str,
+) -> Result<(File, Vec<Comment>), Error> {
+    let (tokens, comments) = tokenize(source, filename)?;
+    let mut parser = Parser {
+        source,
+        filename,
+        tokens,
+        pos: 0,
+    };
+    let expression = parser.parse_term()?;
+    parser.expect(Kind::Eof)?;
+    let file = File {
+        name: filename.into(),
+        expression,
+        location: Location::new(0, source.len(), filename),
+    };
+    Ok((file, comments))
+}
+
+fn error(
+    start: usize,
+    end: usize,
+    filename: &str,
+    message: &str,
+    full_text: String,
+) -> Error {
+    Error {
+        message: message.into(),
+        full_text,
+        location: Location::new(start, end, filename),
+        kind: None,
+    }
+}
+
+fn tokenize(
+    source: &str,
+    filename: &str,
+) -> Result<(Vec<Token>, Vec<Comment>), Error> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut comments = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let start = i;
+        let c = bytes[i];
+
+        if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        if c == b'/' && matches!(bytes.get(i + 1), Some(b'/') | Some(b'*')) {
+            if bytes[i + 1] == b'/' {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            } else {
+                match source[i + 2..].find("*/") {
+                    Some(offset) => i += offset + 4,
+                    None => {
+                        let full_text = "Expected \"*/\"".into();
+                        return Err(error(
+                            start,
+                            bytes.len(),
+                            filename,
+                            "Unterminated comment",
+                            full_text,
+                        ));
+                    }
+                }
+            }
+            let line = &source[..start];
+            let line = &line[line.rfind('\n').map_or(0, |n| n + 1)..];
+            comments.push(Comment {
+                text: source[start..i].trim_end().into(),
+                location: Location::new(start, i, filename),
+                trailing: !line.trim().is_empty(),
+            });
+            continue;
+        }
+
+        let kind = match c {
+            b'0'..=b'9' => {
+                while i < bytes.len() && bytes[i].is_ascii_digit() {
+                    i += 1;
+                }
+                Kind::Int
+            }
+            b'"' => {
+                i += 1;
+                loop {
+                    match bytes.get(i) {
+                        Some(b'"') => break,
+                        Some(b'\\') => i += 2,
+                        Some(_) => i += 1,
+                        None => {
+                            let full_text = "Expected closing \"\\\"\"".into();
+                            return Err(error(
+                                start,
+                                bytes.len(),
+                                filename,
+                                "Unterminated string",
+                                full_text,
+                            ));
+                        }
+                    }
+                }
+                i += 1;
+                Kind::Str
+            }
+            c if c == b'_' || c.is_ascii_alphabetic() => {
+                while i < bytes.len()
+                    && (bytes[i] == b'_' || bytes[i].is_ascii_alphanumeric())
+                {
+                    i += 1;
+                }
+                match &source[start..i] {
+                    "let" => Kind::Let,
+                    "import" => Kind::Import,
+                    "fn" => Kind::Fn,
+                    "if" => Kind::If,
+                    "else" => Kind::Else,
+                    "print" => Kind::Print,
+                    "first" => Kind::First,
+                    "second" => Kind::Second,
+                    "true" => Kind::True,
+                    "false" => Kind::False,
+                    _ => Kind::Ident,
+                }
+            }
+            _ => {
+                let next = bytes.get(i + 1).copied();
+                let (kind, len) = match (c, next) {
+                    (b'=', Some(b'>')) => (Kind::Arrow, 2),
+                    (b'=', Some(b'=')) => (Kind::Op(BinaryOp::Eq), 2),
+                    (b'!', Some(b'=')) => (Kind::Op(BinaryOp::Neq), 2),
+                    (b'<', Some(b'=')) => (Kind::Op(BinaryOp::Lte), 2),
+                    (b'>', Some(b'=')) => (Kind::Op(BinaryOp::Gte), 2),
+                    (b'&', Some(b'&')) => (Kind::Op(BinaryOp::And), 2),
+                    (b'|', Some(b'|')) => (Kind::Op(BinaryOp::Or), 2),
+                    (b'=', _) => (Kind::Assign, 1),
+                    (b'<', _) => (Kind::Op(BinaryOp::Lt), 1),
+                    (b'>', _) => (Kind::Op(BinaryOp::Gt), 1),
+                    (b'+', _) => (Kind::Op(BinaryOp::Add), 1),
+                    (b'-', _) => (Kind::Op(BinaryOp::Sub), 1),
+                    (b'*', _) => (Kind::Op(BinaryOp::Mul), 1),
+                    (b'/', _) => (Kind::Op(BinaryOp::Div), 1),
+                    (b'%', _) => (Kind::Op(BinaryOp::Rem), 1),
+                    (b'(', _) => (Kind::LParen, 1),
+                    (b')', _) => (Kind::RParen, 1),
+                    (b'{', _) => (Kind::LBrace, 1),
+                    (b'}', _) => (Kind::RBrace, 1),
+                    (b',', _) => (Kind::Comma, 1),
+                    (b';', _) => (Kind::Semi, 1),
+                    _ => {
+                        let ch = source[start..].chars().next().unwrap();
+                        let full_text = format!("Unexpected character {ch:?}");
+                        return Err(error(
+                            start,
+                            start + ch.len_utf8(),
+                            filename,
+                            "Invalid token",
+                            full_text,
+                        ));
+                    }
+                };
+                i += len;
+                kind
+            }
+        };
+
+        tokens.push(Token {
+            kind,
+            start,
+            end: i,
+        });
+    }
+
+    tokens.push(Token {
+        kind: Kind::Eof,
+        start: bytes.len(),
+        end: bytes.len(),
+    });
+
+    Ok((tokens, comments))
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    filename: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Token {
+        self.tokens[self.pos]
+    }
+
+    fn bump(&mut self) -> Token {
+        let token = self.peek();
+        if token.kind != Kind::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn text(&self, token: Token) -> &'a str {
+        &self.source[token.start..token.end]
+    }
+
+    fn location(&self, start: usize, end: usize) -> Location {
+        Location::new(start, end, self.filename)
+    }
+
+    fn unexpected(&self, expected: &str) -> Error {
+        let token = self.peek();
+        let found = match token.kind {
+            Kind::Eof => "end of file".into(),
+            _ => format!("\"{}\"", self.text(token)),
+        };
+        let full_text = format!("Expected {expected}, found {found}");
+        error(
+            token.start,
+            token.end,
+            self.filename,
+            "Unexpected token",
+            full_text,
+        )
+    }
+
+    fn expect(&mut self, kind: Kind) -> Result<Token, Error> {
+        match self.peek().kind == kind {
+            true => Ok(self.bump()),
+            false => Err(self.unexpected(kind.describe())),
+        }
+    }
+
+    fn parse_term(&mut self) -> Result<Term, Error> {
+        match self.peek().kind {
+            Kind::Let => self.parse_let(),
+            Kind::Import => self.parse_import(),
+            _ => self.parse_binary(0),
+        }
+    }
+
+    fn parse_let(&mut self) -> Result<Term, Error> {
+        let start = self.expect(Kind::Let)?.start;
+        let name = self.parse_var()?;
+        self.expect(Kind::Assign)?;
+        let value = self.parse_term()?;
+        self.expect(Kind::Semi)?;
+        let next = self.parse_term()?;
+        let location = self.location(start, next.location().end);
+        Ok(Term::Let(Let {
+            name,
+            value: Box::new(value),
+            next: Box::new(next),
+            location,
+        }))
+    }
+
+    fn parse_import(&mut self) -> Result<Term, Error> {
+        let start = self.expect(Kind::Import)?.start;
+        let token = self.expect(Kind::Str)?;
+        let path = Str {
+            value: self.parse_str(token)?,
+            location: self.location(token.start, token.end),
+        };
+        self.expect(Kind::Semi)?;
+        let next = self.parse_term()?;
+        let location = self.location(start, next.location().end);
+        Ok(Term::Import(Import {
+            path,
+            next: Box::new(next),
+            location,
+        }))
+    }
+
+    fn parse_var(&mut self) -> Result<Var, Error> {
+        let token = self.expect(Kind::Ident)?;
+        Ok(Var {
+            text: self.text(token).into(),
+            location: self.location(token.start, token.end),
+        })
+    }
+
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Term, Error> {
+        let mut lhs = self.parse_postfix()?;
+
+        while let Kind::Op(op) = self.peek().kind {
+            let precedence = precedence(op);
+            if precedence < min_precedence {
+                break;
+            }
+            self.bump();
+            let rhs = self.parse_binary(precedence + 1)?;
+            let location =
+                self.location(lhs.location().start, rhs.location().end);
+            lhs = Term::Binary(Binary {
+                lhs: Box::new(lhs),
+                op,
+                rhs: Box::new(rhs),
+                location,
+            });
+        }
+
+        Ok(lhs)
+    }
+
+    fn parse_postfix(&mut self) -> Result<Term, Error> {
+        let mut callee = self.parse_primary()?;
+
+        while self.peek().kind == Kind::LParen {
+            self.bump();
+            let mut arguments = Vec::new();
+            while self.peek().kind != Kind::RParen {
+                arguments.push(self.parse_term()?);
+                if self.peek().kind != Kind::Comma {
+                    break;
+                }
+                self.bump();
+            }
+            let end = self.expect(Kind::RParen)?.end;
+            let location = self.location(callee.location().start, end);
+            callee = Term::Call(Call {
+                callee: Box::new(callee),
+                arguments,
+                location,
+            });
+        }
+
+        Ok(callee)
+    }
+
+    fn parse_primary(&mut self) -> Result<Term, Error> {
+        let token = self.peek();
+        match token.kind {
+            Kind::Int => {
+                self.bump();
+                self.parse_int(token.start, token)
+            }
+            Kind::Op(BinaryOp::Sub)
+                if self.tokens[self.pos + 1].kind == Kind::Int
+                    && self.tokens[self.pos + 1].start == token.end =>
+            {
+                self.bump();
+                let digits = self.bump();
+                self.parse_int(token.start, digits)
+            }
+            Kind::Str => {
+                self.bump();
+                let value = self.parse_str(token)?;
+                let location = self.location(token.start, token.end);
+                Ok(Term::Str(Str { value, location }))
+            }
+            Kind::True | Kind::False => {
+                self.bump();
+                let value = token.kind == Kind::True;
+                let location = self.location(token.start, token.end);
+                Ok(Term::Bool(Bool { value, location }))
+            }
+            Kind::Ident => self.parse_var().map(Term::Var),
+            Kind::LParen => {
+                self.bump();
+                let first = self.parse_term()?;
+                if self.peek().kind != Kind::Comma {
+                    self.expect(Kind::RParen)?;
+                    return Ok(first);
+                }
+                self.bump();
+                let second = self.parse_term()?;
+                let end = self.expect(Kind::RParen)?.end;
+                Ok(Term::Tuple(Tuple {
+                    first: Box::new(first),
+                    second: Box::new(second),
+                    location: self.location(token.start, end),
+                }))
+            }
+            Kind::Fn => {
+                self.bump();
+                self.expect(Kind::LParen)?;
+                let mut parameters = Vec::new();
+                while self.peek().kind != Kind::RParen {
+                    parameters.push(self.parse_var()?);
+                    if self.peek().kind != Kind::Comma {
+                        break;
+                    }
+                    self.bump();
+                }
+                self.expect(Kind::RParen)?;
+                self.expect(Kind::Arrow)?;
+                let (value, end) = self.parse_block()?;
+                Ok(Term::Function(Function {
+                    parameters,
+                    value: Box::new(value),
+                    location: self.location(token.start, end),
+                }))
+            }
+            Kind::If => {
+                self.bump();
+                self.expect(Kind::LParen)?;
+                let condition = self.parse_term()?;
+                self.expect(Kind::RParen)?;
+                let (then, _) = self.parse_block()?;
+                self.expect(Kind::Else)?;
+                let (otherwise, end) = self.parse_block()?;
+                Ok(Term::If(If {
+                    condition: Box::new(condition),
+                    then: Box::new(then),
+                    otherwise: Box::new(otherwise),
+                    location: self.location(token.start, end),
+                }))
+            }
+            Kind::Print | Kind::First | Kind::Second => {
+                self.bump();
+                self.expect(Kind::LParen)?;
+                let value = Box::new(self.parse_term()?);
+                let end = self.expect(Kind::RParen)?.end;
+                let location = self.location(token.start, end);
+                Ok(match token.kind {
+                    Kind::Print => Term::Print(Print { value, location }),
+                    Kind::First => Term::First(First { value, location }),
+                    _ => Term::Second(Second { value, location }),
+                })
+            }
+            _ => Err(self.unexpected("expression")),
+        }
+    }
+
+    fn parse_block(&mut self) -> Result<(Term, usize), Error> {
+        self.expect(Kind::LBrace)?;
+        let term = self.parse_term()?;
+        let end = self.expect(Kind::RBrace)?.end;
+        Ok((term, end))
+    }
+
+    fn parse_int(&self, start: usize, digits: Token) -> Result<Term, Error> {
+        let text = &self.source[start..digits.end];
+        let location = self.location(start, digits.end);
+        match text.parse() {
+            Ok(value) => Ok(Term::Int(Int { value, location })),
+            Err(_) => {
+                let full_text = format!("Invalid integer literal {text}");
+                Err(Error {
+                    message: "Invalid integer".into(),
+                    full_text,
+                    location,
+                    kind: None,
+                })
+            }
+        }
+    }
+
+    fn parse_str(&self, token: Token) -> Result<String, Error> {
+        let text = &self.source[token.start + 1..token.end - 1];
+        let mut value = String::with_capacity(text.len());
+        let mut chars = text.char_indices();
+
+        while let Some((_, c)) = chars.next() {
+            if c != '\\' {
+                value.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 't')) => value.push('\t'),
+                Some((_, 'r')) => value.push('\r'),
+                Some((_, '0')) => value.push('\0'),
+                Some((_, '"')) => value.push('"'),
+                Some((_, '\\')) => value.push('\\'),
+                Some((offset, c)) => {
+                    let start = token.start + 1 + offset - 1;
+                    let end = start + 1 + c.len_utf8();
+                    let full_text =
+                        format!("Unknown escape sequence \"\\{c}\"");
+                    return Err(error(
+                        start,
+                        end,
+                        self.filename,
+                        "Invalid string",
+                        full_text,
+                    ));
+                }
+                None => unreachable!("strings end with an unescaped quote"),
+            }
+        }
+
+        Ok(value)
+    }
+}
+
+/// Escapes a string so that it can be parsed back into the same value.
+pub fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            '\0' => escaped.push_str("\\0"),
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_let_call() {
+        let file = parse("let id = fn (x) => { x };\nid(42)", "id.rinha");
+        let file = file.unwrap();
+        let Term::Let(term) = &file.expression else {
+            panic!("expected let");
+        };
+        assert_eq!(term.name.text, "id");
+        assert_eq!(term.location, Location::new(0, 32, "id.rinha"));
+        assert!(matches!(*term.value, Term::Function(_)));
+        let Term::Call(call) = &*term.next else {
+            panic!("expected call");
+        };
+        assert_eq!(call.location, Location::new(26, 32, "id.rinha"));
+        assert_eq!(call.arguments.len(), 1);
+    }
+
+    #[test]
+    fn parse_import() {
+        let file = parse("import \"lib.rinha\";\nx", "main.rinha").unwrap();
+        let Term::Import(term) = &file.expression else {
+            panic!("expected import");
+        };
+        assert_eq!(term.path.value, "lib.rinha");
+        assert_eq!(term.path.location, Location::new(7, 18, "main.rinha"));
+        assert_eq!(term.location, Location::new(0, 21, "main.rinha"));
+        assert!(matches!(*term.next, Term::Var(_)));
+
+        assert!(parse("import lib;\nx", "main.rinha").is_err());
+    }
+
+    #[test]
+    fn parse_precedence() {
+        let file = parse("1 + 2 * 3 == 7 || false", "").unwrap();
+        let Term::Binary(or) = &file.expression else {
+            panic!("expected binary");
+        };
+        assert_eq!(or.op, BinaryOp::Or);
+        let Term::Binary(eq) = &*or.lhs else {
+            panic!("expected binary");
+        };
+        assert_eq!(eq.op, BinaryOp::Eq);
+        let Term::Binary(add) = &*eq.lhs else {
+            panic!("expected binary");
+        };
+        assert_eq!(add.op, BinaryOp::Add);
+        assert!(matches!(
+            *add.rhs,
+            Term::Binary(Binary {
+                op: BinaryOp::Mul,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn parse_literals() {
+        let file = parse("(\"a\\n\\\"b\\\"\", (-5, true))", "").unwrap();
+        let Term::Tuple(tuple) = &file.expression else {
+            panic!("expected tuple");
+        };
+        let Term::Str(Str { ref value, .. }) = *tuple.first else {
+            panic!("expected str");
+        };
+        assert_eq!(value, "a\n\"b\"");
+        let Term::Tuple(ref tuple) = *tuple.second else {
+            panic!("expected tuple");
+        };
+        let Term::Int(ref int) = *tuple.first else {
+            panic!("expected int");
+        };
+        assert_eq!(int.value, (-5).into());
+        assert!(matches!(
+            *tuple.second,
+            Term::Bool(Bool { value: true, .. })
+        ));
+    }
+
+    #[test]
+    fn parse_comments() {
+        let source = "// leading\nlet x = 1; // trailing\n/* block */ x";
+        let (_, comments) = parse_with_comments(source, "").unwrap();
+        let texts = comments.iter().map(|c| c.text.as_str());
+        let texts = texts.collect::<Vec<_>>();
+        assert_eq!(texts, ["// leading", "// trailing", "/* block */"]);
+        let trailing = comments.iter().map(|c| c.trailing);
+        let trailing = trailing.collect::<Vec<_>>();
+        assert_eq!(trailing, [false, true, false]);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = parse("let x = 1 x", "a.rinha").unwrap_err();
+        assert_eq!(error.message, "Unexpected token");
+        assert_eq!(error.full_text, "Expected \";\", found \"x\"");
+        assert_eq!(error.location, Location::new(10, 11, "a.rinha"));
+
+        let error = parse("\"abc", "").unwrap_err();
+        assert_eq!(error.message, "Unterminated string");
+
+        // Literals are only checked against the precision when evaluated
+        let file = parse("99999999999999999999", "").unwrap();
+        let Term::Int(int) = &file.expression else {
+            panic!("expected int");
+        };
+        assert_eq!(int.value.to_string(), "99999999999999999999");
+    }
+
+    #[test]
+    fn escape_roundtrip() {
+        let value = "tab\there \"quoted\" back\\slash\n";
+        let file = parse(&escape(value), "").unwrap();
+        let Term::Str(str) = &file.expression else {
+            panic!("expected str");
+        };
+        assert_eq!(str.value, value);
+    }
+}
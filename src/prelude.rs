@@ -0,0 +1,495 @@
+// Functions provided by the interpreter, bound in a scope enclosing every
+// program so that programs can still define their own with the same names.
+
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+
+use crate::ast::*;
+use crate::env::Env;
+use crate::error::{Position, RuntimeError, Type};
+use crate::eval::{self, Evaluator};
+use crate::integer::Integer;
+
+/// The file name of the builtins' locations.
+pub const PRELUDE_NAME: &str = "<prelude>";
+
+/// The parameters and result of a builtin. Parameters of type
+/// `Type::Unknown` accept any value.
+pub struct Signature {
+    pub name: &'static str,
+    pub parameters: &'static [(&'static str, Type)],
+    pub result: Type,
+}
+
+pub const BUILTINS: &[Signature] = &[
+    Signature {
+        name: "len",
+        parameters: &[("s", Type::Str)],
+        result: Type::Int,
+    },
+    Signature {
+        name: "substring",
+        parameters: &[
+            ("s", Type::Str),
+            ("start", Type::Int),
+            ("end", Type::Int),
+        ],
+        result: Type::Str,
+    },
+    Signature {
+        name: "char_at",
+        parameters: &[("s", Type::Str), ("index", Type::Int)],
+        result: Type::Str,
+    },
+    Signature {
+        name: "parse_int",
+        parameters: &[("s", Type::Str)],
+        result: Type::Int,
+    },
+    Signature {
+        name: "to_str",
+        parameters: &[("value", Type::Unknown)],
+        result: Type::Str,
+    },
+    Signature {
+        name: "abs",
+        parameters: &[("n", Type::Int)],
+        result: Type::Int,
+    },
+    Signature {
+        name: "min",
+        parameters: &[("a", Type::Int), ("b", Type::Int)],
+        result: Type::Int,
+    },
+    Signature {
+        name: "max",
+        parameters: &[("a", Type::Int), ("b", Type::Int)],
+        result: Type::Int,
+    },
+    Signature {
+        name: "pow",
+        parameters: &[("base", Type::Int), ("exponent", Type::Int)],
+        result: Type::Int,
+    },
+    Signature {
+        name: "swap",
+        parameters: &[("pair", Type::Tuple)],
+        result: Type::Tuple,
+    },
+    Signature {
+        name: "map_pair",
+        parameters: &[("f", Type::Function), ("pair", Type::Tuple)],
+        result: Type::Tuple,
+    },
+];
+
+pub fn signature(name: &str) -> Option<&'static Signature> {
+    BUILTINS.iter().find(|signature| signature.name == name)
+}
+
+/// Where every builtin is defined.
+pub fn location() -> Location {
+    Location {
+        start: 0,
+        end: 0,
+        filename: PRELUDE_NAME.into(),
+    }
+}
+
+/// A new root scope for a program, enclosed by the scope of the builtins.
+pub fn env() -> Rc<RefCell<Env>> {
+    let mut prelude = Env::new();
+    for signature in BUILTINS {
+        let builtin = Term::Builtin(Builtin {
+            name: signature.name.into(),
+            location: location(),
+        });
+        prelude.set(signature.name, builtin);
+    }
+    let prelude = Rc::new(RefCell::new(prelude));
+    Rc::new(RefCell::new(Env::extend(prelude)))
+}
+
+/// Whether `env` is the scope of the builtins created by [`env`].
+pub fn is_prelude(env: &Env) -> bool {
+    let bindings = env.bindings();
+    env.parent().is_none()
+        && !bindings.is_empty()
+        && bindings
+            .iter()
+            .all(|(_, term)| matches!(term, Term::Builtin(_)))
+}
+
+/// Calls `builtin` with evaluated `arguments`, whose count was already
+/// checked against its signature.
+pub fn call(
+    evaluator: &mut Evaluator,
+    env: &mut Rc<RefCell<Env>>,
+    builtin: &Builtin,
+    mut arguments: Vec<Term>,
+    location: Location,
+) -> Term {
+    let signature = signature(&builtin.name).expect("unknown builtin");
+    let mismatched = arguments.iter().zip(signature.parameters).position(
+        |(argument, (_, expected))| {
+            *expected != Type::Unknown && Type::of(argument) != *expected
+        },
+    );
+    if let Some(index) = mismatched {
+        let (_, expected) = signature.parameters[index];
+        let argument = arguments.swap_remove(index);
+        return mismatch(signature, index, argument, vec![expected]);
+    }
+
+    let invalid = |reason: String| {
+        let kind = RuntimeError::InvalidArgument {
+            function: signature.name.into(),
+            reason,
+        };
+        Term::Error(Error::runtime(kind, location.clone()))
+    };
+    let str = |value: String| {
+        let location = location.clone();
+        Term::Str(Str { value, location })
+    };
+    let int = |value: Integer| {
+        let location = location.clone();
+        Term::Int(Int { value, location })
+    };
+
+    let mut arguments = arguments.into_iter();
+    let mut next = || arguments.next().unwrap();
+    match signature.name {
+        "len" => {
+            let s = string(next());
+            int(Integer::from(s.chars().count() as i64))
+        }
+        "substring" => {
+            let (s, start, end) =
+                (string(next()), integer(next()), integer(next()));
+            let len = s.chars().count();
+            match (index(&start, len + 1), index(&end, len + 1)) {
+                (Some(start), Some(end)) if start <= end => {
+                    str(s.chars().skip(start).take(end - start).collect())
+                }
+                (Some(_), Some(_)) => {
+                    invalid(format!("start {start} is after end {end}"))
+                }
+                _ => invalid(format!(
+                    "range {start}..{end} is out of bounds for a string of \
+                     length {len}"
+                )),
+            }
+        }
+        "char_at" => {
+            let (s, i) = (string(next()), integer(next()));
+            let len = s.chars().count();
+            match index(&i, len).and_then(|i| s.chars().nth(i)) {
+                Some(c) => str(c.to_string()),
+                None => invalid(format!(
+                    "index {i} is out of bounds for a string of length {len}"
+                )),
+            }
+        }
+        "parse_int" => {
+            let s = string(next());
+            match s.parse::<Integer>() {
+                // Fails like a literal that doesn't fit the precision
+                Ok(value) => evaluator.eval_int(Int { value, location }),
+                Err(_) => {
+                    let s = crate::parser::escape(&s);
+                    invalid(format!("{s} is not an integer"))
+                }
+            }
+        }
+        "to_str" => match &mut next() {
+            Term::Str(s) => str(mem::take(&mut s.value)),
+            Term::Int(n) => str(n.value.to_string()),
+            Term::Bool(b) => str(b.value.to_string()),
+            term => {
+                let expected = vec![Type::Int, Type::Str, Type::Bool];
+                mismatch(signature, 0, term.take(), expected)
+            }
+        },
+        "abs" => {
+            let n = integer(next());
+            if n >= Integer::default() {
+                return int(n);
+            }
+            let negated = evaluator.arithmetic(
+                BinaryOp::Sub,
+                Integer::default(),
+                n.clone(),
+            );
+            match negated.map_err(|kind| overflow(signature, vec![n], kind)) {
+                Ok(value) => int(value),
+                Err(kind) => Term::Error(Error::runtime(kind, location)),
+            }
+        }
+        "min" | "max" => {
+            let (a, b) = (integer(next()), integer(next()));
+            match (signature.name == "min") == (a <= b) {
+                true => int(a),
+                false => int(b),
+            }
+        }
+        "pow" => {
+            let (base, exponent) = (integer(next()), integer(next()));
+            match exponent.to_i64().map(u64::try_from) {
+                Some(Ok(n)) => match pow(evaluator, base.clone(), n) {
+                    Ok(value) => int(value),
+                    Err(kind) => {
+                        let kind =
+                            overflow(signature, vec![base, exponent], kind);
+                        Term::Error(Error::runtime(kind, location))
+                    }
+                },
+                Some(Err(_)) => {
+                    invalid(format!("exponent {exponent} is negative"))
+                }
+                None => invalid(format!("exponent {exponent} is too large")),
+            }
+        }
+        "swap" => {
+            let mut pair = next();
+            let Term::Tuple(tuple) = &mut pair else {
+                unreachable!("checked against the signature");
+            };
+            mem::swap(&mut tuple.first, &mut tuple.second);
+            tuple.location = location;
+            pair
+        }
+        "map_pair" => {
+            let (f, mut pair) = (next(), next());
+            let Term::Tuple(pair) = &mut pair else {
+                unreachable!("checked against the signature");
+            };
+            let mut apply = |value: Term| {
+                let call = Term::Call(Call {
+                    callee: Box::new(f.clone()),
+                    arguments: vec![value],
+                    location: location.clone(),
+                });
+                evaluator.eval(env, call)
+            };
+            let first = match apply(pair.first.take()) {
+                term @ Term::Error(_) => return term,
+                term => term,
+            };
+            let second = match apply(pair.second.take()) {
+                term @ Term::Error(_) => return term,
+                term => term,
+            };
+            Term::Tuple(Tuple {
+                first: Box::new(first),
+                second: Box::new(second),
+                location,
+            })
+        }
+        name => unreachable!("builtin {name} has no implementation"),
+    }
+}
+
+fn string(mut term: Term) -> String {
+    match &mut term {
+        Term::Str(s) => mem::take(&mut s.value),
+        _ => unreachable!("checked against the signature"),
+    }
+}
+
+fn integer(mut term: Term) -> Integer {
+    match &mut term {
+        Term::Int(n) => mem::take(&mut n.value),
+        _ => unreachable!("checked against the signature"),
+    }
+}
+
+/// `value` as an index less than `len`.
+fn index(value: &Integer, len: usize) -> Option<usize> {
+    let index = usize::try_from(value.to_i64()?).ok()?;
+    (index < len).then_some(index)
+}
+
+/// `base` raised to `exponent` by repeated squaring, overflowing like
+/// multiplication does.
+fn pow(
+    evaluator: &Evaluator,
+    mut base: Integer,
+    mut exponent: u64,
+) -> Result<Integer, RuntimeError> {
+    let mut result = Integer::from(1);
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result =
+                evaluator.arithmetic(BinaryOp::Mul, result, base.clone())?;
+        }
+        exponent >>= 1;
+        // Squaring once more than needed could overflow needlessly
+        if exponent > 0 {
+            base = evaluator.arithmetic(BinaryOp::Mul, base.clone(), base)?;
+        }
+    }
+    Ok(result)
+}
+
+/// Reports an overflow while computing a builtin as an overflow of the
+/// builtin itself, rather than of the operations it is computed with.
+fn overflow(
+    signature: &Signature,
+    arguments: Vec<Integer>,
+    kind: RuntimeError,
+) -> RuntimeError {
+    match kind {
+        RuntimeError::Overflow { precision, .. } => {
+            RuntimeError::BuiltinOverflow {
+                function: signature.name.into(),
+                arguments,
+                precision,
+            }
+        }
+        kind => kind,
+    }
+}
+
+fn mismatch(
+    signature: &Signature,
+    index: usize,
+    term: Term,
+    expected: Vec<Type>,
+) -> Term {
+    let found = Type::of(&term);
+    let kind = RuntimeError::TypeMismatch {
+        position: Position::Argument {
+            function: signature.name,
+            index,
+        },
+        expected,
+        found,
+    };
+    eval::error(term, kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integer::Overflow;
+
+    fn eval(source: &str) -> Term {
+        let file = crate::parser::parse(source, "p.rinha").unwrap();
+        let mut evaluator = Evaluator::new();
+        evaluator.overflow(Overflow::Error);
+        evaluator.eval(&mut env(), file.expression)
+    }
+
+    #[test]
+    fn prelude_builtins() {
+        let cases = [
+            ("len(\"héllo\")", "5"),
+            ("substring(\"héllo\", 1, 3)", "\"él\""),
+            ("substring(\"abc\", 3, 3)", "\"\""),
+            ("char_at(\"héllo\", 4)", "\"o\""),
+            ("parse_int(\"-42\") + 1", "-41"),
+            ("to_str(12) + to_str(true) + to_str(\"!\")", "\"12true!\""),
+            ("(abs(-5), abs(5))", "(5, 5)"),
+            ("(min(3, 2), max(3, 2))", "(2, 3)"),
+            ("(pow(2, 10), pow(-3, 3))", "(1024, -27)"),
+            ("(pow(0, 0), pow(-2, 31))", "(1, -2147483648)"),
+            ("swap((1, \"a\"))", "(\"a\", 1)"),
+            ("map_pair(fn (x) => { x * 10 }, (1, 2))", "(10, 20)"),
+            ("let len = fn (s) => { 0 }; len(\"abc\")", "0"),
+            ("let f = max; f(1, 2) == max(2, 1)", "true"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(eval::inspect(&eval(source)), expected, "{source}");
+        }
+    }
+
+    #[test]
+    fn prelude_errors() {
+        let cases = [
+            (
+                "len(1)",
+                "R0003",
+                "The len function expects argument 1 of type \"Str\"",
+            ),
+            (
+                "to_str((1, 2))",
+                "R0003",
+                "The to_str function expects argument 1 of type \"Int\", \
+                 \"Str\", or \"Bool\"",
+            ),
+            ("min(1)", "R0004", "Expected 2 arguments, found 1"),
+            (
+                "char_at(\"abc\", 3)",
+                "R0012",
+                "Invalid argument to char_at: index 3 is out of bounds for a \
+                 string of length 3",
+            ),
+            (
+                "substring(\"abc\", -1, 2)",
+                "R0012",
+                "Invalid argument to substring: range -1..2 is out of bounds \
+                 for a string of length 3",
+            ),
+            (
+                "parse_int(\"1x\")",
+                "R0012",
+                "Invalid argument to parse_int: \"1x\" is not an integer",
+            ),
+            (
+                "parse_int(\" 12 \")",
+                "R0012",
+                "Invalid argument to parse_int: \" 12 \" is not an integer",
+            ),
+            (
+                "parse_int(\"3000000000\")",
+                "R0008",
+                "Integer literal 3000000000 does not fit in i32",
+            ),
+            (
+                "pow(2, -1)",
+                "R0012",
+                "Invalid argument to pow: exponent -1 is negative",
+            ),
+            ("pow(2, 31)", "R0009", "Computing pow(2, 31) overflows i32"),
+            (
+                "abs(-2147483647 - 1)",
+                "R0009",
+                "Computing abs(-2147483648) overflows i32",
+            ),
+            (
+                "map_pair(fn (x) => { x / 0 }, (1, 2))",
+                "R0005",
+                "Attempted to divide by zero",
+            ),
+        ];
+        for (source, code, full_text) in cases {
+            let Term::Error(error) = &eval(source) else {
+                panic!("{source} did not fail");
+            };
+            assert_eq!(error.kind.as_ref().map(|kind| kind.code()), Some(code));
+            assert_eq!(error.full_text, full_text);
+        }
+    }
+
+    #[test]
+    fn prelude_overflow() {
+        // Overflows are reported at the call, naming the builtin
+        let Term::Error(error) = &eval("let n = -2147483647 - 1;\nabs(n)")
+        else {
+            panic!("abs(i32::MIN) did not fail");
+        };
+        assert_eq!(error.message, "Integer overflow");
+        assert_eq!((error.location.start, error.location.end), (25, 31));
+    }
+
+    #[test]
+    fn prelude_scope() {
+        let env = env();
+        assert!(!is_prelude(&env.borrow()));
+        let prelude = env.borrow().parent().unwrap();
+        assert!(is_prelude(&prelude.borrow()));
+        assert_eq!(prelude.borrow().bindings().len(), BUILTINS.len());
+    }
+}
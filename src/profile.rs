@@ -0,0 +1,298 @@
+// Call profiler, collecting per-function statistics and folded stacks for
+// flame graph tools.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use crate::ast::Term;
+use crate::eval::{Frame, Observer};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Options {
+    /// Where to write the folded stacks.
+    pub output: String,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            output: "profile.folded".into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Stats {
+    pub calls: u64,
+    /// Time spent in the function and its callees, counting recursive
+    /// calls only once.
+    pub inclusive: Duration,
+    /// Time spent in the function itself.
+    pub exclusive: Duration,
+    /// Maximum number of simultaneous activations of the function.
+    pub max_depth: usize,
+}
+
+struct Entry {
+    node: usize,
+    start: Instant,
+    children: Duration,
+}
+
+/// A call stack, as a node in the trie of the stacks seen so far.
+#[derive(Default)]
+struct Node {
+    name: String,
+    parent: usize,
+    children: HashMap<String, usize>,
+    /// Exclusive time of the calls that returned with this stack, if any.
+    time: Option<Duration>,
+}
+
+pub struct Profiler {
+    stack: Vec<Entry>,
+    active: HashMap<String, usize>,
+    functions: HashMap<String, Stats>,
+    /// The stacks seen so far, with the empty stack at the root.
+    nodes: Vec<Node>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self {
+            stack: Vec::new(),
+            active: HashMap::new(),
+            functions: HashMap::new(),
+            nodes: vec![Node::default()],
+        }
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The node of the stack `parent` followed by a call to `name`.
+    fn node(&mut self, parent: usize, name: &str) -> usize {
+        if let Some(&node) = self.nodes[parent].children.get(name) {
+            return node;
+        }
+        let node = self.nodes.len();
+        self.nodes.push(Node {
+            name: name.into(),
+            parent,
+            ..Default::default()
+        });
+        self.nodes[parent].children.insert(name.into(), node);
+        node
+    }
+
+    /// Statistics per function, slowest first.
+    pub fn functions(&self) -> Vec<(&str, &Stats)> {
+        let mut functions = self
+            .functions
+            .iter()
+            .map(|(name, stats)| (name.as_str(), stats))
+            .collect::<Vec<_>>();
+        functions.sort_by(|(lhs_name, lhs), (rhs_name, rhs)| {
+            rhs.exclusive
+                .cmp(&lhs.exclusive)
+                .then_with(|| lhs_name.cmp(rhs_name))
+        });
+        functions
+    }
+
+    pub fn summary(&self) -> String {
+        let functions = self.functions();
+        let width = functions
+            .iter()
+            .map(|(name, _)| name.len())
+            .chain(["function".len()])
+            .max()
+            .unwrap_or_default();
+
+        let mut summary = String::new();
+        writeln!(
+            summary,
+            "{:width$}  {:>10}  {:>14}  {:>14}  {:>9}",
+            "function",
+            "calls",
+            "inclusive (ms)",
+            "exclusive (ms)",
+            "max depth",
+        )
+        .unwrap();
+        for (name, stats) in functions {
+            writeln!(
+                summary,
+                "{:width$}  {:>10}  {:>14.3}  {:>14.3}  {:>9}",
+                name,
+                stats.calls,
+                stats.inclusive.as_secs_f64() * 1000.0,
+                stats.exclusive.as_secs_f64() * 1000.0,
+                stats.max_depth,
+            )
+            .unwrap();
+        }
+        summary
+    }
+
+    /// Exclusive time in microseconds per call stack, one stack per line
+    /// with frames separated by `;`.
+    pub fn folded(&self) -> String {
+        // Parents are created before their children, so each stack extends
+        // one already named
+        let mut names = vec![String::new()];
+        let mut stacks = BTreeMap::new();
+        for node in &self.nodes[1..] {
+            let name = match node.parent {
+                0 => node.name.clone(),
+                parent => format!("{};{}", names[parent], node.name),
+            };
+            if let Some(time) = node.time {
+                stacks.insert(name.clone(), time);
+            }
+            names.push(name);
+        }
+
+        let mut folded = String::new();
+        for (stack, time) in stacks {
+            writeln!(folded, "{} {}", stack, time.as_micros()).unwrap();
+        }
+        folded
+    }
+}
+
+impl Observer for Profiler {
+    fn enter(&mut self, frame: &Frame, _arguments: &[Term]) {
+        let active = self.active.entry(frame.name.clone()).or_default();
+        *active += 1;
+
+        let stats = self.functions.entry(frame.name.clone()).or_default();
+        stats.calls += 1;
+        stats.max_depth = stats.max_depth.max(*active);
+
+        let parent = self.stack.last().map_or(0, |entry| entry.node);
+        let node = self.node(parent, &frame.name);
+        self.stack.push(Entry {
+            node,
+            start: Instant::now(),
+            children: Duration::ZERO,
+        });
+    }
+
+    fn exit(&mut self, _frame: &Frame, _result: &Term) {
+        let Some(entry) = self.stack.pop() else {
+            return;
+        };
+
+        let elapsed = entry.start.elapsed();
+        let exclusive = elapsed.saturating_sub(entry.children);
+
+        if let Some(parent) = self.stack.last_mut() {
+            parent.children += elapsed;
+        }
+
+        let node = &mut self.nodes[entry.node];
+        *node.time.get_or_insert(Duration::ZERO) += exclusive;
+
+        let active = self.active.get_mut(&node.name).unwrap();
+        *active -= 1;
+
+        let stats = self.functions.get_mut(&node.name).unwrap();
+        stats.exclusive += exclusive;
+        if *active == 0 {
+            stats.inclusive += elapsed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::eval::Evaluator;
+    use crate::parser;
+
+    const SOURCE: &str = "\
+let fib = fn (n) => { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };
+let main = fn () => { fib(5) };
+main()";
+
+    fn profile() -> Profiler {
+        let file = parser::parse(SOURCE, "fib.rinha").unwrap();
+        let profiler = Rc::new(RefCell::new(Profiler::new()));
+        let mut evaluator = Evaluator::new();
+        evaluator.observe(profiler.clone());
+        evaluator.eval(&mut Default::default(), file.expression);
+        drop(evaluator);
+        Rc::try_unwrap(profiler).ok().unwrap().into_inner()
+    }
+
+    #[test]
+    fn profile_functions() {
+        let profiler = profile();
+        let mut functions = profiler.functions();
+        functions.sort_by_key(|(name, _)| *name);
+        let counts = functions
+            .iter()
+            .map(|(name, stats)| (*name, stats.calls, stats.max_depth))
+            .collect::<Vec<_>>();
+        assert_eq!(counts, [("fib", 15, 5), ("main", 1, 1)]);
+
+        let (_, fib) = functions[0];
+        let (_, main) = functions[1];
+        assert!(main.inclusive >= fib.inclusive);
+        assert!(fib.inclusive >= fib.exclusive);
+    }
+
+    #[test]
+    fn profile_folded() {
+        let profiler = profile();
+        let stacks = profiler
+            .folded()
+            .lines()
+            .map(|line| line.rsplit_once(' ').unwrap().0.to_string())
+            .collect::<Vec<_>>();
+        let expected = [
+            "main",
+            "main;fib",
+            "main;fib;fib",
+            "main;fib;fib;fib",
+            "main;fib;fib;fib;fib",
+            "main;fib;fib;fib;fib;fib",
+        ];
+        assert_eq!(stacks, expected);
+    }
+
+    #[test]
+    fn profile_deep_recursion() {
+        let source = "\
+let count = fn (n) => { if (n == 0) { 0 } else { 1 + count(n - 1) } };
+count(2000)";
+        let file = parser::parse(source, "count.rinha").unwrap();
+        let profiler = Rc::new(RefCell::new(Profiler::new()));
+        let mut evaluator = Evaluator::new();
+        evaluator.observe(profiler.clone());
+        evaluator.eval(&mut Default::default(), file.expression);
+
+        let folded = profiler.borrow().folded();
+        assert_eq!(folded.lines().count(), 2001);
+        let deepest = folded.lines().last().unwrap();
+        assert_eq!(deepest.matches("count").count(), 2001);
+    }
+
+    #[test]
+    fn profile_summary() {
+        let summary = profile().summary();
+        let mut lines = summary.lines();
+        let header = lines.next().unwrap();
+        assert!(header.starts_with("function  "));
+        assert!(header.ends_with("max depth"));
+        assert_eq!(lines.count(), 2);
+    }
+}
@@ -0,0 +1,319 @@
+// Reporting of failures as JSON diagnostics, and the exit code of each kind
+// of failure.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use serde_json::{json, Value};
+
+use crate::ast::{self, Location};
+use crate::check::ParseErrors;
+use crate::source::{self, LineIndex};
+use crate::{binary, eval, validate, EvalError};
+
+/// The program failed while running, or a command reported a failure.
+pub const EXIT_RUNTIME: i32 = 1;
+/// The command line could not be parsed.
+pub const EXIT_USAGE: i32 = 2;
+/// A file could not be read or written.
+pub const EXIT_IO: i32 = 3;
+/// The JSON AST could not be parsed.
+pub const EXIT_JSON: i32 = 4;
+/// The source has syntax errors.
+pub const EXIT_STATIC: i32 = 5;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Format {
+    #[default]
+    Human,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("invalid error format {value}")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Kind {
+    Runtime,
+    Io,
+    Json,
+    Static,
+    Other,
+}
+
+impl Kind {
+    pub fn of(error: &(dyn Error + 'static)) -> Kind {
+        if error.is::<EvalError>() {
+            Kind::Runtime
+        } else if error.is::<ast::Error>() || error.is::<ParseErrors>() {
+            Kind::Static
+        } else if error.is::<validate::Invalid>()
+            || error.is::<binary::Invalid>()
+        {
+            Kind::Json
+        } else if error.is::<io::Error>() {
+            Kind::Io
+        } else if let Some(error) = error.downcast_ref::<serde_json::Error>() {
+            match error.is_io() {
+                true => Kind::Io,
+                false => Kind::Json,
+            }
+        } else {
+            Kind::Other
+        }
+    }
+
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Kind::Runtime | Kind::Other => EXIT_RUNTIME,
+            Kind::Io => EXIT_IO,
+            Kind::Json => EXIT_JSON,
+            Kind::Static => EXIT_STATIC,
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kind::Runtime => write!(f, "runtime"),
+            Kind::Io => write!(f, "io"),
+            Kind::Json => write!(f, "json"),
+            Kind::Static => write!(f, "static"),
+            Kind::Other => write!(f, "error"),
+        }
+    }
+}
+
+/// Resolves locations to lines and columns, reading each source file once.
+pub struct Locator {
+    program: String,
+    sources: HashMap<String, Option<LineIndex>>,
+}
+
+impl Locator {
+    /// Sources are looked up as given or relative to `program`.
+    pub fn new(program: &str) -> Self {
+        Self {
+            program: program.into(),
+            sources: HashMap::new(),
+        }
+    }
+
+    pub fn source(&mut self, filename: &str, text: &str) {
+        let index = Some(LineIndex::new(text));
+        self.sources.insert(filename.into(), index);
+    }
+
+    /// The file, byte span and, when the source is readable, the 1-based
+    /// line and column of both ends of `location`.
+    fn locate(&mut self, location: &Location) -> Value {
+        let Location {
+            start,
+            end,
+            filename,
+        } = location;
+        let program = &self.program;
+        let index = self
+            .sources
+            .entry(filename.clone())
+            .or_insert_with(|| {
+                let (_, text) = source::read(program, filename).ok()?;
+                Some(LineIndex::new(&text))
+            })
+            .as_ref();
+        let position = |offset| match index {
+            Some(index) => {
+                let (line, column) = index.position(offset);
+                json!({ "line": line, "column": column })
+            }
+            None => Value::Null,
+        };
+        json!({
+            "file": filename,
+            "span": { "start": start, "end": end },
+            "start": position(*start),
+            "end": position(*end),
+        })
+    }
+
+    fn diagnostic(&mut self, kind: Kind, error: &ast::Error) -> Value {
+        let mut diagnostic = self.locate(&error.location);
+        diagnostic["kind"] = json!(kind.to_string());
+        diagnostic["code"] = json!(error.kind.as_ref().map(|kind| kind.code()));
+        diagnostic["message"] = json!(error.message);
+        diagnostic["full_text"] = json!(error.full_text);
+        diagnostic
+    }
+
+    /// Describes `error` as JSON objects, one for each error left in the tree
+    /// by the parser, or a single one for any other failure.
+    pub fn diagnostics(&mut self, error: &(dyn Error + 'static)) -> Vec<Value> {
+        let Some(ParseErrors(errors)) = error.downcast_ref() else {
+            return vec![self.json(error)];
+        };
+        errors.iter().map(|error| self.json(error)).collect()
+    }
+
+    /// Describes `error` as a JSON object, with the call stack of runtime
+    /// errors innermost first.
+    pub fn json(&mut self, error: &(dyn Error + 'static)) -> Value {
+        let kind = Kind::of(error);
+        if let Some(EvalError(error, backtrace)) = error.downcast_ref() {
+            let mut diagnostic = self.diagnostic(kind, error);
+            let frames = match backtrace {
+                Some(backtrace) => backtrace.frames.as_slice(),
+                None => &[],
+            };
+            let stack = frames
+                .iter()
+                .map(|(frame, arguments)| {
+                    let mut call = self.locate(&frame.location);
+                    call["name"] = json!(frame.name);
+                    call["arguments"] = json!(arguments
+                        .iter()
+                        .map(eval::inspect)
+                        .collect::<Vec<_>>());
+                    call
+                })
+                .collect::<Vec<_>>();
+            diagnostic["stack"] = json!(stack);
+            return diagnostic;
+        }
+        if let Some(error) = error.downcast_ref::<ast::Error>() {
+            let mut diagnostic = self.diagnostic(kind, error);
+            diagnostic["stack"] = json!([]);
+            return diagnostic;
+        }
+        let invalid = error.downcast_ref::<validate::Invalid>();
+        let (message, start) = match error.downcast_ref::<serde_json::Error>() {
+            Some(error) if kind == Kind::Json => {
+                let start = json!({
+                    "line": error.line(),
+                    "column": error.column(),
+                });
+                ("Invalid JSON AST", start)
+            }
+            _ if error.is::<binary::Invalid>() => {
+                ("Invalid binary AST", Value::Null)
+            }
+            _ if kind == Kind::Json => ("Invalid JSON AST", Value::Null),
+            _ if kind == Kind::Io => ("I/O error", Value::Null),
+            _ => ("Error", Value::Null),
+        };
+        let file = match kind {
+            Kind::Json => json!(self.program),
+            _ => Value::Null,
+        };
+        let mut diagnostic = json!({
+            "kind": kind.to_string(),
+            "code": null,
+            "message": message,
+            "full_text": error.to_string(),
+            "file": file,
+            "span": null,
+            "start": start,
+            "end": null,
+            "stack": [],
+        });
+        if let Some(invalid) = invalid {
+            diagnostic["path"] = json!(invalid.path);
+        }
+        diagnostic
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::Evaluator;
+
+    #[test]
+    fn report_runtime_error() {
+        let source = "\
+let f = fn (n) => {
+  if (n < 1) { 1 / n } else { f(n - 1) }
+};
+f(1)";
+        let file = crate::parser::parse(source, "f.rinha").unwrap();
+        let mut evaluator = Evaluator::new();
+        let result = evaluator.eval(&mut Default::default(), file.expression);
+        let ast::Term::Error(error) = &result else {
+            panic!("expected an error");
+        };
+        let error: Box<dyn Error> =
+            Box::new(EvalError::new(error.clone(), &evaluator));
+        assert_eq!(Kind::of(error.as_ref()).exit_code(), EXIT_RUNTIME);
+
+        let mut locator = Locator::new("f.rinha");
+        locator.source("f.rinha", source);
+        let expected = json!({
+            "kind": "runtime",
+            "code": "R0005",
+            "message": "Division by zero",
+            "full_text": "Attempted to divide by zero",
+            "file": "f.rinha",
+            "span": { "start": 35, "end": 40 },
+            "start": { "line": 2, "column": 16 },
+            "end": { "line": 2, "column": 21 },
+            "stack": [
+                {
+                    "name": "f",
+                    "arguments": ["0"],
+                    "file": "f.rinha",
+                    "span": { "start": 50, "end": 58 },
+                    "start": { "line": 2, "column": 31 },
+                    "end": { "line": 2, "column": 39 },
+                },
+                {
+                    "name": "f",
+                    "arguments": ["1"],
+                    "file": "f.rinha",
+                    "span": { "start": 64, "end": 68 },
+                    "start": { "line": 4, "column": 1 },
+                    "end": { "line": 4, "column": 5 },
+                },
+            ],
+        });
+        assert_eq!(locator.json(error.as_ref()), expected);
+    }
+
+    #[test]
+    fn report_exit_codes() {
+        let error = crate::parser::parse("let", "a.rinha").unwrap_err();
+        let error: Box<dyn Error> = Box::new(error);
+        assert_eq!(Kind::of(error.as_ref()).exit_code(), EXIT_STATIC);
+
+        let error = serde_json::from_str::<ast::File>("{").unwrap_err();
+        let error: Box<dyn Error> = Box::new(error);
+        assert_eq!(Kind::of(error.as_ref()).exit_code(), EXIT_JSON);
+        let diagnostic = Locator::new("a.json").json(error.as_ref());
+        assert_eq!(diagnostic["kind"], "json");
+        assert_eq!(diagnostic["file"], "a.json");
+        assert_eq!(diagnostic["start"]["line"], 1);
+
+        let error = source::load("missing.json").unwrap_err();
+        assert_eq!(Kind::of(error.as_ref()).exit_code(), EXIT_IO);
+
+        let error: Box<dyn Error> =
+            binary::decode(b"RINHAAST").unwrap_err().into();
+        assert_eq!(Kind::of(error.as_ref()).exit_code(), EXIT_JSON);
+        let diagnostic = Locator::new("a.ast").json(error.as_ref());
+        assert_eq!(diagnostic["message"], "Invalid binary AST");
+
+        let error: Box<dyn Error> = "not formatted: a.rinha".into();
+        assert_eq!(Kind::of(error.as_ref()).exit_code(), EXIT_RUNTIME);
+    }
+}
@@ -0,0 +1,73 @@
+// Base protocol shared by the debug adapter and language servers, where
+// each JSON message is preceded by a `Content-Length` header.
+
+use std::io::{self, BufRead, Write};
+
+use serde_json::Value;
+
+/// A message body, or why it isn't JSON.
+pub type Message = Result<Value, serde_json::Error>;
+
+/// Reads the next message, or `None` at the end of the input. A body that
+/// isn't JSON leaves the input at the next message, so the caller may go on.
+pub fn read(input: &mut impl BufRead) -> io::Result<Option<Message>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() && length.is_some() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+
+    let mut body = vec![0; length.unwrap_or_default()];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)))
+}
+
+pub fn write(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rpc_roundtrip() {
+        let mut output = Vec::new();
+        let messages = [serde_json::json!({ "id": 1 }), Value::Null];
+        for message in &messages {
+            write(&mut output, message).unwrap();
+        }
+        assert!(output.starts_with(b"Content-Length: 8\r\n\r\n{\"id\":1}"));
+
+        let mut input = &output[..];
+        let mut read = || read(&mut input).unwrap().map(Result::unwrap);
+        assert_eq!(read(), Some(messages[0].clone()));
+        assert_eq!(read(), Some(messages[1].clone()));
+        assert_eq!(read(), None);
+    }
+
+    #[test]
+    fn rpc_malformed() {
+        let mut input = b"Content-Length: 6\r\n\r\n{\"id\":".to_vec();
+        write(&mut input, &serde_json::json!({ "id": 2 })).unwrap();
+
+        let mut input = &input[..];
+        assert!(read(&mut input).unwrap().unwrap().is_err());
+        let message = read(&mut input).unwrap().unwrap().unwrap();
+        assert_eq!(message, serde_json::json!({ "id": 2 }));
+        assert!(read(&mut input).unwrap().is_none());
+    }
+}
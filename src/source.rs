@@ -0,0 +1,333 @@
+// Loading of programs and their sources, and mapping from the byte offsets
+// used by `Location` to lines and columns.
+
+use std::error::Error;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{ast, binary, eval, parser, validate};
+
+/// The path that stands for standard input, or for standard output where
+/// a file is written.
+pub const STDIO: &str = "-";
+
+/// The file name of programs read from standard input.
+pub const STDIN_NAME: &str = "<stdin>";
+
+/// The file name of programs given on the command line.
+pub const EXPRESSION_NAME: &str = "<expr>";
+
+/// Loads a program from its binary AST, its JSON AST or its `.rinha`
+/// source, reading standard input if `path` is `STDIO`. Binary ASTs are
+/// recognized by their header, whatever the path, and programs read from
+/// stdin are JSON when they start with `{`.
+pub fn load(path: &str) -> Result<ast::File, Box<dyn Error>> {
+    let contents = match path {
+        STDIO => {
+            let mut contents = Vec::new();
+            io::stdin().read_to_end(&mut contents)?;
+            contents
+        }
+        _ => std::fs::read(path)?,
+    };
+    if binary::is_binary(&contents) {
+        return Ok(binary::decode(&contents)?);
+    }
+    let contents = String::from_utf8(contents)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    match path {
+        STDIO if contents.trim_start().starts_with('{') => from_json(&contents),
+        STDIO => Ok(parser::parse(&contents, STDIN_NAME)?),
+        _ if path.ends_with(".rinha") => Ok(parser::parse(&contents, path)?),
+        _ => from_json(&contents),
+    }
+}
+
+/// Parses a JSON AST, however deeply nested it is.
+pub fn from_json(json: &str) -> Result<ast::File, Box<dyn Error>> {
+    let mut deserializer = serde_json::Deserializer::from_str(json);
+    deserializer.disable_recursion_limit();
+    let stacker = serde_stacker::Deserializer::new(&mut deserializer);
+    let value = Value::deserialize(stacker)?;
+    deserializer.end()?;
+    if let Err(invalid) = validate::validate(&value) {
+        dispose(value);
+        return Err(invalid.into());
+    }
+    let Value::Object(mut file) = value else {
+        unreachable!("validated files are objects");
+    };
+    Ok(ast::File {
+        name: field(&mut file, "name")?,
+        expression: term(take(&mut file, "expression"))?,
+        location: field(&mut file, "location")?,
+    })
+}
+
+/// Builds a term from its validated JSON value. Terms are tagged by their
+/// `kind`, which `serde` buffers recursively, so nodes with subterms are
+/// built by hand, growing the stack as needed.
+fn term(value: Value) -> Result<ast::Term, serde_json::Error> {
+    use ast::*;
+
+    let Value::Object(mut node) = value else {
+        return serde_json::from_value(value);
+    };
+    let kind = match node.get("kind").and_then(Value::as_str) {
+        Some(kind) => kind.to_string(),
+        None => return serde_json::from_value(Value::Object(node)),
+    };
+    let node = &mut node;
+
+    stacker::maybe_grow(eval::RED_ZONE, eval::STACK_SIZE, || {
+        let term = match kind.as_str() {
+            "Call" => Term::Call(Call {
+                callee: subterm(node, "callee")?,
+                arguments: match take(node, "arguments") {
+                    Value::Array(items) => {
+                        items.into_iter().map(term).collect::<Result<_, _>>()?
+                    }
+                    value => serde_json::from_value(value)?,
+                },
+                location: field(node, "location")?,
+            }),
+            "Binary" => Term::Binary(Binary {
+                lhs: subterm(node, "lhs")?,
+                op: field(node, "op")?,
+                rhs: subterm(node, "rhs")?,
+                location: field(node, "location")?,
+            }),
+            "Function" => Term::Function(Function {
+                parameters: field(node, "parameters")?,
+                value: subterm(node, "value")?,
+                location: field(node, "location")?,
+            }),
+            "Let" => Term::Let(Let {
+                name: field(node, "name")?,
+                value: subterm(node, "value")?,
+                next: subterm(node, "next")?,
+                location: field(node, "location")?,
+            }),
+            "Import" => Term::Import(Import {
+                path: field(node, "path")?,
+                next: subterm(node, "next")?,
+                location: field(node, "location")?,
+            }),
+            "If" => Term::If(If {
+                condition: subterm(node, "condition")?,
+                then: subterm(node, "then")?,
+                otherwise: subterm(node, "otherwise")?,
+                location: field(node, "location")?,
+            }),
+            "Print" => Term::Print(Print {
+                value: subterm(node, "value")?,
+                location: field(node, "location")?,
+            }),
+            "First" => Term::First(First {
+                value: subterm(node, "value")?,
+                location: field(node, "location")?,
+            }),
+            "Second" => Term::Second(Second {
+                value: subterm(node, "value")?,
+                location: field(node, "location")?,
+            }),
+            "Tuple" => Term::Tuple(Tuple {
+                first: subterm(node, "first")?,
+                second: subterm(node, "second")?,
+                location: field(node, "location")?,
+            }),
+            _ => serde_json::from_value(Value::Object(std::mem::take(node)))?,
+        };
+        Ok(term)
+    })
+}
+
+fn take(node: &mut Map<String, Value>, name: &str) -> Value {
+    node.remove(name).unwrap_or_default()
+}
+
+fn field<T: DeserializeOwned>(
+    node: &mut Map<String, Value>,
+    name: &str,
+) -> Result<T, serde_json::Error> {
+    serde_json::from_value(take(node, name))
+}
+
+fn subterm(
+    node: &mut Map<String, Value>,
+    name: &str,
+) -> Result<Box<ast::Term>, serde_json::Error> {
+    term(take(node, name)).map(Box::new)
+}
+
+/// Drops a JSON value without recursing into nested arrays and objects.
+fn dispose(value: Value) {
+    let mut pending = vec![value];
+    while let Some(value) = pending.pop() {
+        match value {
+            Value::Array(items) => pending.extend(items),
+            Value::Object(fields) => {
+                pending.extend(fields.into_iter().map(|(_, value)| value))
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The file imported as `path` from the file named `importer`, relative to
+/// the directory of the importer. `.` and `..` are removed, so that files
+/// are named the same wherever they are imported from.
+pub fn resolve(importer: &str, path: &str) -> PathBuf {
+    let directory = Path::new(importer).parent().unwrap_or(Path::new(""));
+    let mut resolved = PathBuf::new();
+    for component in directory.join(path).components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    resolved.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                resolved.pop();
+            }
+            component => resolved.push(component),
+        }
+    }
+    resolved
+}
+
+/// Reads a source file named by a `Location`, either as given or relative
+/// to the directory of the program being run, returning where it was found.
+pub fn read(
+    program: &str,
+    filename: &str,
+) -> Result<(PathBuf, String), Box<dyn Error>> {
+    let relative = Path::new(program)
+        .parent()
+        .map(|directory| directory.join(filename));
+    let candidates =
+        std::iter::once(Path::new(filename).to_path_buf()).chain(relative);
+    for path in candidates {
+        if let Ok(source) = std::fs::read_to_string(&path) {
+            return Ok((path, source));
+        }
+    }
+    let message = format!("cannot read source file {filename}");
+    Err(io::Error::new(io::ErrorKind::NotFound, message).into())
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let newlines = text.match_indices('\n').map(|(i, _)| i + 1);
+        Self {
+            starts: std::iter::once(0).chain(newlines).collect(),
+        }
+    }
+
+    /// The 1-based line containing `offset`.
+    pub fn line(&self, offset: usize) -> usize {
+        self.starts.partition_point(|&start| start <= offset)
+    }
+
+    /// The 1-based line and byte column of `offset`.
+    pub fn position(&self, offset: usize) -> (usize, usize) {
+        let line = self.line(offset);
+        (line, offset - self.starts[line - 1] + 1)
+    }
+
+    /// The byte offset where the 1-based `line` starts, if it exists.
+    pub fn offset(&self, line: usize) -> Option<usize> {
+        self.starts.get(line.checked_sub(1)?).copied()
+    }
+
+    pub fn lines(&self) -> usize {
+        self.starts.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::Evaluator;
+
+    #[test]
+    fn line_index() {
+        let index = LineIndex::new("let x = 1;\n\nprint(x)\n");
+        assert_eq!(index.position(0), (1, 1));
+        assert_eq!(index.position(4), (1, 5));
+        assert_eq!(index.position(10), (1, 11));
+        assert_eq!(index.position(11), (2, 1));
+        assert_eq!(index.position(12), (3, 1));
+        assert_eq!(index.position(18), (3, 7));
+        assert_eq!(index.offset(3), Some(12));
+        assert_eq!(index.offset(0), None);
+        assert_eq!(index.lines(), 4);
+    }
+
+    #[test]
+    fn resolve_imports() {
+        assert_eq!(resolve("main.rinha", "a.rinha"), Path::new("a.rinha"));
+        assert_eq!(
+            resolve("src/main.rinha", "./a.rinha"),
+            Path::new("src/a.rinha")
+        );
+        assert_eq!(
+            resolve("src/lib/b.rinha", "../a.rinha"),
+            Path::new("src/a.rinha")
+        );
+        assert_eq!(resolve("b.rinha", "../a.rinha"), Path::new("../a.rinha"));
+        assert_eq!(
+            resolve("<expr>", "/lib/a.rinha"),
+            Path::new("/lib/a.rinha")
+        );
+
+        // Imports are kept in JSON ASTs
+        let file = parser::parse("import \"a.rinha\";\nx", "main.rinha");
+        let file = file.unwrap();
+        let json = serde_json::to_string(&file).unwrap();
+        assert_eq!(from_json(&json).unwrap(), file);
+    }
+
+    #[test]
+    fn load_deep_json() {
+        const DEPTH: usize = 100_000;
+        let location = r#"{"start":0,"end":0,"filename":"d"}"#;
+        let int = |value| {
+            format!(r#"{{"kind":"Int","value":{value},"location":{location}}}"#)
+        };
+        let mut json =
+            format!(r#"{{"name":"d","location":{location},"expression":"#);
+        for _ in 0..DEPTH {
+            json += &format!(
+                r#"{{"kind":"Let","name":{{"text":"_","location":{location}}},"value":{},"location":{location},"next":"#,
+                int("1")
+            );
+        }
+        json += &int("42");
+        json += &"}".repeat(DEPTH + 1);
+
+        drop(from_json(&json).unwrap());
+
+        let file = from_json(&json).unwrap();
+        let result =
+            Evaluator::new().eval(&mut Default::default(), file.expression);
+        let ast::Term::Int(int) = &result else {
+            panic!("expected int");
+        };
+        assert_eq!(int.value, 42.into());
+
+        let json = json.replace(r#""value":42"#, r#""value":"42""#);
+        let error = from_json(&json).unwrap_err();
+        assert!(error.to_string().contains("expected an integer"));
+    }
+}
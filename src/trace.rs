@@ -0,0 +1,160 @@
+// Execution tracing, logging every function call with its arguments and
+// result.
+
+use std::io::Write;
+
+use crate::ast::*;
+use crate::eval::{inspect, Frame, Observer};
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Options {
+    /// Only trace calls to these functions, or every call if empty.
+    pub filter: Vec<String>,
+    /// Only trace calls nested less than this many calls deep.
+    pub depth: Option<usize>,
+    /// Write the trace to this file instead of stderr.
+    pub output: Option<String>,
+}
+
+pub struct Tracer {
+    filter: Vec<String>,
+    max_depth: Option<usize>,
+    depth: usize,
+    output: Box<dyn Write>,
+}
+
+impl Tracer {
+    pub fn new(options: &Options, output: Box<dyn Write>) -> Self {
+        Self {
+            filter: options.filter.clone(),
+            max_depth: options.depth,
+            depth: 0,
+            output,
+        }
+    }
+
+    fn traced(&self, frame: &Frame) -> bool {
+        let depth = match self.max_depth {
+            Some(max) => self.depth < max,
+            None => true,
+        };
+        let name = self.filter.is_empty() || self.filter.contains(&frame.name);
+        depth && name
+    }
+
+    fn indent(&self) -> String {
+        "  ".repeat(self.depth)
+    }
+}
+
+impl Observer for Tracer {
+    fn enter(&mut self, frame: &Frame, arguments: &[Term]) {
+        if self.traced(frame) {
+            let Location {
+                start,
+                end,
+                filename,
+            } = &frame.location;
+            let arguments = arguments.iter().map(inspect).collect::<Vec<_>>();
+            let _ = writeln!(
+                self.output,
+                "{}call {}({}) at {}:{}:{}",
+                self.indent(),
+                frame.name,
+                arguments.join(", "),
+                filename,
+                start,
+                end,
+            );
+        }
+        self.depth += 1;
+    }
+
+    fn exit(&mut self, frame: &Frame, result: &Term) {
+        self.depth -= 1;
+        if self.traced(frame) {
+            let _ = writeln!(
+                self.output,
+                "{}return {} = {}",
+                self.indent(),
+                frame.name,
+                inspect(result),
+            );
+        }
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        let _ = self.output.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::eval::Evaluator;
+    use crate::parser;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    const SOURCE: &str = "\
+let sum = fn (n) => { if (n < 1) { 0 } else { n + sum(n - 1) } };
+let pair = fn (a, b) => { (a, b) };
+pair(sum(2), \"x\")";
+
+    fn trace(options: Options) -> String {
+        let file = parser::parse(SOURCE, "sum.rinha").unwrap();
+        let buffer = Buffer::default();
+        let mut evaluator = Evaluator::new();
+        evaluator.observe(Tracer::new(&options, Box::new(buffer.clone())));
+        evaluator.eval(&mut Default::default(), file.expression);
+        let output = buffer.0.borrow().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn trace_calls() {
+        let expected = "\
+call sum(2) at sum.rinha:107:113
+  call sum(1) at sum.rinha:50:60
+    call sum(0) at sum.rinha:50:60
+    return sum = 0
+  return sum = 1
+return sum = 3
+call pair(3, \"x\") at sum.rinha:102:119
+return pair = (3, \"x\")
+";
+        assert_eq!(trace(Default::default()), expected);
+    }
+
+    #[test]
+    fn trace_filter_depth() {
+        let options = Options {
+            filter: vec!["sum".into()],
+            depth: Some(2),
+            ..Default::default()
+        };
+        let expected = "\
+call sum(2) at sum.rinha:107:113
+  call sum(1) at sum.rinha:50:60
+  return sum = 1
+return sum = 3
+";
+        assert_eq!(trace(options), expected);
+    }
+}
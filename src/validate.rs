@@ -0,0 +1,565 @@
+// Validation of JSON ASTs before deserializing them, reporting the path of
+// the first node that does not match `ast::File` along with hints for the
+// shapes other rinha AST producers commonly emit.
+
+use std::error::Error;
+use std::fmt;
+
+use serde_json::{Map, Value};
+
+use crate::ast::BinaryOp;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Invalid {
+    /// Where the node is, like `expression.next.value.arguments[2]`.
+    pub path: String,
+    pub message: String,
+    pub hint: Option<String>,
+}
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.path.as_str() {
+            "" => write!(f, "invalid AST: {}", self.message)?,
+            path => write!(f, "invalid AST at {path}: {}", self.message)?,
+        }
+        match &self.hint {
+            Some(hint) => write!(f, "\nhint: {hint}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Error for Invalid {}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Shape {
+    File,
+    Term,
+    Terms,
+    Var,
+    Vars,
+    Path,
+    Location,
+    Op,
+    Str,
+    Bool,
+    Int,
+    Offset,
+}
+
+const KINDS: &[(&str, &[(&str, Shape)])] = &[
+    ("Int", &[("value", Shape::Int)]),
+    ("Str", &[("value", Shape::Str)]),
+    ("Bool", &[("value", Shape::Bool)]),
+    ("Var", &[("text", Shape::Str)]),
+    (
+        "Call",
+        &[("callee", Shape::Term), ("arguments", Shape::Terms)],
+    ),
+    (
+        "Binary",
+        &[
+            ("lhs", Shape::Term),
+            ("op", Shape::Op),
+            ("rhs", Shape::Term),
+        ],
+    ),
+    (
+        "Function",
+        &[("parameters", Shape::Vars), ("value", Shape::Term)],
+    ),
+    (
+        "Let",
+        &[
+            ("name", Shape::Var),
+            ("value", Shape::Term),
+            ("next", Shape::Term),
+        ],
+    ),
+    ("Import", &[("path", Shape::Path), ("next", Shape::Term)]),
+    (
+        "If",
+        &[
+            ("condition", Shape::Term),
+            ("then", Shape::Term),
+            ("otherwise", Shape::Term),
+        ],
+    ),
+    ("Print", &[("value", Shape::Term)]),
+    ("First", &[("value", Shape::Term)]),
+    ("Second", &[("value", Shape::Term)]),
+    ("Tuple", &[("first", Shape::Term), ("second", Shape::Term)]),
+    (
+        "Error",
+        &[("message", Shape::Str), ("full_text", Shape::Str)],
+    ),
+];
+
+/// Names other producers use for kinds, mapped to the ones used here.
+const KIND_ALIASES: &[(&str, &str)] = &[
+    ("Integer", "Int"),
+    ("Number", "Int"),
+    ("String", "Str"),
+    ("Boolean", "Bool"),
+    ("Variable", "Var"),
+    ("Identifier", "Var"),
+    ("Application", "Call"),
+    ("BinaryOp", "Binary"),
+    ("BinOp", "Binary"),
+    ("Lambda", "Function"),
+    ("Fn", "Function"),
+    ("Pair", "Tuple"),
+];
+
+/// Field names other producers use, mapped to the ones used here.
+const FIELD_ALIASES: &[(&str, &str, &str)] = &[
+    ("If", "else", "otherwise"),
+    ("If", "cond", "condition"),
+    ("Let", "body", "next"),
+    ("Let", "in", "next"),
+    ("Function", "body", "value"),
+    ("Function", "params", "parameters"),
+    ("Call", "function", "callee"),
+    ("Call", "args", "arguments"),
+    ("Binary", "left", "lhs"),
+    ("Binary", "right", "rhs"),
+    ("Binary", "operator", "op"),
+    ("Var", "name", "text"),
+];
+
+enum Segment<'a> {
+    Root,
+    Field(&'a str),
+    Index(usize),
+}
+
+struct Validator<'a> {
+    /// Path segments with the index of their parent.
+    segments: Vec<(usize, Segment<'a>)>,
+}
+
+impl<'a> Validator<'a> {
+    fn push(&mut self, parent: usize, segment: Segment<'a>) -> usize {
+        self.segments.push((parent, segment));
+        self.segments.len() - 1
+    }
+
+    fn path(&self, mut index: usize) -> String {
+        let mut segments = Vec::new();
+        loop {
+            let (parent, segment) = &self.segments[index];
+            if let Segment::Root = segment {
+                break;
+            }
+            segments.push(segment);
+            index = *parent;
+        }
+        let mut path = String::new();
+        for segment in segments.into_iter().rev() {
+            match segment {
+                Segment::Field(name) if path.is_empty() => path += name,
+                Segment::Field(name) => path += &format!(".{name}"),
+                Segment::Index(i) => path += &format!("[{i}]"),
+                Segment::Root => {}
+            }
+        }
+        path
+    }
+
+    fn invalid(&self, at: usize, message: String) -> Invalid {
+        Invalid {
+            path: self.path(at),
+            message,
+            hint: None,
+        }
+    }
+
+    fn object(
+        &self,
+        value: &'a Value,
+        at: usize,
+        expected: &str,
+    ) -> Result<&'a Map<String, Value>, Invalid> {
+        value.as_object().ok_or_else(|| {
+            let message = format!(
+                "expected {expected} object, found {}",
+                describe(value)
+            );
+            self.invalid(at, message)
+        })
+    }
+
+    /// Checks that `object` has every field, queueing them for validation.
+    fn fields(
+        &mut self,
+        object: &'a Map<String, Value>,
+        at: usize,
+        kind: &str,
+        fields: &[(&'static str, Shape)],
+        pending: &mut Vec<(&'a Value, Shape, usize)>,
+    ) -> Result<(), Invalid> {
+        if let Some((name, _)) =
+            fields.iter().find(|(name, _)| !object.contains_key(*name))
+        {
+            let names = fields
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(", ");
+            let message = format!(
+                "missing field \"{name}\" in {kind} (expected fields: {names})"
+            );
+            let mut invalid = self.invalid(at, message);
+            invalid.hint = missing_hint(object, kind, name);
+            return Err(invalid);
+        }
+        // Queued in reverse so that fields are validated in order
+        for &(name, shape) in fields.iter().rev() {
+            let index = self.push(at, Segment::Field(name));
+            pending.push((&object[name], shape, index));
+        }
+        Ok(())
+    }
+
+    fn validate(&mut self, root: &'a Value) -> Result<(), Invalid> {
+        let index = self.push(0, Segment::Root);
+        let mut pending = vec![(root, Shape::File, index)];
+
+        while let Some((value, shape, at)) = pending.pop() {
+            match shape {
+                Shape::File => {
+                    let object = self.object(value, at, "a file")?;
+                    let fields = [
+                        ("name", Shape::Str),
+                        ("expression", Shape::Term),
+                        ("location", Shape::Location),
+                    ];
+                    self.fields(object, at, "the file", &fields, &mut pending)?;
+                }
+                Shape::Term => {
+                    let object = self.object(value, at, "a term")?;
+                    let kind = match object.get("kind") {
+                        Some(Value::String(kind)) => kind.as_str(),
+                        found => {
+                            let found = match found {
+                                Some(value) => describe(value),
+                                None => "none".into(),
+                            };
+                            let message = format!(
+                                "expected a \"kind\" naming the term ({}), \
+                                 found {found}",
+                                kinds()
+                            );
+                            return Err(self.invalid(at, message));
+                        }
+                    };
+                    let Some((kind, fields)) =
+                        KINDS.iter().find(|(name, _)| *name == kind)
+                    else {
+                        let message = format!(
+                            "unknown kind \"{kind}\", expected one of {}",
+                            kinds()
+                        );
+                        let mut invalid = self.invalid(at, message);
+                        invalid.hint = kind_hint(kind);
+                        return Err(invalid);
+                    };
+                    let mut fields = fields.to_vec();
+                    fields.push(("location", Shape::Location));
+                    self.fields(object, at, kind, &fields, &mut pending)?;
+                }
+                Shape::Var => {
+                    let object = self.object(value, at, "a variable")?;
+                    let fields =
+                        [("text", Shape::Str), ("location", Shape::Location)];
+                    self.fields(object, at, "Var", &fields, &mut pending)?;
+                }
+                Shape::Path => {
+                    let object = self.object(value, at, "a path")?;
+                    let fields =
+                        [("value", Shape::Str), ("location", Shape::Location)];
+                    self.fields(object, at, "Path", &fields, &mut pending)?;
+                }
+                Shape::Terms | Shape::Vars => {
+                    let Some(items) = value.as_array() else {
+                        let message = format!(
+                            "expected an array, found {}",
+                            describe(value)
+                        );
+                        return Err(self.invalid(at, message));
+                    };
+                    let shape = match shape {
+                        Shape::Terms => Shape::Term,
+                        _ => Shape::Var,
+                    };
+                    for (i, item) in items.iter().enumerate().rev() {
+                        let index = self.push(at, Segment::Index(i));
+                        pending.push((item, shape, index));
+                    }
+                }
+                Shape::Location => {
+                    let object = self.object(value, at, "a location")?;
+                    let fields = [
+                        ("start", Shape::Offset),
+                        ("end", Shape::Offset),
+                        ("filename", Shape::Str),
+                    ];
+                    self.fields(
+                        object,
+                        at,
+                        "the location",
+                        &fields,
+                        &mut pending,
+                    )?;
+                }
+                Shape::Op => {
+                    let op = value.as_str().unwrap_or_default();
+                    if !BinaryOp::ALL.iter().any(|known| name(known) == op) {
+                        let names = BinaryOp::ALL
+                            .iter()
+                            .map(name)
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        let found = match value {
+                            Value::String(op) => format!("\"{op}\""),
+                            value => describe(value),
+                        };
+                        let message = format!(
+                            "unknown operator {found}, expected one of {names}"
+                        );
+                        let mut invalid = self.invalid(at, message);
+                        invalid.hint = op_hint(op);
+                        return Err(invalid);
+                    }
+                }
+                Shape::Str if !value.is_string() => {
+                    let message =
+                        format!("expected a string, found {}", describe(value));
+                    return Err(self.invalid(at, message));
+                }
+                Shape::Bool if !value.is_boolean() => {
+                    let message = format!(
+                        "expected a boolean, found {}",
+                        describe(value)
+                    );
+                    return Err(self.invalid(at, message));
+                }
+                Shape::Int if !value.is_i64() && !value.is_u64() => {
+                    let message = format!(
+                        "expected an integer, found {}",
+                        describe(value)
+                    );
+                    let mut invalid = self.invalid(at, message);
+                    invalid.hint = int_hint(value);
+                    return Err(invalid);
+                }
+                Shape::Offset if !value.is_u64() => {
+                    let message = format!(
+                        "expected a byte offset, found {}",
+                        describe(value)
+                    );
+                    return Err(self.invalid(at, message));
+                }
+                Shape::Str | Shape::Bool | Shape::Int | Shape::Offset => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that `value` has the shape of an `ast::File`.
+pub fn validate(value: &Value) -> Result<(), Invalid> {
+    let segments = Vec::new();
+    Validator { segments }.validate(value)
+}
+
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Null => "null".into(),
+        Value::Bool(value) => format!("the boolean {value}"),
+        Value::Number(value) => format!("the number {value}"),
+        Value::String(value) => format!("the string {value:?}"),
+        Value::Array(_) => "an array".into(),
+        Value::Object(_) => "an object".into(),
+    }
+}
+
+fn kinds() -> String {
+    let kinds = KINDS.iter().map(|(kind, _)| *kind).collect::<Vec<_>>();
+    kinds.join(", ")
+}
+
+fn name(op: &BinaryOp) -> String {
+    match serde_json::to_value(op) {
+        Ok(Value::String(name)) => name,
+        _ => unreachable!("operators serialize to their names"),
+    }
+}
+
+fn kind_hint(kind: &str) -> Option<String> {
+    let known = KINDS
+        .iter()
+        .map(|(known, _)| *known)
+        .find(|known| known.eq_ignore_ascii_case(kind))
+        .or_else(|| {
+            KIND_ALIASES
+                .iter()
+                .find(|(alias, _)| alias.eq_ignore_ascii_case(kind))
+                .map(|(_, known)| *known)
+        })?;
+    Some(format!("did you mean \"{known}\"?"))
+}
+
+fn op_hint(op: &str) -> Option<String> {
+    let known = BinaryOp::ALL.iter().find(|known| {
+        known.symbol() == op || name(known).eq_ignore_ascii_case(op)
+    })?;
+    let name = name(known);
+    Some(format!(
+        "operators are written by name, use \"{name}\" for {}",
+        known.symbol()
+    ))
+}
+
+fn int_hint(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) if text.parse::<i64>().is_ok() => {
+            Some("integers must be JSON numbers, not strings".into())
+        }
+        // Integers beyond 64 bits are parsed as floats
+        Value::Number(number)
+            if number.as_f64().is_some_and(|value| value.fract() == 0.0) =>
+        {
+            Some(format!(
+                "JSON integers range from {} to {}, larger literals can \
+                 only be written in rinha source",
+                i64::MIN,
+                u64::MAX
+            ))
+        }
+        _ => None,
+    }
+}
+
+fn missing_hint(
+    object: &Map<String, Value>,
+    kind: &str,
+    field: &str,
+) -> Option<String> {
+    if let Some((_, found, _)) =
+        FIELD_ALIASES.iter().find(|(k, found, expected)| {
+            *k == kind && *expected == field && object.contains_key(*found)
+        })
+    {
+        return Some(format!("rename \"{found}\" to \"{field}\""));
+    }
+    match field {
+        "location" => Some(
+            "every node needs a location with start, end and filename".into(),
+        ),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn location() -> Value {
+        json!({ "start": 0, "end": 1, "filename": "a.rinha" })
+    }
+
+    fn file(expression: Value) -> Value {
+        json!({
+            "name": "a.rinha",
+            "expression": expression,
+            "location": location(),
+        })
+    }
+
+    fn int(value: Value) -> Value {
+        json!({ "kind": "Int", "value": value, "location": location() })
+    }
+
+    #[test]
+    fn validate_paths() {
+        let call = json!({
+            "kind": "Call",
+            "callee": { "kind": "Var", "text": "f", "location": location() },
+            "arguments": [int(json!(1)), int(json!(2)), int(json!("3"))],
+            "location": location(),
+        });
+        let term = json!({
+            "kind": "Let",
+            "name": { "text": "x", "location": location() },
+            "value": int(json!(1)),
+            "next": { "kind": "Print", "value": call, "location": location() },
+            "location": location(),
+        });
+        let invalid = validate(&file(term)).unwrap_err();
+        assert_eq!(invalid.path, "expression.next.value.arguments[2].value");
+        assert_eq!(
+            invalid.message,
+            "expected an integer, found the string \"3\""
+        );
+        assert_eq!(
+            invalid.hint.as_deref(),
+            Some("integers must be JSON numbers, not strings")
+        );
+        assert!(validate(&file(int(json!(3)))).is_ok());
+    }
+
+    #[test]
+    fn validate_hints() {
+        let term = json!({
+            "kind": "If",
+            "condition": {
+                "kind": "Bool",
+                "value": true,
+                "location": location(),
+            },
+            "then": int(json!(1)),
+            "else": int(json!(2)),
+            "location": location(),
+        });
+        let invalid = validate(&file(term)).unwrap_err();
+        assert_eq!(invalid.path, "expression");
+        assert_eq!(
+            invalid.to_string(),
+            "invalid AST at expression: missing field \"otherwise\" in If \
+             (expected fields: condition, then, otherwise, location)\n\
+             hint: rename \"else\" to \"otherwise\""
+        );
+
+        let term = json!({
+            "kind": "Binary",
+            "lhs": int(json!(1)),
+            "op": "+",
+            "rhs": int(json!(2)),
+            "location": location(),
+        });
+        let invalid = validate(&file(term)).unwrap_err();
+        assert_eq!(invalid.path, "expression.op");
+        assert_eq!(
+            invalid.hint.as_deref(),
+            Some("operators are written by name, use \"Add\" for +")
+        );
+
+        let term = json!({ "kind": "integer", "value": 1 });
+        let invalid = validate(&file(term)).unwrap_err();
+        assert_eq!(invalid.hint.as_deref(), Some("did you mean \"Int\"?"));
+
+        let term = json!({ "kind": "Int", "value": 1 });
+        let invalid = validate(&file(term)).unwrap_err();
+        assert!(invalid.message.starts_with("missing field \"location\""));
+
+        let invalid = validate(&json!([])).unwrap_err();
+        assert_eq!(
+            invalid.to_string(),
+            "invalid AST: expected a file object, found an array"
+        );
+    }
+}
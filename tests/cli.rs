@@ -0,0 +1,148 @@
+// Runs the interpreter on programs given through stdin and on the command
+// line, which the golden tests in tests/programs can't express.
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+const FIB: &str = include_str!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/programs/fib.rinha.json"
+));
+
+fn run(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rinha-interpreter"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn run_from_stdin() {
+    let output = run(&["-"], FIB.as_bytes());
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "fib: 55\n");
+
+    let output = run(&["-"], b"let x = 20; print(x + 1)");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "21\n");
+
+    let binary = run(&["compile-ast", "-"], FIB.as_bytes());
+    assert!(binary.status.success());
+    let output = run(&["-"], &binary.stdout);
+    assert_eq!(stdout(&output), "fib: 55\n");
+}
+
+#[test]
+fn run_expression() {
+    let output = run(&["-e", "print(first((1 + 2, \"a\")))"], b"");
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "3\n");
+
+    let output = run(&["-e", "print(1 +"], b"");
+    assert_eq!(output.status.code(), Some(5));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("[Error (<expr>:9:9)]"), "{stderr}");
+}
+
+const LOCATION: &str = r#"{"start":0,"end":0,"filename":"d"}"#;
+
+/// A JSON term of `let _ = 1;` nested `depth` times around `last`.
+fn chain(depth: usize, last: &str) -> String {
+    let mut json = String::new();
+    for _ in 0..depth {
+        json += &format!(
+            r#"{{"kind":"Let","name":{{"text":"_","location":{LOCATION}}},"value":{{"kind":"Int","value":1,"location":{LOCATION}}},"location":{LOCATION},"next":"#
+        );
+    }
+    json += &last.replace("LOCATION", LOCATION);
+    json += &"}".repeat(depth);
+    json
+}
+
+/// A JSON AST of the program `expression`.
+fn program(expression: &str) -> String {
+    format!(r#"{{"name":"d","location":{LOCATION},"expression":{expression}}}"#)
+}
+
+/// A JSON AST of `let _ = 1;` nested `depth` times around `last`.
+fn deep(depth: usize, last: &str) -> String {
+    program(&chain(depth, last))
+}
+
+const DEPTH: usize = 200_000;
+
+const PRINT: &str = r#"{"kind":"Print","value":{"kind":"Int","value":42,"location":LOCATION},"location":LOCATION}"#;
+
+#[test]
+fn run_deep_ast() {
+    let json = deep(DEPTH, PRINT);
+    let output = run(&["-"], json.as_bytes());
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(stdout(&output), "42\n");
+}
+
+#[test]
+fn report_deep_ast() {
+    let division = r#"{"kind":"Binary","op":"Div","lhs":{"kind":"Int","value":1,"location":LOCATION},"rhs":{"kind":"Int","value":0,"location":LOCATION},"location":LOCATION}"#;
+    let json = deep(DEPTH, division);
+    let output = run(&["--error-format", "json", "-"], json.as_bytes());
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(r#""code":"R0005""#), "{stderr}");
+
+    let error = r#"{"kind":"Error","message":"Unexpected token","full_text":"Unexpected token","location":LOCATION}"#;
+    let json = deep(DEPTH, error);
+    let output = run(&["--error-format", "json", "-"], json.as_bytes());
+    assert_eq!(output.status.code(), Some(5), "{output:?}");
+}
+
+#[test]
+fn compile_and_run_deep_ast() {
+    let json = deep(DEPTH, PRINT);
+    let output = run(&["compile-ast", "-"], json.as_bytes());
+    assert!(output.status.success(), "{output:?}");
+
+    let output = run(&["-"], &output.stdout);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(stdout(&output), "42\n");
+}
+
+#[test]
+fn dot_deep_ast() {
+    let json = deep(DEPTH, PRINT);
+    let output = run(&["dot", "--depth", "1", "-"], json.as_bytes());
+    assert!(output.status.success(), "{output:?}");
+    assert!(stdout(&output).starts_with("digraph ast {"));
+}
+
+#[test]
+fn skip_deep_branch() {
+    let json = program(&format!(
+        r#"{{"kind":"If","condition":{{"kind":"Bool","value":false,"location":{LOCATION}}},"then":{},"otherwise":{},"location":{LOCATION}}}"#,
+        chain(DEPTH, PRINT),
+        PRINT.replace("LOCATION", LOCATION),
+    ));
+    let output = run(&["-"], json.as_bytes());
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(stdout(&output), "42\n");
+}
+
+#[test]
+fn call_deep_function() {
+    let json = program(&format!(
+        r#"{{"kind":"Let","name":{{"text":"f","location":{LOCATION}}},"value":{{"kind":"Function","parameters":[],"value":{},"location":{LOCATION}}},"next":{{"kind":"Call","callee":{{"kind":"Var","text":"f","location":{LOCATION}}},"arguments":[],"location":{LOCATION}}},"location":{LOCATION}}}"#,
+        chain(DEPTH, PRINT),
+    ));
+    let output = run(&["-"], json.as_bytes());
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(stdout(&output), "42\n");
+}
@@ -0,0 +1,111 @@
+// Runs every program in tests/programs through the interpreter and compares
+// its stdout, stderr and exit status with the expectation files next to it.
+//
+// A program `name.rinha.json` or `name.rinha` is checked against
+// `name.stdout`, `name.stderr` and `name.status`, and is run with the
+// options listed in `name.args`, if present. Run with `BLESS=1` to write the
+// current outputs as the new expectations.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const PROGRAMS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/programs");
+
+const EXTENSIONS: [&str; 2] = [".rinha.json", ".rinha"];
+
+struct Program {
+    name: String,
+    file: String,
+}
+
+fn programs(dir: &Path) -> Vec<Program> {
+    let mut programs = Vec::new();
+    let mut names = HashSet::new();
+    for entry in fs::read_dir(dir).unwrap() {
+        let file = entry.unwrap().file_name().into_string().unwrap();
+        let Some(name) = EXTENSIONS
+            .iter()
+            .find_map(|extension| file.strip_suffix(extension))
+        else {
+            continue;
+        };
+        assert!(names.insert(name.to_string()), "two programs named {name}");
+        let name = name.to_string();
+        programs.push(Program { name, file });
+    }
+    programs.sort_by(|a, b| a.name.cmp(&b.name));
+    programs
+}
+
+/// The outputs of a run, as stored in the expectation files.
+fn run(dir: &Path, program: &Program) -> [(&'static str, String); 3] {
+    let args = fs::read_to_string(dir.join(format!("{}.args", program.name)))
+        .unwrap_or_default();
+    // Programs run from their directory, so the paths in the output don't
+    // depend on where the repository is
+    let output = Command::new(env!("CARGO_BIN_EXE_rinha-interpreter"))
+        .args(args.split_whitespace())
+        .arg(&program.file)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    let status = match output.status.code() {
+        Some(code) => format!("{code}\n"),
+        None => "killed\n".into(),
+    };
+    let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+    [
+        ("stdout", text(&output.stdout)),
+        ("stderr", text(&output.stderr)),
+        ("status", status),
+    ]
+}
+
+fn expectation(dir: &Path, program: &Program, stream: &str) -> PathBuf {
+    dir.join(format!("{}.{stream}", program.name))
+}
+
+#[test]
+fn golden() {
+    let dir = Path::new(PROGRAMS);
+    let bless = std::env::var_os("BLESS").is_some();
+    let programs = programs(dir);
+    assert!(!programs.is_empty(), "no programs in {PROGRAMS}");
+
+    let mut failures = Vec::new();
+    for program in &programs {
+        for (stream, actual) in run(dir, program) {
+            let path = expectation(dir, program, stream);
+            if bless {
+                fs::write(&path, &actual).unwrap();
+                continue;
+            }
+            match fs::read_to_string(&path) {
+                Ok(expected) if expected == actual => {}
+                Ok(expected) => failures.push(format!(
+                    "{} {stream} differs\n--- expected\n{expected}--- actual\n\
+                     {actual}",
+                    program.file
+                )),
+                Err(_) => failures.push(format!(
+                    "{} has no {stream} expectation, run with BLESS=1 to \
+                     create it",
+                    program.file
+                )),
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        panic!(
+            "{} of {} expectations failed, run with BLESS=1 to accept the \
+             new outputs\n\n{}",
+            failures.len(),
+            programs.len() * 3,
+            failures.join("\n")
+        );
+    }
+}